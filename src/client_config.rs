@@ -0,0 +1,566 @@
+/// Configs handed out to clients, so that they can connect to the server.
+///
+/// Clients that brought their own public key get a template instead, with a placeholder where
+/// their private key goes. Besides wg-quick, configs can be exported for systemd-networkd and
+/// NetworkManager, for clients that manage their interfaces with those.
+use std::{
+    fmt::{self, Write},
+    net::{IpAddr, SocketAddrV4},
+    str::FromStr,
+};
+
+use ipnet::{IpNet, Ipv4Net};
+use serde::Serialize;
+
+use crate::tuning::Table;
+
+/// Stands in for the private key of clients whose key the server never sees
+pub const PRIVATE_KEY_PLACEHOLDER: &str = "<paste yours>";
+
+/// Where systemd-networkd's configs, and the key files they point at, are installed
+const NETWORKD_DIR: &str = "/etc/systemd/network";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ExportFormat {
+    /// A config for `wg-quick up`
+    #[serde(rename = "wg-quick")]
+    WgQuick,
+    /// A `.netdev` and `.network` pair, with the keys in files of their own
+    #[serde(rename = "networkd")]
+    Networkd,
+    /// A NetworkManager keyfile, holding the keys itself
+    #[serde(rename = "nmconnection")]
+    NmConnection,
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExportFormat::WgQuick => write!(f, "wg-quick"),
+            ExportFormat::Networkd => write!(f, "networkd"),
+            ExportFormat::NmConnection => write!(f, "nmconnection"),
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wg-quick" => Ok(ExportFormat::WgQuick),
+            "networkd" => Ok(ExportFormat::Networkd),
+            "nmconnection" => Ok(ExportFormat::NmConnection),
+            other => Err(format!(
+                "unknown format '{}', expected wg-quick, networkd or nmconnection",
+                other
+            )),
+        }
+    }
+}
+
+/// One of the files an exported config is made of, named after the interface it sets up
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigFile {
+    pub name: String,
+    pub contents: String,
+}
+
+/// Everything needed to render a client's config
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientConfig {
+    /// `None` if only the client knows its private key
+    pub private_key: Option<String>,
+    pub address: Ipv4Net,
+    pub dns: Vec<IpAddr>,
+    /// Domains that short names are looked up in, i.e. the VPN's domain
+    pub search_domains: Vec<String>,
+    pub server_public_key: String,
+    pub preshared_key: Option<String>,
+    pub endpoint: SocketAddrV4,
+    pub allowed_ips: Vec<IpNet>,
+    pub persistent_keepalive: Option<u16>,
+    pub mtu: Option<u16>,
+    pub fwmark: Option<u32>,
+    pub table: Option<Table>,
+    pub post_up: Vec<String>,
+    pub post_down: Vec<String>,
+}
+
+impl ClientConfig {
+    /// Renders the config in the format read by `wg-quick`
+    pub fn to_wg_quick(&self) -> String {
+        let mut out = String::new();
+
+        // Writing to a `String` cannot fail
+        let _ = writeln!(out, "[Interface]");
+        let _ = writeln!(
+            out,
+            "PrivateKey = {}",
+            self.private_key
+                .as_deref()
+                .unwrap_or(PRIVATE_KEY_PLACEHOLDER)
+        );
+        let _ = writeln!(out, "Address = {}", self.address);
+        // wg-quick takes anything in `DNS` that isn't an address as a search domain
+        let mut dns: Vec<String> = self.dns.iter().map(IpAddr::to_string).collect();
+        dns.extend(self.search_domains.iter().cloned());
+        if !dns.is_empty() {
+            let _ = writeln!(out, "DNS = {}", dns.join(", "));
+        }
+        if let Some(mtu) = self.mtu {
+            let _ = writeln!(out, "MTU = {}", mtu);
+        }
+        if let Some(fwmark) = self.fwmark {
+            let _ = writeln!(out, "FwMark = 0x{:x}", fwmark);
+        }
+        if let Some(table) = self.table {
+            let _ = writeln!(out, "Table = {}", table);
+        }
+        for hook in &self.post_up {
+            let _ = writeln!(out, "PostUp = {}", hook);
+        }
+        for hook in &self.post_down {
+            let _ = writeln!(out, "PostDown = {}", hook);
+        }
+        let _ = writeln!(out);
+        let _ = writeln!(out, "[Peer]");
+        let _ = writeln!(out, "PublicKey = {}", self.server_public_key);
+        if let Some(preshared_key) = &self.preshared_key {
+            let _ = writeln!(out, "PresharedKey = {}", preshared_key);
+        }
+        let _ = writeln!(out, "Endpoint = {}", self.endpoint);
+        let _ = writeln!(out, "AllowedIPs = {}", join(&self.allowed_ips));
+        if let Some(persistent_keepalive) = self.persistent_keepalive {
+            let _ = writeln!(out, "PersistentKeepalive = {}", persistent_keepalive);
+        }
+
+        out
+    }
+
+    /// Renders the config in `format`, for an interface called `interface` on the client
+    pub fn export(&self, format: ExportFormat, interface: &str) -> Vec<ConfigFile> {
+        match format {
+            ExportFormat::WgQuick => vec![ConfigFile {
+                name: format!("{}.conf", interface),
+                contents: self.to_wg_quick(),
+            }],
+            ExportFormat::Networkd => self.to_networkd(interface),
+            ExportFormat::NmConnection => vec![ConfigFile {
+                name: format!("{}.nmconnection", interface),
+                contents: self.to_nmconnection(interface),
+            }],
+        }
+    }
+
+    /// Renders a `.netdev` and `.network` pair for systemd-networkd.
+    ///
+    /// networkd reads keys from files rather than from the `.netdev`, which may be world-readable,
+    /// so the private and preshared keys come as `<interface>.key` and `<interface>.psk`. The
+    /// private key file is left out when only the client knows its key.
+    pub fn to_networkd(&self, interface: &str) -> Vec<ConfigFile> {
+        let key_file = |extension: &str| format!("{}.{}", interface, extension);
+        let mut files = Vec::new();
+
+        let mut netdev = String::new();
+        let _ = writeln!(netdev, "[NetDev]");
+        let _ = writeln!(netdev, "Name = {}", interface);
+        let _ = writeln!(netdev, "Kind = wireguard");
+        if let Some(mtu) = self.mtu {
+            let _ = writeln!(netdev, "MTUBytes = {}", mtu);
+        }
+        let _ = writeln!(netdev);
+        let _ = writeln!(netdev, "[WireGuard]");
+        let _ = writeln!(
+            netdev,
+            "PrivateKeyFile = {}/{}",
+            NETWORKD_DIR,
+            key_file("key")
+        );
+        if let Some(fwmark) = self.fwmark {
+            let _ = writeln!(netdev, "FirewallMark = 0x{:x}", fwmark);
+        }
+        // Unlike wg-quick, networkd only adds routes for `AllowedIPs` when given a table
+        match self.table {
+            None | Some(Table::Auto) | Some(Table::Id(254)) => {
+                let _ = writeln!(netdev, "RouteTable = main");
+            }
+            Some(Table::Id(id)) => {
+                let _ = writeln!(netdev, "RouteTable = {}", id);
+            }
+            Some(Table::Off) => {}
+        }
+        let _ = writeln!(netdev);
+        let _ = writeln!(netdev, "[WireGuardPeer]");
+        let _ = writeln!(netdev, "PublicKey = {}", self.server_public_key);
+        if self.preshared_key.is_some() {
+            let _ = writeln!(
+                netdev,
+                "PresharedKeyFile = {}/{}",
+                NETWORKD_DIR,
+                key_file("psk")
+            );
+        }
+        let _ = writeln!(netdev, "Endpoint = {}", self.endpoint);
+        let _ = writeln!(netdev, "AllowedIPs = {}", join(&self.allowed_ips));
+        if let Some(persistent_keepalive) = self.persistent_keepalive {
+            let _ = writeln!(netdev, "PersistentKeepalive = {}", persistent_keepalive);
+        }
+        files.push(ConfigFile {
+            name: key_file("netdev"),
+            contents: netdev,
+        });
+
+        let mut network = String::new();
+        self.write_hook_comments(&mut network);
+        let _ = writeln!(network, "[Match]");
+        let _ = writeln!(network, "Name = {}", interface);
+        let _ = writeln!(network);
+        let _ = writeln!(network, "[Network]");
+        let _ = writeln!(network, "Address = {}", self.address);
+        for dns in &self.dns {
+            let _ = writeln!(network, "DNS = {}", dns);
+        }
+        if !self.search_domains.is_empty() {
+            let _ = writeln!(network, "Domains = {}", self.search_domains.join(" "));
+        }
+        files.push(ConfigFile {
+            name: key_file("network"),
+            contents: network,
+        });
+
+        if let Some(private_key) = &self.private_key {
+            files.push(ConfigFile {
+                name: key_file("key"),
+                contents: format!("{}\n", private_key),
+            });
+        }
+        if let Some(preshared_key) = &self.preshared_key {
+            files.push(ConfigFile {
+                name: key_file("psk"),
+                contents: format!("{}\n", preshared_key),
+            });
+        }
+
+        files
+    }
+
+    /// Renders a NetworkManager keyfile, for `/etc/NetworkManager/system-connections`.
+    ///
+    /// Keyfiles hold their secrets themselves. When only the client knows its private key, the
+    /// key is marked as agent-owned instead, so that NetworkManager asks for it.
+    pub fn to_nmconnection(&self, interface: &str) -> String {
+        let mut out = String::new();
+
+        self.write_hook_comments(&mut out);
+        let _ = writeln!(out, "[connection]");
+        let _ = writeln!(out, "id={}", interface);
+        let _ = writeln!(out, "type=wireguard");
+        let _ = writeln!(out, "interface-name={}", interface);
+        let _ = writeln!(out);
+        let _ = writeln!(out, "[wireguard]");
+        match &self.private_key {
+            Some(private_key) => {
+                let _ = writeln!(out, "private-key={}", private_key);
+            }
+            None => {
+                let _ = writeln!(out, "private-key-flags=1");
+            }
+        }
+        if let Some(mtu) = self.mtu {
+            let _ = writeln!(out, "mtu={}", mtu);
+        }
+        if let Some(fwmark) = self.fwmark {
+            let _ = writeln!(out, "fwmark={}", fwmark);
+        }
+        if self.table == Some(Table::Off) {
+            let _ = writeln!(out, "peer-routes=false");
+        }
+        let _ = writeln!(out);
+        let _ = writeln!(out, "[wireguard-peer.{}]", self.server_public_key);
+        let _ = writeln!(out, "endpoint={}", self.endpoint);
+        if let Some(preshared_key) = &self.preshared_key {
+            let _ = writeln!(out, "preshared-key={}", preshared_key);
+            let _ = writeln!(out, "preshared-key-flags=0");
+        }
+        if let Some(persistent_keepalive) = self.persistent_keepalive {
+            let _ = writeln!(out, "persistent-keepalive={}", persistent_keepalive);
+        }
+        let _ = writeln!(out, "allowed-ips={}", list(&self.allowed_ips));
+
+        // Each address family gets its own DNS servers and routing table
+        let route_table = match self.table {
+            Some(Table::Id(id)) => Some(id),
+            _ => None,
+        };
+        let (dns_v4, dns_v6): (Vec<&IpAddr>, Vec<&IpAddr>) =
+            self.dns.iter().partition(|dns| dns.is_ipv4());
+        let _ = writeln!(out);
+        let _ = writeln!(out, "[ipv4]");
+        let _ = writeln!(out, "method=manual");
+        let _ = writeln!(out, "address1={}", self.address);
+        if !dns_v4.is_empty() {
+            let _ = writeln!(out, "dns={}", list(&dns_v4));
+        }
+        if !self.search_domains.is_empty() {
+            let _ = writeln!(out, "dns-search={}", list(&self.search_domains));
+        }
+        if let Some(id) = route_table {
+            let _ = writeln!(out, "route-table={}", id);
+        }
+        let _ = writeln!(out);
+        let _ = writeln!(out, "[ipv6]");
+        // The VPN itself is IPv4 only, but IPv6 routes (such as those of a split tunnel) and DNS
+        // servers still need IPv6 on the interface
+        let ipv6 =
+            !dns_v6.is_empty() || self.allowed_ips.iter().any(|ip| matches!(ip, IpNet::V6(_)));
+        match ipv6 {
+            true => {
+                let _ = writeln!(out, "method=link-local");
+                if !dns_v6.is_empty() {
+                    let _ = writeln!(out, "dns={}", list(&dns_v6));
+                }
+                if let Some(id) = route_table {
+                    let _ = writeln!(out, "route-table={}", id);
+                }
+            }
+            false => {
+                let _ = writeln!(out, "method=disabled");
+            }
+        }
+
+        out
+    }
+
+    /// Carries wg-quick's hooks over as comments, since networkd and NetworkManager have no
+    /// equivalent and they would otherwise be lost without a trace
+    fn write_hook_comments(&self, out: &mut String) {
+        if self.post_up.is_empty() && self.post_down.is_empty() {
+            return;
+        }
+        let _ = writeln!(
+            out,
+            "# Hooks to run some other way, e.g. from a dispatcher script:"
+        );
+        for hook in &self.post_up {
+            let _ = writeln!(out, "# PostUp = {}", hook);
+        }
+        for hook in &self.post_down {
+            let _ = writeln!(out, "# PostDown = {}", hook);
+        }
+        let _ = writeln!(out);
+    }
+}
+
+/// A list in the format of NetworkManager keyfiles, e.g. `10.0.0.0/24;10.1.0.0/24;`
+fn list<T: ToString>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(|item| format!("{};", item.to_string()))
+        .collect()
+}
+
+fn join<T: ToString>(ips: &[T]) -> String {
+    ips.iter()
+        .map(|ip| ip.to_string())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_wg_quick() {
+        let config = ClientConfig {
+            private_key: Some("client-private".into()),
+            address: "10.0.0.2/32".parse().unwrap(),
+            dns: vec!["10.0.0.1".parse().unwrap(), "1.1.1.1".parse().unwrap()],
+            search_domains: vec!["vpn.example.com".into()],
+            server_public_key: "server-public".into(),
+            preshared_key: Some("preshared".into()),
+            endpoint: "1.2.3.4:51900".parse().unwrap(),
+            allowed_ips: vec![
+                "10.0.0.0/24".parse().unwrap(),
+                "192.168.0.0/16".parse().unwrap(),
+            ],
+            persistent_keepalive: Some(25),
+            mtu: Some(1380),
+            fwmark: Some(0x51820),
+            table: Some(Table::Off),
+            post_up: vec!["ip route add 10.0.0.0/24 dev %i".into()],
+            post_down: vec![],
+        };
+
+        assert_eq!(
+            config.to_wg_quick(),
+            "[Interface]\n\
+             PrivateKey = client-private\n\
+             Address = 10.0.0.2/32\n\
+             DNS = 10.0.0.1, 1.1.1.1, vpn.example.com\n\
+             MTU = 1380\n\
+             FwMark = 0x51820\n\
+             Table = off\n\
+             PostUp = ip route add 10.0.0.0/24 dev %i\n\
+             \n\
+             [Peer]\n\
+             PublicKey = server-public\n\
+             PresharedKey = preshared\n\
+             Endpoint = 1.2.3.4:51900\n\
+             AllowedIPs = 10.0.0.0/24, 192.168.0.0/16\n\
+             PersistentKeepalive = 25\n"
+        );
+    }
+
+    #[test]
+    fn test_to_wg_quick_template() {
+        let config = ClientConfig {
+            private_key: None,
+            address: "10.0.0.2/32".parse().unwrap(),
+            dns: vec![],
+            search_domains: vec![],
+            server_public_key: "server-public".into(),
+            preshared_key: None,
+            endpoint: "1.2.3.4:51900".parse().unwrap(),
+            allowed_ips: vec!["10.0.0.0/24".parse().unwrap()],
+            persistent_keepalive: None,
+            mtu: None,
+            fwmark: None,
+            table: None,
+            post_up: vec![],
+            post_down: vec![],
+        };
+
+        assert!(config
+            .to_wg_quick()
+            .starts_with("[Interface]\nPrivateKey = <paste yours>\n"));
+    }
+
+    fn config() -> ClientConfig {
+        ClientConfig {
+            private_key: Some("client-private".into()),
+            address: "10.0.0.2/32".parse().unwrap(),
+            dns: vec!["10.0.0.1".parse().unwrap(), "fd00::1".parse().unwrap()],
+            search_domains: vec!["vpn.example.com".into()],
+            server_public_key: "server-public".into(),
+            preshared_key: Some("preshared".into()),
+            endpoint: "1.2.3.4:51900".parse().unwrap(),
+            allowed_ips: vec![
+                "10.0.0.0/24".parse().unwrap(),
+                "192.168.0.0/16".parse().unwrap(),
+            ],
+            persistent_keepalive: Some(25),
+            mtu: Some(1380),
+            fwmark: Some(0x51820),
+            table: Some(Table::Id(1234)),
+            post_up: vec!["logger up".into()],
+            post_down: vec![],
+        }
+    }
+
+    #[test]
+    fn test_to_networkd() {
+        let files = config().to_networkd("wg0");
+        let names: Vec<&str> = files.iter().map(|file| file.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["wg0.netdev", "wg0.network", "wg0.key", "wg0.psk"]
+        );
+        assert_eq!(
+            files[0].contents,
+            "[NetDev]\n\
+             Name = wg0\n\
+             Kind = wireguard\n\
+             MTUBytes = 1380\n\
+             \n\
+             [WireGuard]\n\
+             PrivateKeyFile = /etc/systemd/network/wg0.key\n\
+             FirewallMark = 0x51820\n\
+             RouteTable = 1234\n\
+             \n\
+             [WireGuardPeer]\n\
+             PublicKey = server-public\n\
+             PresharedKeyFile = /etc/systemd/network/wg0.psk\n\
+             Endpoint = 1.2.3.4:51900\n\
+             AllowedIPs = 10.0.0.0/24, 192.168.0.0/16\n\
+             PersistentKeepalive = 25\n"
+        );
+        assert_eq!(
+            files[1].contents,
+            "# Hooks to run some other way, e.g. from a dispatcher script:\n\
+             # PostUp = logger up\n\
+             \n\
+             [Match]\n\
+             Name = wg0\n\
+             \n\
+             [Network]\n\
+             Address = 10.0.0.2/32\n\
+             DNS = 10.0.0.1\n\
+             DNS = fd00::1\n\
+             Domains = vpn.example.com\n"
+        );
+        assert_eq!(files[2].contents, "client-private\n");
+
+        // Without a stored private key, the client puts its own in the key file
+        let template = ClientConfig {
+            private_key: None,
+            preshared_key: None,
+            table: None,
+            ..config()
+        };
+        let files = template.to_networkd("wg0");
+        assert_eq!(files.len(), 2);
+        assert!(files[0].contents.contains("RouteTable = main\n"));
+        assert!(!files[0].contents.contains("PresharedKeyFile"));
+    }
+
+    #[test]
+    fn test_to_nmconnection() {
+        assert_eq!(
+            config().to_nmconnection("wg0"),
+            "# Hooks to run some other way, e.g. from a dispatcher script:\n\
+             # PostUp = logger up\n\
+             \n\
+             [connection]\n\
+             id=wg0\n\
+             type=wireguard\n\
+             interface-name=wg0\n\
+             \n\
+             [wireguard]\n\
+             private-key=client-private\n\
+             mtu=1380\n\
+             fwmark=333856\n\
+             \n\
+             [wireguard-peer.server-public]\n\
+             endpoint=1.2.3.4:51900\n\
+             preshared-key=preshared\n\
+             preshared-key-flags=0\n\
+             persistent-keepalive=25\n\
+             allowed-ips=10.0.0.0/24;192.168.0.0/16;\n\
+             \n\
+             [ipv4]\n\
+             method=manual\n\
+             address1=10.0.0.2/32\n\
+             dns=10.0.0.1;\n\
+             dns-search=vpn.example.com;\n\
+             route-table=1234\n\
+             \n\
+             [ipv6]\n\
+             method=link-local\n\
+             dns=fd00::1;\n\
+             route-table=1234\n"
+        );
+
+        let template = ClientConfig {
+            private_key: None,
+            dns: vec![],
+            table: Some(Table::Off),
+            ..config()
+        };
+        let keyfile = template.to_nmconnection("wg0");
+        assert!(keyfile.contains("[wireguard]\nprivate-key-flags=1\n"));
+        assert!(keyfile.contains("peer-routes=false\n"));
+        assert!(keyfile.ends_with("[ipv6]\nmethod=disabled\n"));
+    }
+}
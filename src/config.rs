@@ -0,0 +1,254 @@
+/// The config file, which holds one or more WireGuard interfaces, each managed by a `Manager`.
+///
+/// A config with a single interface is stored as that interface's `Manager`, as configs always
+/// have been, and one with several as `{ "interfaces": [...] }`. Interfaces are kept apart: their
+/// address pools and listen ports may not overlap, and each has its own clients, groups, policy
+/// and firewall table.
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::manager::{Manager, ManagerError};
+use crate::nft;
+
+pub struct Config {
+    /// Sorted by interface name
+    interfaces: Vec<Manager>,
+}
+
+/// How a config with several interfaces is stored
+#[derive(Serialize, Deserialize)]
+struct Interfaces {
+    interfaces: Vec<Manager>,
+}
+
+impl Config {
+    pub fn new(manager: Manager) -> Self {
+        Config {
+            interfaces: vec![manager],
+        }
+    }
+
+    /// Produces `Config` struct from the contents of a config file
+    ///
+    /// NOTE: `from_config` and `save_config` do not handle file locking
+    pub fn from_config(path: &Path) -> Result<Self, ManagerError> {
+        let data = std::fs::read(path)?;
+        Self::from_slice(&data)
+    }
+
+    fn from_slice(data: &[u8]) -> Result<Self, ManagerError> {
+        let value: serde_json::Value = serde_json::from_slice(data)?;
+        let mut interfaces = match value.get("interfaces") {
+            Some(_) => serde_json::from_value::<Interfaces>(value)?.interfaces,
+            None => vec![serde_json::from_value::<Manager>(value)?],
+        };
+        interfaces.sort_by(|a, b| a.interface_name().cmp(b.interface_name()));
+
+        Ok(Config { interfaces })
+    }
+
+    /// Save `Config` struct to the contents of a config file
+    ///
+    /// NOTE: `from_config` and `save_config` do not handle file locking
+    pub fn save_config(self, path: &Path) -> Result<(), ManagerError> {
+        std::fs::write(path, self.into_bytes()?)?;
+        Ok(())
+    }
+
+    fn into_bytes(mut self) -> Result<Vec<u8>, ManagerError> {
+        let data = match self.interfaces.len() {
+            1 => serde_json::to_vec(&self.interfaces.remove(0))?,
+            _ => serde_json::to_vec(&Interfaces {
+                interfaces: self.interfaces,
+            })?,
+        };
+        Ok(data)
+    }
+
+    /// Interfaces, sorted by name
+    pub fn interfaces(&self) -> &[Manager] {
+        &self.interfaces
+    }
+
+    pub fn interfaces_mut(&mut self) -> &mut [Manager] {
+        &mut self.interfaces
+    }
+
+    /// The interface called `name`, or the only one if no name is given
+    pub fn interface(&self, name: Option<&str>) -> Result<&Manager, ManagerError> {
+        match name {
+            Some(name) => self
+                .interfaces
+                .iter()
+                .find(|manager| manager.interface_name() == name)
+                .ok_or_else(|| ManagerError::InterfaceNotFoundError(name.to_owned())),
+            None if self.interfaces.len() == 1 => Ok(&self.interfaces[0]),
+            None => Err(ManagerError::InterfaceRequiredError(
+                self.interfaces
+                    .iter()
+                    .map(|manager| manager.interface_name().clone())
+                    .collect(),
+            )),
+        }
+    }
+
+    /// Removes the interface called `name` (or the only one), so that it can be changed and put
+    /// back with `set_interface`
+    pub fn take_interface(&mut self, name: Option<&str>) -> Result<Manager, ManagerError> {
+        let name = self.interface(name)?.interface_name().clone();
+        let index = self
+            .interfaces
+            .iter()
+            .position(|manager| *manager.interface_name() == name)
+            .expect("the interface was just found");
+        Ok(self.interfaces.remove(index))
+    }
+
+    /// Puts an interface back, replacing any with the same name
+    pub fn set_interface(&mut self, manager: Manager) {
+        self.interfaces
+            .retain(|other| other.interface_name() != manager.interface_name());
+        let index = self
+            .interfaces
+            .partition_point(|other| other.interface_name() < manager.interface_name());
+        self.interfaces.insert(index, manager);
+    }
+
+    /// Adds a new interface, whose address pool, listen port and firewall table must not overlap
+    /// any other's
+    pub fn add_interface(&mut self, manager: Manager) -> Result<(), ManagerError> {
+        for other in &self.interfaces {
+            if other.interface_name() == manager.interface_name() {
+                return Err(ManagerError::InterfaceExistsError(
+                    manager.interface_name().clone(),
+                ));
+            }
+            if other.ip_range().contains(&manager.ip_range())
+                || manager.ip_range().contains(&other.ip_range())
+            {
+                return Err(ManagerError::IpRangeOverlapError {
+                    ip_range: manager.ip_range(),
+                    interface: other.interface_name().clone(),
+                });
+            }
+            if other.endpoint().port() == manager.endpoint().port() {
+                return Err(ManagerError::ListenPortTakenError {
+                    port: manager.endpoint().port(),
+                    interface: other.interface_name().clone(),
+                });
+            }
+            if nft::table(other.interface_name()) == nft::table(manager.interface_name()) {
+                return Err(ManagerError::FirewallTableTakenError {
+                    table: nft::table(manager.interface_name()),
+                    interface: other.interface_name().clone(),
+                });
+            }
+        }
+
+        self.set_interface(manager);
+        Ok(())
+    }
+
+    /// Commits every interface, as `Manager::commit` does for one
+    pub fn commit(&mut self, now: u64) -> Result<(), ManagerError> {
+        for manager in &mut self.interfaces {
+            manager.commit(now)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manager(interface_name: &str, endpoint: &str, ip_range: &str) -> Manager {
+        serde_json::from_value(serde_json::json!({
+            "interface_name": interface_name,
+            "private_key": "server-private",
+            "public_key": "server-public",
+            "endpoint": endpoint,
+            "ip_range": ip_range,
+            "clients": {},
+            "wg": {"binary_path": "wg"},
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_add_interface() {
+        let mut config = Config::new(manager("wg0", "1.2.3.4:51820", "10.0.0.0/24"));
+
+        assert!(matches!(
+            config.add_interface(manager("wg0", "1.2.3.4:51821", "10.1.0.0/24")),
+            Err(ManagerError::InterfaceExistsError(_))
+        ));
+        assert!(matches!(
+            config.add_interface(manager("wg1", "1.2.3.4:51821", "10.0.0.128/25")),
+            Err(ManagerError::IpRangeOverlapError { .. })
+        ));
+        assert!(matches!(
+            config.add_interface(manager("wg1", "1.2.3.4:51821", "10.0.0.0/16")),
+            Err(ManagerError::IpRangeOverlapError { .. })
+        ));
+        assert!(matches!(
+            config.add_interface(manager("wg1", "5.6.7.8:51820", "10.1.0.0/24")),
+            Err(ManagerError::ListenPortTakenError { port: 51820, .. })
+        ));
+        config
+            .add_interface(manager("wg1", "1.2.3.4:51821", "10.1.0.0/24"))
+            .unwrap();
+
+        assert!(matches!(
+            config.interface(None),
+            Err(ManagerError::InterfaceRequiredError(_))
+        ));
+        assert_eq!(
+            config.interface(Some("wg1")).unwrap().ip_range(),
+            "10.1.0.0/24".parse().unwrap()
+        );
+        assert!(matches!(
+            config.interface(Some("wg2")),
+            Err(ManagerError::InterfaceNotFoundError(_))
+        ));
+
+        // Both would have the table wg_manager_wg_2
+        config
+            .add_interface(manager("wg-2", "1.2.3.4:51822", "10.2.0.0/24"))
+            .unwrap();
+        assert!(matches!(
+            config.add_interface(manager("wg_2", "1.2.3.4:51823", "10.3.0.0/24")),
+            Err(ManagerError::FirewallTableTakenError { .. })
+        ));
+    }
+
+    #[test]
+    fn test_layout() {
+        // A single interface is stored as it always was
+        let config = Config::new(manager("wg0", "1.2.3.4:51820", "10.0.0.0/24"));
+        let data = config.into_bytes().unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&data).unwrap();
+        assert_eq!(value["interface_name"], "wg0");
+
+        let mut config = Config::from_slice(&data).unwrap();
+        config
+            .add_interface(manager("iot", "1.2.3.4:51821", "10.1.0.0/24"))
+            .unwrap();
+        let data = config.into_bytes().unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&data).unwrap();
+        assert_eq!(value["interfaces"][0]["interface_name"], "iot");
+        assert_eq!(value["interfaces"][1]["interface_name"], "wg0");
+
+        let mut config = Config::from_slice(&data).unwrap();
+        let wg0 = config.take_interface(Some("wg0")).unwrap();
+        assert_eq!(config.interfaces().len(), 1);
+        config.set_interface(wg0);
+        let names: Vec<&String> = config
+            .interfaces()
+            .iter()
+            .map(|manager| manager.interface_name())
+            .collect();
+        assert_eq!(names, ["iot", "wg0"]);
+    }
+}
@@ -0,0 +1,326 @@
+/// Long-running reconciliation of the WireGuard interfaces against the config.
+///
+/// Every interface is reconciled on start, whenever the config file changes, and every `interval`
+/// otherwise, which repairs drift such as peers removed by hand or the interface being recreated.
+/// The config lock is held for the duration of each reconciliation, so the daemon never races
+/// with CLI edits.
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    ffi::OsString,
+    fmt,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, RecvTimeoutError, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+
+use inotify::{Inotify, WatchMask};
+use signal_hook::{
+    consts::{SIGINT, SIGTERM},
+    iterator::Signals,
+};
+
+use crate::config::Config;
+use crate::manager::{Manager, ManagerError};
+use crate::utils::{self, Lock, LockError};
+
+/// How long to wait before retrying when the config is locked by another process
+const LOCKED_RETRY: Duration = Duration::from_secs(1);
+
+enum Event {
+    ConfigChanged,
+    Shutdown,
+}
+
+/// Why a single reconciliation did not happen
+enum ReconcileError {
+    Locked(LockError),
+    /// Loading or saving the config failed
+    Config(ManagerError),
+    /// Reconciling the named interface failed
+    Failed(String, ManagerError),
+}
+
+impl fmt::Display for ReconcileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReconcileError::Locked(e) => write!(f, "config is locked: {}", e),
+            ReconcileError::Config(e) => write!(f, "{}", e),
+            ReconcileError::Failed(interface, e) => write!(f, "{}: {}", interface, e),
+        }
+    }
+}
+
+pub struct Daemon {
+    config: PathBuf,
+    interval: Duration,
+    dry_run: bool,
+    /// The firewall ruleset last loaded for each interface, so that reloading an unchanged one
+    /// isn't logged
+    firewalls: RefCell<HashMap<String, Option<String>>>,
+}
+
+impl Daemon {
+    pub fn new(config: &Path, interval: Duration, dry_run: bool) -> Self {
+        Daemon {
+            config: config.to_owned(),
+            interval,
+            dry_run,
+            firewalls: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Runs until SIGTERM or SIGINT is received.
+    ///
+    /// Only fails if the config can't be watched, or signal handlers can't be installed.
+    pub fn run(&self) -> std::io::Result<()> {
+        let (sender, receiver) = mpsc::channel();
+        watch_signals(sender.clone())?;
+        watch_config(&self.config, sender)?;
+
+        log(&format!(
+            "watching {}, reconciling every {}s",
+            self.config.display(),
+            self.interval.as_secs()
+        ));
+
+        let mut next_reconcile = Instant::now();
+        loop {
+            let timeout = next_reconcile.saturating_duration_since(Instant::now());
+            match receiver.recv_timeout(timeout) {
+                Ok(Event::ConfigChanged) => {
+                    // Give whoever changed the config a moment to release the lock
+                    next_reconcile = Instant::now() + Duration::from_millis(100);
+                    continue;
+                }
+                Ok(Event::Shutdown) | Err(RecvTimeoutError::Disconnected) => break,
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+
+            next_reconcile = match self.reconcile() {
+                Ok(()) => Instant::now() + self.interval,
+                Err(ReconcileError::Locked(e)) => {
+                    log(&format!("config is locked ({}), retrying", e));
+                    Instant::now() + LOCKED_RETRY
+                }
+                Err(e) => {
+                    log(&format!("reconciliation failed: {}", e));
+                    Instant::now() + self.interval
+                }
+            };
+        }
+
+        log("shutting down");
+        Ok(())
+    }
+
+    fn reconcile(&self) -> Result<(), ReconcileError> {
+        // Held until the end of the function, so the config can't change mid-reconciliation
+        let _lock =
+            Lock::acquire(utils::lock_path(&self.config)).map_err(ReconcileError::Locked)?;
+        let mut config = Config::from_config(&self.config).map_err(ReconcileError::Config)?;
+        let now = utils::unix_now();
+
+        let mut changed = false;
+        for manager in config.interfaces_mut() {
+            changed |= self.update(manager, now);
+        }
+        if changed {
+            config = self.save(config)?;
+        }
+
+        // A failing interface (one that doesn't exist yet, say) doesn't hold up the others
+        let mut result = Ok(());
+        for manager in config.interfaces() {
+            if let Err(e) = self.reconcile_interface(manager) {
+                if result.is_ok() {
+                    result = Err(e);
+                } else {
+                    log(&format!("reconciliation failed: {}", e));
+                }
+            }
+        }
+        result
+    }
+
+    /// Makes the changes to an interface's config that are due, returning whether there were any
+    /// to save (never in a dry run)
+    fn update(&self, manager: &mut Manager, now: u64) -> bool {
+        let name = manager.interface_name().clone();
+        let log = |message: &str| log(&format!("{}: {}", name, message));
+        let mut changed = false;
+
+        if manager.switch_due(now) {
+            if self.dry_run {
+                log("would switch to the staged server key");
+            } else {
+                manager.switch_server_key();
+                log(&format!(
+                    "switched to the staged server key {}",
+                    manager.public_key()
+                ));
+                changed = true;
+            }
+        }
+
+        // Expired clients are disabled on this copy of the config, so this run's plan leaves them
+        // out, but a dry run never saves it
+        let expiries = manager.expire_clients(now);
+        for expiry in &expiries {
+            if self.dry_run {
+                log(&format!("would {}", expiry));
+            } else {
+                log(&expiry.to_string());
+            }
+        }
+
+        changed || (!expiries.is_empty() && !self.dry_run)
+    }
+
+    fn reconcile_interface(&self, manager: &Manager) -> Result<(), ReconcileError> {
+        let name = manager.interface_name();
+        let log = |message: &str| log(&format!("{}: {}", name, message));
+        let failed = |e: ManagerError| ReconcileError::Failed(name.clone(), e);
+
+        let changes = manager.plan().map_err(failed)?;
+        for change in &changes {
+            if self.dry_run {
+                log(&format!("would {}", change));
+            } else {
+                log(&change.to_string());
+            }
+        }
+
+        if !self.dry_run {
+            manager.apply(&changes).map_err(failed)?;
+        }
+
+        // The ruleset is loaded every time, which repairs it if it was flushed
+        let firewall = manager.firewall();
+        let changed = firewall != self.firewalls.borrow().get(name).cloned().flatten();
+        if firewall.is_some() || changed {
+            let (action, done) = match firewall {
+                Some(_) => ("load the firewall ruleset", "loaded the firewall ruleset"),
+                None => ("remove the firewall table", "removed the firewall table"),
+            };
+            if self.dry_run {
+                if changed {
+                    log(&format!("would {}", action));
+                }
+            } else {
+                manager.apply_firewall().map_err(failed)?;
+                if changed {
+                    log(done);
+                }
+            }
+        }
+        self.firewalls.borrow_mut().insert(name.clone(), firewall);
+
+        let forwarding_changes = manager.plan_forwarding().map_err(failed)?;
+        for change in &forwarding_changes {
+            if self.dry_run {
+                log(&format!("would {}", change));
+            } else {
+                log(&change.to_string());
+            }
+        }
+        if !self.dry_run {
+            manager
+                .apply_forwarding(&forwarding_changes)
+                .map_err(failed)?;
+        }
+
+        // Records are only rewritten when they change, so that resolvers aren't reloaded for
+        // nothing and zone serials only go up when there is something new
+        for file in manager.dns_files() {
+            if file.is_current().map_err(|e| failed(e.into()))? {
+                continue;
+            }
+            if self.dry_run {
+                log(&format!(
+                    "would write DNS records to {}",
+                    file.path.display()
+                ));
+            } else {
+                file.write().map_err(|e| failed(e.into()))?;
+                log(&format!("wrote DNS records to {}", file.path.display()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Saves the config (the lock must be held), returning the config as it was saved
+    fn save(&self, config: Config) -> Result<Config, ReconcileError> {
+        config
+            .save_config(&self.config)
+            .and_then(|()| Config::from_config(&self.config))
+            .map_err(ReconcileError::Config)
+    }
+}
+
+/// Sends `Event::Shutdown` on SIGTERM or SIGINT
+fn watch_signals(sender: Sender<Event>) -> std::io::Result<()> {
+    let mut signals = Signals::new([SIGTERM, SIGINT])?;
+
+    thread::spawn(move || {
+        if let Some(signal) = signals.forever().next() {
+            log(&format!("received signal {}", signal));
+            let _ = sender.send(Event::Shutdown);
+        }
+    });
+
+    Ok(())
+}
+
+/// Sends `Event::ConfigChanged` whenever the config file is written or replaced.
+///
+/// The parent directory is watched rather than the file itself, since editors tend to replace
+/// the file, which would silently end a watch on it.
+fn watch_config(config: &Path, sender: Sender<Event>) -> std::io::Result<()> {
+    let file_name: OsString = config
+        .file_name()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "not a file"))?
+        .to_owned();
+    let directory = match config.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_owned(),
+        _ => PathBuf::from("."),
+    };
+
+    let mut inotify = Inotify::init()?;
+    inotify.watches().add(
+        &directory,
+        WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::CREATE,
+    )?;
+
+    thread::spawn(move || {
+        let mut buffer = [0; 4096];
+        loop {
+            let events = match inotify.read_events_blocking(&mut buffer) {
+                Ok(events) => events,
+                Err(e) => {
+                    log(&format!("stopped watching config: {}", e));
+                    return;
+                }
+            };
+
+            if events
+                .into_iter()
+                .any(|event| event.name == Some(file_name.as_os_str()))
+            {
+                log("config changed");
+                if sender.send(Event::ConfigChanged).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Logs to stderr, which is where a service manager expects to find it
+fn log(message: &str) {
+    eprintln!("{}", message);
+}
@@ -0,0 +1,415 @@
+/// DNS records for clients, so that they can reach each other as `<client>.<domain>`.
+///
+/// `wg-manager` doesn't answer queries itself. Commits write the records, forward and reverse, in
+/// a format that a resolver running on the server reads: a hosts file fragment, a dnsmasq config
+/// file, an unbound include or an RFC 1035 zone file (with the reverse zone next to it). The
+/// server itself is named after the interface.
+use std::{
+    fmt::{self, Write},
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use ipnet::Ipv4Net;
+use serde::{Deserialize, Serialize};
+
+use crate::utils;
+
+/// How long resolvers may cache the records, in seconds
+const TTL: u32 = 300;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DnsError {
+    InvalidDomain(String),
+    ServerOutOfRange { server: Ipv4Addr, ip_range: Ipv4Net },
+}
+
+impl fmt::Display for DnsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DnsError::InvalidDomain(domain) => write!(f, "'{}' is not a valid domain", domain),
+            DnsError::ServerOutOfRange { server, ip_range } => {
+                write!(f, "{} is not in the VPN's range {}", server, ip_range)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DnsFormat {
+    /// Lines of `<ip> <name>`, for `/etc/hosts` or dnsmasq's `addn-hosts`
+    Hosts,
+    /// `host-record=` lines, which give dnsmasq both the forward and reverse record
+    Dnsmasq,
+    /// `local-data` and `local-data-ptr`, for an unbound `include:`
+    Unbound,
+    /// A zone file for the domain, and one for the reverse zone at `<path>.rev`
+    Zone,
+}
+
+impl fmt::Display for DnsFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DnsFormat::Hosts => write!(f, "hosts"),
+            DnsFormat::Dnsmasq => write!(f, "dnsmasq"),
+            DnsFormat::Unbound => write!(f, "unbound"),
+            DnsFormat::Zone => write!(f, "zone"),
+        }
+    }
+}
+
+impl FromStr for DnsFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hosts" => Ok(DnsFormat::Hosts),
+            "dnsmasq" => Ok(DnsFormat::Dnsmasq),
+            "unbound" => Ok(DnsFormat::Unbound),
+            "zone" => Ok(DnsFormat::Zone),
+            other => Err(format!(
+                "unknown format '{}', expected hosts, dnsmasq, unbound or zone",
+                other
+            )),
+        }
+    }
+}
+
+/// Where and how the records are written, and the resolver clients are pointed at
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Dns {
+    /// Without the trailing dot
+    domain: String,
+    /// Address of the resolver on the server, inside the VPN's range
+    server: Ipv4Addr,
+    format: DnsFormat,
+    path: PathBuf,
+}
+
+impl Dns {
+    pub fn new(
+        domain: &str,
+        server: Ipv4Addr,
+        format: DnsFormat,
+        path: PathBuf,
+    ) -> Result<Self, DnsError> {
+        let domain = domain.strip_suffix('.').unwrap_or(domain).to_lowercase();
+        if domain.is_empty() || domain.len() > 253 || !domain.split('.').all(is_valid_label) {
+            return Err(DnsError::InvalidDomain(domain));
+        }
+
+        Ok(Dns {
+            domain,
+            server,
+            format,
+            path,
+        })
+    }
+
+    pub fn domain(&self) -> &String {
+        &self.domain
+    }
+
+    pub fn server(&self) -> Ipv4Addr {
+        self.server
+    }
+
+    pub fn format(&self) -> DnsFormat {
+        self.format
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    /// Renders the records for `hosts`, which are `(label, ip)` pairs with the server first.
+    ///
+    /// `serial` only ends up in zone files, as the SOA serial.
+    pub fn render(
+        &self,
+        ip_range: Ipv4Net,
+        hosts: &[(String, Ipv4Addr)],
+        serial: u32,
+    ) -> Vec<DnsFile> {
+        let fqdn = |label: &str| format!("{}.{}", label, self.domain);
+        let mut out = String::new();
+
+        // Writing to a `String` cannot fail
+        match self.format {
+            DnsFormat::Hosts => {
+                for (label, ip) in hosts {
+                    let _ = writeln!(out, "{}\t{}", ip, fqdn(label));
+                }
+            }
+            DnsFormat::Dnsmasq => {
+                // Names in the domain that aren't here don't exist, rather than being forwarded
+                let _ = writeln!(out, "local=/{}/", self.domain);
+                for (label, ip) in hosts {
+                    let _ = writeln!(out, "host-record={},{},{}", fqdn(label), ip, TTL);
+                }
+            }
+            DnsFormat::Unbound => {
+                let _ = writeln!(out, "server:");
+                let _ = writeln!(out, "\tlocal-zone: \"{}.\" static", self.domain);
+                for (label, ip) in hosts {
+                    let _ = writeln!(
+                        out,
+                        "\tlocal-data: \"{}. {} IN A {}\"",
+                        fqdn(label),
+                        TTL,
+                        ip
+                    );
+                    let _ = writeln!(out, "\tlocal-data-ptr: \"{} {} {}.\"", ip, TTL, fqdn(label));
+                }
+            }
+            DnsFormat::Zone => {
+                let (server, _) = &hosts[0];
+                let _ = write!(
+                    out,
+                    "{}",
+                    self.zone_header(&self.domain, &fqdn(server), serial)
+                );
+                let _ = writeln!(out, "@\tIN\tNS\t{}.", fqdn(server));
+                for (label, ip) in hosts {
+                    let _ = writeln!(out, "{}\tIN\tA\t{}", label, ip);
+                }
+
+                let (origin, octets) = reverse_zone(ip_range);
+                let mut reverse = self.zone_header(&origin, &fqdn(server), serial);
+                let _ = writeln!(reverse, "@\tIN\tNS\t{}.", fqdn(server));
+                for (label, ip) in hosts {
+                    let name: Vec<String> = ip.octets()[octets..]
+                        .iter()
+                        .rev()
+                        .map(u8::to_string)
+                        .collect();
+                    let _ = writeln!(reverse, "{}\tIN\tPTR\t{}.", name.join("."), fqdn(label));
+                }
+
+                let mut reverse_path = self.path.clone().into_os_string();
+                reverse_path.push(".rev");
+                return vec![
+                    DnsFile {
+                        path: self.path.clone(),
+                        contents: out,
+                    },
+                    DnsFile {
+                        path: reverse_path.into(),
+                        contents: reverse,
+                    },
+                ];
+            }
+        }
+
+        vec![DnsFile {
+            path: self.path.clone(),
+            contents: out,
+        }]
+    }
+
+    /// `$ORIGIN`, `$TTL` and the SOA record of a zone, whose primary name server is `primary`
+    fn zone_header(&self, origin: &str, primary: &str, serial: u32) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "$ORIGIN {}.", origin);
+        let _ = writeln!(out, "$TTL {}", TTL);
+        let _ = writeln!(
+            out,
+            "@\tIN\tSOA\t{}. hostmaster.{}. (",
+            primary, self.domain
+        );
+        let _ = writeln!(out, "\t\t{}\t; serial", serial);
+        let _ = writeln!(out, "\t\t3600\t; refresh");
+        let _ = writeln!(out, "\t\t600\t; retry");
+        let _ = writeln!(out, "\t\t86400\t; expire");
+        let _ = writeln!(out, "\t\t{} )\t; minimum", TTL);
+        out
+    }
+}
+
+/// The reverse zone holding the VPN's addresses, which starts at the octet boundary at or above
+/// its prefix, and how many octets of an address the zone's name takes up
+fn reverse_zone(ip_range: Ipv4Net) -> (String, usize) {
+    let octets = (ip_range.prefix_len() as usize / 8).clamp(1, 3);
+    let mut labels: Vec<String> = ip_range.network().octets()[..octets]
+        .iter()
+        .rev()
+        .map(u8::to_string)
+        .collect();
+    labels.push("in-addr.arpa".into());
+    (labels.join("."), octets)
+}
+
+/// Whether `label` can be used as one label of a host name (RFC 1123)
+pub fn is_valid_label(label: &str) -> bool {
+    !label.is_empty()
+        && label.len() <= 63
+        && !label.starts_with('-')
+        && !label.ends_with('-')
+        && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+/// A file of records, as rendered by `Dns::render`
+#[derive(Debug, Clone, PartialEq)]
+pub struct DnsFile {
+    pub path: PathBuf,
+    pub contents: String,
+}
+
+impl DnsFile {
+    /// Whether the file on disk already holds these records, leaving aside the zone serial
+    pub fn is_current(&self) -> std::io::Result<bool> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(existing) => Ok(without_serial(&existing) == without_serial(&self.contents)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Writes the file atomically, so that the resolver never reads half of it
+    pub fn write(&self) -> std::io::Result<()> {
+        utils::write_atomic(Path::new(&self.path), self.contents.as_bytes())
+    }
+}
+
+fn without_serial(contents: &str) -> Vec<&str> {
+    contents
+        .lines()
+        .filter(|line| !line.ends_with("; serial"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hosts() -> Vec<(String, Ipv4Addr)> {
+        vec![
+            ("wg0".into(), "10.0.0.1".parse().unwrap()),
+            ("alice-laptop".into(), "10.0.0.2".parse().unwrap()),
+        ]
+    }
+
+    fn render(format: DnsFormat, ip_range: &str) -> Vec<DnsFile> {
+        let dns = Dns::new(
+            "VPN.example.com.",
+            "10.0.0.1".parse().unwrap(),
+            format,
+            "/etc/vpn".into(),
+        )
+        .unwrap();
+        dns.render(ip_range.parse().unwrap(), &hosts(), 1700000000)
+    }
+
+    #[test]
+    fn test_new() {
+        let server = "10.0.0.1".parse().unwrap();
+        for domain in [
+            "",
+            ".",
+            "vpn..example.com",
+            "-vpn.example.com",
+            "vpn_example.com",
+        ] {
+            assert_eq!(
+                Dns::new(domain, server, DnsFormat::Hosts, "/etc/vpn".into()),
+                Err(DnsError::InvalidDomain(
+                    domain.strip_suffix('.').unwrap_or(domain).into()
+                ))
+            );
+        }
+        assert_eq!(
+            Dns::new(
+                "VPN.Example.com.",
+                server,
+                DnsFormat::Hosts,
+                "/etc/vpn".into()
+            )
+            .unwrap()
+            .domain(),
+            "vpn.example.com"
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(
+            render(DnsFormat::Hosts, "10.0.0.0/24")[0].contents,
+            "10.0.0.1\twg0.vpn.example.com\n\
+             10.0.0.2\talice-laptop.vpn.example.com\n"
+        );
+        assert_eq!(
+            render(DnsFormat::Dnsmasq, "10.0.0.0/24")[0].contents,
+            "local=/vpn.example.com/\n\
+             host-record=wg0.vpn.example.com,10.0.0.1,300\n\
+             host-record=alice-laptop.vpn.example.com,10.0.0.2,300\n"
+        );
+        assert_eq!(
+            render(DnsFormat::Unbound, "10.0.0.0/24")[0].contents,
+            "server:\n\
+             \tlocal-zone: \"vpn.example.com.\" static\n\
+             \tlocal-data: \"wg0.vpn.example.com. 300 IN A 10.0.0.1\"\n\
+             \tlocal-data-ptr: \"10.0.0.1 300 wg0.vpn.example.com.\"\n\
+             \tlocal-data: \"alice-laptop.vpn.example.com. 300 IN A 10.0.0.2\"\n\
+             \tlocal-data-ptr: \"10.0.0.2 300 alice-laptop.vpn.example.com.\"\n"
+        );
+    }
+
+    #[test]
+    fn test_render_zone() {
+        let files = render(DnsFormat::Zone, "10.0.0.0/24");
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].path, PathBuf::from("/etc/vpn"));
+        assert_eq!(
+            files[0].contents,
+            "$ORIGIN vpn.example.com.\n\
+             $TTL 300\n\
+             @\tIN\tSOA\twg0.vpn.example.com. hostmaster.vpn.example.com. (\n\
+             \t\t1700000000\t; serial\n\
+             \t\t3600\t; refresh\n\
+             \t\t600\t; retry\n\
+             \t\t86400\t; expire\n\
+             \t\t300 )\t; minimum\n\
+             @\tIN\tNS\twg0.vpn.example.com.\n\
+             wg0\tIN\tA\t10.0.0.1\n\
+             alice-laptop\tIN\tA\t10.0.0.2\n"
+        );
+        assert_eq!(files[1].path, PathBuf::from("/etc/vpn.rev"));
+        assert!(files[1]
+            .contents
+            .starts_with("$ORIGIN 0.0.10.in-addr.arpa.\n"));
+        assert!(files[1].contents.ends_with(
+            "1\tIN\tPTR\twg0.vpn.example.com.\n2\tIN\tPTR\talice-laptop.vpn.example.com.\n"
+        ));
+
+        // Ranges that don't end on an octet boundary are in the zone of the larger one
+        let files = render(DnsFormat::Zone, "10.0.0.0/20");
+        assert!(files[1]
+            .contents
+            .starts_with("$ORIGIN 0.10.in-addr.arpa.\n"));
+        assert!(files[1]
+            .contents
+            .contains("\n2.0\tIN\tPTR\talice-laptop.vpn.example.com.\n"));
+    }
+
+    #[test]
+    fn test_is_current() {
+        let dir = std::env::temp_dir().join(format!("wg-manager-dns-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut file = render(DnsFormat::Zone, "10.0.0.0/24").remove(0);
+        file.path = dir.join("db.vpn");
+
+        assert!(!file.is_current().unwrap());
+        file.write().unwrap();
+        assert!(file.is_current().unwrap());
+
+        // A new serial alone doesn't need writing, but a new record does
+        file.contents = file.contents.replace("1700000000", "1700000060");
+        assert!(file.is_current().unwrap());
+        file.contents.push_str("bob\tIN\tA\t10.0.0.3\n");
+        assert!(!file.is_current().unwrap());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
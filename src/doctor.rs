@@ -0,0 +1,92 @@
+/// Checks for problems with the host that stop the VPN working as configured.
+use serde::Serialize;
+
+use crate::forwarding::{NatMode, IP_FORWARD};
+use crate::manager::Manager;
+
+/// The result of a single check
+#[derive(Debug, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub ok: bool,
+    pub message: String,
+}
+
+/// Runs every check against the config and the host
+pub fn run(manager: &Manager) -> Vec<Check> {
+    let full_tunnel: Vec<&String> = manager
+        .full_tunnel_clients()
+        .into_iter()
+        .map(|client| client.name())
+        .collect();
+
+    vec![
+        forwarding(manager, &full_tunnel),
+        nat(manager, &full_tunnel),
+    ]
+}
+
+/// Clients routing everything through the server need it to forward their traffic
+fn forwarding(manager: &Manager, full_tunnel: &[&String]) -> Check {
+    let (ok, message) = match manager.forwarding_enabled() {
+        Ok(true) => (true, format!("{} is on", IP_FORWARD)),
+        Ok(false) if full_tunnel.is_empty() => (
+            true,
+            format!(
+                "{} is off, but no clients route all traffic through the server",
+                IP_FORWARD
+            ),
+        ),
+        Ok(false) => (
+            false,
+            format!(
+                "{} is off, but {} all traffic through the server",
+                IP_FORWARD,
+                describe(full_tunnel)
+            ),
+        ),
+        Err(e) => (false, format!("couldn't read {}: {}", IP_FORWARD, e)),
+    };
+
+    Check {
+        name: "forwarding",
+        ok,
+        message,
+    }
+}
+
+/// Traffic from the VPN's private range needs masquerading to get replies from the internet
+fn nat(manager: &Manager, full_tunnel: &[&String]) -> Check {
+    let (ok, message) = match (manager.nat(), manager.egress_interface()) {
+        (NatMode::Off, _) if full_tunnel.is_empty() => (true, "NAT is off".to_owned()),
+        (NatMode::Off, _) => (
+            false,
+            format!(
+                "NAT is off, but {} all traffic through the server",
+                describe(full_tunnel)
+            ),
+        ),
+        (mode, None) => (
+            false,
+            format!("NAT is {}, but there is no egress interface", mode),
+        ),
+        (mode, Some(egress)) => (
+            true,
+            format!("NAT ({}) masquerades out of {}", mode, egress),
+        ),
+    };
+
+    Check {
+        name: "nat",
+        ok,
+        message,
+    }
+}
+
+fn describe(clients: &[&String]) -> String {
+    let names: Vec<&str> = clients.iter().map(|name| name.as_str()).collect();
+    match names.len() {
+        1 => format!("1 client ({}) routes", names[0]),
+        n => format!("{} clients ({}) route", n, names.join(", ")),
+    }
+}
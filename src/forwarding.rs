@@ -0,0 +1,266 @@
+/// IP forwarding and NAT, for clients that route traffic beyond the VPN through the server.
+///
+/// Forwarding is the `net.ipv4.ip_forward` sysctl, and NAT masquerades the VPN's range out of the
+/// egress interface, either in `wg-manager`'s own nftables table or as an iptables rule.
+use std::{fmt, path::PathBuf, str::FromStr};
+
+use ipnet::Ipv4Net;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::{self, CommandError};
+
+pub const IP_FORWARD: &str = "net.ipv4.ip_forward";
+
+/// How traffic from the VPN is masqueraded out of the egress interface
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NatMode {
+    #[default]
+    Off,
+    Nftables,
+    Iptables,
+}
+
+impl fmt::Display for NatMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NatMode::Off => write!(f, "off"),
+            NatMode::Nftables => write!(f, "nftables"),
+            NatMode::Iptables => write!(f, "iptables"),
+        }
+    }
+}
+
+impl FromStr for NatMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(NatMode::Off),
+            "nftables" => Ok(NatMode::Nftables),
+            "iptables" => Ok(NatMode::Iptables),
+            other => Err(format!(
+                "unknown NAT mode '{}', expected off, nftables or iptables",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ForwardingError {
+    /// Reading or writing a sysctl failed
+    Sysctl { key: String, error: std::io::Error },
+    /// The iptables binary could not be found
+    NotInstalled { command: String },
+    /// iptables exited with a non-zero status
+    CommandFailed {
+        command: String,
+        code: Option<i32>,
+        stderr: String,
+    },
+    /// Spawning iptables failed
+    IOError {
+        command: String,
+        error: std::io::Error,
+    },
+}
+
+impl From<CommandError> for ForwardingError {
+    fn from(error: CommandError) -> Self {
+        match error {
+            CommandError::NotInstalled { command } => ForwardingError::NotInstalled { command },
+            CommandError::Failed {
+                command,
+                code,
+                stderr,
+            } => ForwardingError::CommandFailed {
+                command,
+                code,
+                stderr,
+            },
+            CommandError::IOError { command, error } => ForwardingError::IOError { command, error },
+        }
+    }
+}
+
+impl fmt::Display for ForwardingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ForwardingError::Sysctl { key, error } => write!(f, "sysctl {}: {}", key, error),
+            ForwardingError::NotInstalled { command } => write!(
+                f,
+                "`{}` failed: iptables binary not found, is it installed?",
+                command
+            ),
+            ForwardingError::CommandFailed {
+                command,
+                code: Some(code),
+                stderr,
+            } => write!(f, "`{}` failed (exit code {}): {}", command, code, stderr),
+            ForwardingError::CommandFailed {
+                command, stderr, ..
+            } => write!(f, "`{}` failed (killed by signal): {}", command, stderr),
+            ForwardingError::IOError { command, error } => {
+                write!(f, "`{}` failed: {}", command, error)
+            }
+        }
+    }
+}
+
+/// A change to forwarding or NAT outside of `wg-manager`'s nftables table
+#[derive(Debug, Clone, PartialEq)]
+pub enum ForwardingChange {
+    EnableForwarding,
+    DisableForwarding,
+    AddMasquerade { source: Ipv4Net, egress: String },
+    RemoveMasquerade { source: Ipv4Net, egress: String },
+}
+
+impl fmt::Display for ForwardingChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ForwardingChange::EnableForwarding => write!(f, "enable {}", IP_FORWARD),
+            ForwardingChange::DisableForwarding => write!(f, "disable {}", IP_FORWARD),
+            ForwardingChange::AddMasquerade { source, egress } => write!(
+                f,
+                "add iptables masquerade for {} out of {}",
+                source, egress
+            ),
+            ForwardingChange::RemoveMasquerade { source, egress } => write!(
+                f,
+                "remove iptables masquerade for {} out of {}",
+                source, egress
+            ),
+        }
+    }
+}
+
+/// The nat chain masquerading `source` out of `egress`, for `nft::replace_table`
+pub fn masquerade_chain(source: Ipv4Net, egress: &str) -> String {
+    format!(
+        "\tchain postrouting {{\n\
+         \t\ttype nat hook postrouting priority srcnat; policy accept;\n\
+         \t\tip saddr {} oifname \"{}\" masquerade\n\
+         \t}}\n",
+        source, egress
+    )
+}
+
+/// Handle to sysctls, through files under `/proc/sys`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Sysctl {
+    proc_path: PathBuf,
+}
+
+impl Default for Sysctl {
+    fn default() -> Self {
+        Sysctl {
+            proc_path: PathBuf::from("/proc/sys"),
+        }
+    }
+}
+
+impl Sysctl {
+    fn path(&self, key: &str) -> PathBuf {
+        self.proc_path.join(key.replace('.', "/"))
+    }
+
+    pub fn get(&self, key: &str) -> Result<String, ForwardingError> {
+        std::fs::read_to_string(self.path(key))
+            .map(|value| value.trim().to_owned())
+            .map_err(|error| ForwardingError::Sysctl {
+                key: key.to_owned(),
+                error,
+            })
+    }
+
+    pub fn set(&self, key: &str, value: &str) -> Result<(), ForwardingError> {
+        std::fs::write(self.path(key), format!("{}\n", value)).map_err(|error| {
+            ForwardingError::Sysctl {
+                key: key.to_owned(),
+                error,
+            }
+        })
+    }
+}
+
+/// Handle to the iptables binary
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Iptables {
+    binary_path: String,
+}
+
+impl Default for Iptables {
+    fn default() -> Self {
+        Iptables {
+            binary_path: "iptables".into(),
+        }
+    }
+}
+
+impl Iptables {
+    /// Whether the masquerade rule for `source` out of `egress` exists
+    pub fn has_masquerade(&self, source: Ipv4Net, egress: &str) -> Result<bool, ForwardingError> {
+        match self.masquerade("-C", source, egress) {
+            Ok(()) => Ok(true),
+            // iptables exits with 1 when checking for a rule that doesn't exist
+            Err(ForwardingError::CommandFailed { code: Some(1), .. }) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn add_masquerade(&self, source: Ipv4Net, egress: &str) -> Result<(), ForwardingError> {
+        self.masquerade("-A", source, egress)
+    }
+
+    pub fn remove_masquerade(&self, source: Ipv4Net, egress: &str) -> Result<(), ForwardingError> {
+        self.masquerade("-D", source, egress)
+    }
+
+    fn masquerade(
+        &self,
+        action: &str,
+        source: Ipv4Net,
+        egress: &str,
+    ) -> Result<(), ForwardingError> {
+        let source = source.to_string();
+        self.run(&[
+            "-t",
+            "nat",
+            action,
+            "POSTROUTING",
+            "-s",
+            &source,
+            "-o",
+            egress,
+            "-j",
+            "MASQUERADE",
+        ])
+    }
+
+    fn run(&self, args: &[&str]) -> Result<(), ForwardingError> {
+        utils::run_command(&self.binary_path, args, None)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sysctl() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("net/ipv4")).unwrap();
+        std::fs::write(dir.path().join("net/ipv4/ip_forward"), "0\n").unwrap();
+        let sysctl = Sysctl {
+            proc_path: dir.path().to_owned(),
+        };
+
+        assert_eq!(sysctl.get(IP_FORWARD).unwrap(), "0");
+        sysctl.set(IP_FORWARD, "1").unwrap();
+        assert_eq!(sysctl.get(IP_FORWARD).unwrap(), "1");
+        assert!(sysctl.get("net.ipv4.nonsense").is_err());
+    }
+}
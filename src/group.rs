@@ -0,0 +1,144 @@
+/// Named groups of clients, whose settings are pushed into the configs of their members.
+///
+/// Clients name the groups they belong to, and groups that aren't defined simply contribute
+/// nothing, since groups were free-form labels (used to scope API tokens) before they had settings.
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+
+use crate::tuning::Tuning;
+use crate::utils;
+
+/// Client-side settings shared by a group's members
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Group {
+    /// Routed through the VPN, on top of the VPN's own range
+    #[serde(
+        default,
+        serialize_with = "utils::serialize_ipnets",
+        deserialize_with = "utils::deserialize_ipnets"
+    )]
+    allowed_ips: Vec<IpNet>,
+    #[serde(default)]
+    dns: Vec<IpAddr>,
+    /// MTU, keepalive and so on, stored alongside the other settings
+    #[serde(flatten)]
+    tuning: Tuning,
+    /// If any, everything is routed through the VPN except these, see `split_tunnel`
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        serialize_with = "utils::serialize_ipnets",
+        deserialize_with = "utils::deserialize_ipnets"
+    )]
+    route_all_except: Vec<IpNet>,
+}
+
+impl Group {
+    pub fn new(
+        allowed_ips: Vec<IpNet>,
+        dns: Vec<IpAddr>,
+        tuning: Tuning,
+        route_all_except: Vec<IpNet>,
+    ) -> Self {
+        Group {
+            allowed_ips,
+            dns,
+            tuning,
+            route_all_except,
+        }
+    }
+
+    /// Combines the settings of every group a client is in: the union of their allowed ips, dns
+    /// servers and excluded ranges (in order, without duplicates), and their tuning as
+    /// `Tuning::merge` combines it
+    pub fn merge<'a>(groups: impl IntoIterator<Item = &'a Group>) -> Group {
+        let mut merged = Group::default();
+        let mut tunings = Vec::new();
+
+        for group in groups {
+            for allowed_ip in &group.allowed_ips {
+                if !merged.allowed_ips.contains(allowed_ip) {
+                    merged.allowed_ips.push(*allowed_ip);
+                }
+            }
+            for dns in &group.dns {
+                if !merged.dns.contains(dns) {
+                    merged.dns.push(*dns);
+                }
+            }
+            for excluded in &group.route_all_except {
+                if !merged.route_all_except.contains(excluded) {
+                    merged.route_all_except.push(*excluded);
+                }
+            }
+            tunings.push(&group.tuning);
+        }
+
+        merged.tuning = Tuning::merge(tunings);
+        merged
+    }
+
+    pub fn allowed_ips(&self) -> &Vec<IpNet> {
+        &self.allowed_ips
+    }
+
+    pub fn dns(&self) -> &Vec<IpAddr> {
+        &self.dns
+    }
+
+    pub fn tuning(&self) -> &Tuning {
+        &self.tuning
+    }
+
+    pub fn route_all_except(&self) -> &Vec<IpNet> {
+        &self.route_all_except
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keepalive(secs: u16) -> Tuning {
+        Tuning {
+            persistent_keepalive: Some(secs),
+            ..Tuning::default()
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let engineering = Group::new(
+            vec!["10.0.0.0/8".parse().unwrap()],
+            vec!["10.0.0.1".parse().unwrap()],
+            keepalive(25),
+            vec!["192.168.0.0/16".parse().unwrap()],
+        );
+        let oncall = Group::new(
+            vec![
+                "10.0.0.0/8".parse().unwrap(),
+                "172.16.0.0/12".parse().unwrap(),
+            ],
+            vec!["10.0.0.2".parse().unwrap(), "10.0.0.1".parse().unwrap()],
+            keepalive(15),
+            vec![],
+        );
+        let sales = Group::default();
+
+        assert_eq!(
+            Group::merge([&engineering, &sales, &oncall]),
+            Group::new(
+                vec![
+                    "10.0.0.0/8".parse().unwrap(),
+                    "172.16.0.0/12".parse().unwrap(),
+                ],
+                vec!["10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap()],
+                keepalive(15),
+                vec!["192.168.0.0/16".parse().unwrap()],
+            )
+        );
+        assert_eq!(Group::merge([&sales]), Group::default());
+    }
+}
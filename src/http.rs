@@ -0,0 +1,188 @@
+/// Minimal HTTP/1.1 server, enough for local endpoints without pulling in a web framework.
+///
+/// Every connection handles exactly one request and is then closed.
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+/// Largest request body that will be read, anything bigger is rejected
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+pub struct Request {
+    pub method: String,
+    /// Path, without any query string
+    pub path: String,
+    /// Header names are lowercase
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn text(status: u16, body: impl Into<String>) -> Self {
+        Response {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: body.into().into_bytes(),
+        }
+    }
+
+    pub fn json(status: u16, body: &impl serde::Serialize) -> Self {
+        Response {
+            status,
+            content_type: "application/json",
+            body: serde_json::to_vec_pretty(body).expect("body is always serializable"),
+        }
+    }
+}
+
+/// Reads a request, including its body if it has a `Content-Length`
+pub fn read_request(stream: &mut impl BufRead) -> io::Result<Request> {
+    let mut request_line = String::new();
+    stream.read_line(&mut request_line)?;
+
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => {
+            let path = path.split('?').next().unwrap_or_default();
+            (method.to_owned(), path.to_owned())
+        }
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "malformed request line",
+            ))
+        }
+    };
+
+    // Headers, up to and including the empty line
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_lowercase(), value.trim().to_owned()));
+        }
+    }
+
+    let content_length = headers
+        .iter()
+        .find(|(name, _)| name == "content-length")
+        .map(|(_, value)| value.parse::<usize>())
+        .transpose()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "malformed content-length"))?
+        .unwrap_or(0);
+    if content_length > MAX_BODY_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "request body too large",
+        ));
+    }
+
+    let mut body = vec![0; content_length];
+    stream.read_exact(&mut body)?;
+
+    Ok(Request {
+        method,
+        path,
+        headers,
+        body,
+    })
+}
+
+pub fn write_response(stream: &mut impl Write, response: &Response) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason_phrase(response.status),
+        response.content_type,
+        response.body.len()
+    )?;
+    stream.write_all(&response.body)?;
+    stream.flush()
+}
+
+/// Serves requests sequentially until accepting a connection fails.
+///
+/// Works with any listener, e.g. `TcpListener::incoming` or `UnixListener::incoming`.
+pub fn serve<S: Read + Write>(
+    incoming: impl Iterator<Item = io::Result<S>>,
+    mut handler: impl FnMut(Request) -> Response,
+) -> io::Result<()> {
+    for stream in incoming {
+        let mut stream = stream?;
+
+        let request = read_request(&mut BufReader::new(&mut stream));
+        let response = match request {
+            Ok(request) => handler(request),
+            Err(e) => Response::text(400, e.to_string()),
+        };
+
+        // A client hanging up early is not our problem
+        let _ = write_response(&mut stream, &response);
+    }
+
+    Ok(())
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        410 => "Gone",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_request() {
+        let mut bytes: &[u8] =
+            b"POST /clients?x=1 HTTP/1.1\r\nHost: localhost\r\nContent-Length: 2\r\n\r\n{}";
+
+        let request = read_request(&mut bytes).unwrap();
+
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/clients");
+        assert_eq!(request.header("Host"), Some("localhost"));
+        assert_eq!(request.body, b"{}");
+    }
+
+    #[test]
+    fn test_write_response() {
+        let mut bytes: Vec<u8> = Vec::new();
+
+        write_response(&mut bytes, &Response::text(404, "nope")).unwrap();
+
+        assert_eq!(
+            String::from_utf8(bytes).unwrap(),
+            "HTTP/1.1 404 Not Found\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: 4\r\nConnection: close\r\n\r\nnope"
+        );
+    }
+}
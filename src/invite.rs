@@ -0,0 +1,135 @@
+/// Single-use invites, which let someone set up their own client without access to the server.
+///
+/// An invite reserves a client's name, ip and groups. Redeeming it (through the REST API) turns it
+/// into a client, either with a public key the user supplies or with a freshly generated keypair.
+/// Like API tokens, only a hash of the invite's token is stored.
+use std::{fmt, net::Ipv4Addr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::auth;
+
+/// Prefix of every invite token, distinct from API tokens so the two can't be confused
+const INVITE_PREFIX: &str = "wgi_";
+
+/// Why an invite could not be redeemed
+#[derive(Debug)]
+pub enum InviteError {
+    NotFound,
+    Expired(String),
+    Used(String),
+}
+
+impl fmt::Display for InviteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InviteError::NotFound => write!(f, "invite does not exist"),
+            InviteError::Expired(name) => write!(f, "invite for '{}' has expired", name),
+            InviteError::Used(name) => write!(f, "invite for '{}' has already been used", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Invite {
+    /// Name of the client that will be created
+    name: String,
+    ip: Ipv4Addr,
+    #[serde(default)]
+    groups: Vec<String>,
+
+    /// Hex-encoded SHA-256 of the token
+    hash: String,
+    /// Unix timestamps
+    created_at: u64,
+    expires_at: u64,
+    redeemed_at: Option<u64>,
+}
+
+impl Invite {
+    /// Creates a new invite, returning it along with the token to hand to the user
+    pub fn generate(
+        name: String,
+        ip: Ipv4Addr,
+        groups: Vec<String>,
+        created_at: u64,
+        ttl: u64,
+    ) -> std::io::Result<(Self, String)> {
+        let secret = auth::generate_secret(INVITE_PREFIX)?;
+
+        let invite = Invite {
+            name,
+            ip,
+            groups,
+            hash: auth::hash(&secret),
+            created_at,
+            expires_at: created_at.saturating_add(ttl),
+            redeemed_at: None,
+        };
+        Ok((invite, secret))
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn ip(&self) -> Ipv4Addr {
+        self.ip
+    }
+
+    pub fn groups(&self) -> &Vec<String> {
+        &self.groups
+    }
+
+    pub fn created_at(&self) -> u64 {
+        self.created_at
+    }
+
+    pub fn expires_at(&self) -> u64 {
+        self.expires_at
+    }
+
+    /// Whether `secret` is this invite's token
+    pub fn matches(&self, secret: &str) -> bool {
+        auth::hash(secret) == self.hash
+    }
+
+    /// Whether the invite can still be redeemed, i.e. it still reserves its name
+    pub fn is_pending(&self, now: u64) -> bool {
+        self.redeemed_at.is_none() && now < self.expires_at
+    }
+
+    /// Marks the invite as redeemed, failing if it has expired or already been used
+    pub fn redeem(&mut self, now: u64) -> Result<(), InviteError> {
+        if self.redeemed_at.is_some() {
+            return Err(InviteError::Used(self.name.clone()));
+        }
+        if now >= self.expires_at {
+            return Err(InviteError::Expired(self.name.clone()));
+        }
+
+        self.redeemed_at = Some(now);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redeem() {
+        let (mut invite, secret) =
+            Invite::generate("alice".into(), Ipv4Addr::new(10, 0, 0, 2), vec![], 1000, 60).unwrap();
+
+        assert!(secret.starts_with(INVITE_PREFIX));
+        assert!(invite.matches(&secret));
+        assert!(invite.is_pending(1059));
+        assert!(!invite.is_pending(1060));
+
+        assert!(matches!(invite.redeem(1060), Err(InviteError::Expired(_))));
+        assert!(invite.redeem(1030).is_ok());
+        assert!(!invite.is_pending(1031));
+        assert!(matches!(invite.redeem(1031), Err(InviteError::Used(_))));
+    }
+}
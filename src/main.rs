@@ -1,24 +1,75 @@
 #[macro_use]
 extern crate clap;
 
+mod api;
+mod auth;
+mod client_config;
+mod config;
+mod daemon;
+mod dns;
+mod doctor;
+mod forwarding;
+mod group;
+mod http;
+mod invite;
 mod manager;
+mod mesh;
+mod metrics;
+mod nft;
+mod output;
+mod policy;
+mod server_config;
+mod split_tunnel;
+mod status;
+mod tuning;
 mod utils;
 mod wg;
 
 use std::{
-    net::{Ipv4Addr, SocketAddrV4},
-    path::Path,
+    collections::BTreeMap,
+    fmt,
+    io::Write,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4, TcpListener},
+    os::unix::{
+        fs::{FileTypeExt, OpenOptionsExt},
+        net::UnixListener,
+    },
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
 };
 
-use clap::ArgMatches;
-use ipnet::Ipv4Net;
+use clap::{ArgMatches, SubCommand};
+use ipnet::{IpNet, Ipv4Net};
 
-use manager::{Manager, ManagerError};
-use utils::{cli_table, Lock, LockError};
+use auth::Role;
+use client_config::ExportFormat;
+use config::Config;
+use dns::{Dns, DnsFormat};
+use forwarding::NatMode;
+use group::Group;
+use invite::InviteError;
+use manager::{Client, ClientEdit, ClientFilter, ClientOptions, Manager, ManagerError};
+use mesh::Mesh;
+use output::{
+    ChangeView, ClientListOutput, ClientOutput, ClientView, DeletedClientOutput,
+    DeletedGroupOutput, DeletedMeshOutput, DeletedRuleOutput, DeletedTokenOutput, DnsFileView,
+    DnsOutput, DnsRenderOutput, DoctorOutput, ErrorBody, ErrorOutput, ExportOutput,
+    GroupListOutput, GroupOutput, GroupView, InterfaceDownOutput, InterfaceListOutput, InviteView,
+    MeshListOutput, MeshOutput, MeshView, NewClientOutput, NewInviteOutput, NewTokenOutput,
+    OutputFormat, PeerStatusView, PlanOutput, PolicyCheckOutput, PolicyListOutput,
+    PolicyRenderOutput, PresharedKeyOutput, RotatedClientsOutput, RuleOutput, RuleView,
+    ServerConfigOutput, ServerKeyOutput, ServerOutput, SettingsOutput, StatusOutput,
+    TokenListOutput, TokenView, TuningView,
+};
+use policy::{Ports, Rule, Target, Verdict};
+use tuning::{Table, Tuning};
+use utils::{Lock, LockError};
+use wg::WgError;
 
-const NAME: &'static str = env!("CARGO_PKG_NAME");
-const VERSION: &'static str = env!("CARGO_PKG_VERSION");
-const AUTHOR: &'static str = env!("CARGO_PKG_AUTHORS");
+const NAME: &str = env!("CARGO_PKG_NAME");
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+const AUTHOR: &str = env!("CARGO_PKG_AUTHORS");
 
 type CLIResult = std::result::Result<(), CLIError>;
 
@@ -28,23 +79,116 @@ enum CLIError {
     FailedToSaveConfig(ManagerError),
     ClapError(clap::Error),
     LockAcquisitionError(LockError),
+    WgError(WgError),
+    ClientNotFound(String),
+    ClientExists(String),
+    InvalidPublicKey(String),
+    PublicKeyExists(String),
+    InviteRejected(InviteError),
+    /// The ip is outside the VPN's range, or already in use
+    IpUnavailable(ManagerError),
+    InvalidName(String),
     Other(String),
 }
 
-impl ToString for CLIError {
-    fn to_string(&self) -> String {
+impl CLIError {
+    /// Process exit code for this error, so that scripts can tell failures apart.
+    ///
+    /// | Code | Meaning                                        |
+    /// |------|------------------------------------------------|
+    /// | 1    | Other failure (including argument parsing)     |
+    /// | 3    | Config could not be loaded or saved            |
+    /// | 4    | Config is locked by another process            |
+    /// | 5    | Named client does not exist                    |
+    /// | 6    | Client with that name already exists           |
+    /// | 7    | Invite does not exist, has expired or was used |
+    /// | 8    | Public key already belongs to another client   |
+    /// | 9    | IP address is out of range or already in use   |
+    /// | 10   | `wg` binary not found                          |
+    /// | 11   | WireGuard interface does not exist             |
+    /// | 12   | Permission denied when running `wg`            |
+    /// | 13   | `wg` failed for any other reason               |
+    fn exit_code(&self) -> i32 {
+        match self {
+            CLIError::FailedToLoadConfig(_) | CLIError::FailedToSaveConfig(_) => 3,
+            CLIError::LockAcquisitionError(_) => 4,
+            CLIError::ClientNotFound(_) => 5,
+            CLIError::ClientExists(_) => 6,
+            CLIError::InviteRejected(_) => 7,
+            CLIError::PublicKeyExists(_) => 8,
+            CLIError::IpUnavailable(_) => 9,
+            CLIError::WgError(e) => match e {
+                WgError::NotInstalled { .. } => 10,
+                WgError::InterfaceNotFound { .. } => 11,
+                WgError::PermissionDenied { .. } => 12,
+                WgError::CommandFailed { .. }
+                | WgError::IOError { .. }
+                | WgError::InvalidOutput { .. } => 13,
+            },
+            CLIError::ClapError(_)
+            | CLIError::InvalidPublicKey(_)
+            | CLIError::InvalidName(_)
+            | CLIError::Other(_) => 1,
+        }
+    }
+
+    /// Stable identifier for the error, used in JSON output
+    fn kind(&self) -> &'static str {
+        match self {
+            CLIError::FailedToLoadConfig(_) => "config_load_failed",
+            CLIError::FailedToSaveConfig(_) => "config_save_failed",
+            CLIError::ClapError(_) => "invalid_arguments",
+            CLIError::LockAcquisitionError(_) => "config_locked",
+            CLIError::ClientNotFound(_) => "client_not_found",
+            CLIError::ClientExists(_) => "client_exists",
+            CLIError::InvalidPublicKey(_) => "invalid_public_key",
+            CLIError::PublicKeyExists(_) => "public_key_exists",
+            CLIError::IpUnavailable(_) => "ip_unavailable",
+            CLIError::InvalidName(_) => "invalid_name",
+            CLIError::InviteRejected(e) => match e {
+                InviteError::NotFound => "invite_not_found",
+                InviteError::Expired(_) => "invite_expired",
+                InviteError::Used(_) => "invite_used",
+            },
+            CLIError::WgError(e) => match e {
+                WgError::NotInstalled { .. } => "wg_not_installed",
+                WgError::InterfaceNotFound { .. } => "interface_not_found",
+                WgError::PermissionDenied { .. } => "permission_denied",
+                WgError::CommandFailed { .. }
+                | WgError::IOError { .. }
+                | WgError::InvalidOutput { .. } => "wg_failed",
+            },
+            CLIError::Other(_) => "other",
+        }
+    }
+}
+
+impl fmt::Display for CLIError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            CLIError::FailedToLoadConfig(e) => {
-                format!("Failed to load config: {}", e.to_string())
+            CLIError::FailedToLoadConfig(e) => write!(f, "Failed to load config: {}", e),
+            CLIError::FailedToSaveConfig(e) => write!(f, "Failed to save config: {}", e),
+            CLIError::ClapError(e) => write!(f, "Failure in argument parsing: {}", e),
+            CLIError::LockAcquisitionError(e) => {
+                write!(f, "Failed to acquire lock on config: {}", e)
             }
-            CLIError::FailedToSaveConfig(e) => {
-                format!("Failed to save config: {}", e.to_string())
+            CLIError::WgError(e) => write!(f, "WireGuard error: {}", e),
+            CLIError::ClientNotFound(name) => {
+                write!(f, "client with name '{}' does not exist", name)
             }
-            CLIError::ClapError(e) => format!("Failure in argument parsing: {}", e.to_string()),
-            CLIError::LockAcquisitionError(e) => {
-                format!("Failed to acquire lock on config: {}", e.to_string())
+            CLIError::ClientExists(name) => {
+                write!(f, "client with name '{}' already exists", name)
+            }
+            CLIError::InvalidPublicKey(key) => write!(f, "'{}' is not a valid public key", key),
+            CLIError::PublicKeyExists(name) => {
+                write!(f, "public key already belongs to client '{}'", name)
             }
-            CLIError::Other(e) => e.to_string(),
+            CLIError::InviteRejected(e) => write!(f, "{}", e),
+            CLIError::IpUnavailable(e) => write!(f, "{}", e),
+            CLIError::InvalidName(name) => {
+                write!(f, "{}", ManagerError::InvalidNameError(name.clone()))
+            }
+            CLIError::Other(e) => write!(f, "{}", e),
         }
     }
 }
@@ -58,9 +202,16 @@ impl From<clap::Error> for CLIError {
 impl From<ManagerError> for CLIError {
     fn from(e: ManagerError) -> Self {
         match e {
-            ManagerError::ClientNameExistsError(name) => {
-                CLIError::Other(format!("client with name '{}' already exists", name))
+            ManagerError::WgError(e) => CLIError::WgError(e),
+            ManagerError::ClientNotFoundError(name) => CLIError::ClientNotFound(name),
+            ManagerError::ClientNameExistsError(name) => CLIError::ClientExists(name),
+            ManagerError::InvalidPublicKeyError(key) => CLIError::InvalidPublicKey(key),
+            ManagerError::PublicKeyExistsError(name) => CLIError::PublicKeyExists(name),
+            ManagerError::InviteError(e) => CLIError::InviteRejected(e),
+            ManagerError::IpOutOfRangeError { .. } | ManagerError::IpTakenError { .. } => {
+                CLIError::IpUnavailable(e)
             }
+            ManagerError::InvalidNameError(name) => CLIError::InvalidName(name),
             _ => CLIError::Other(e.to_string()),
         }
     }
@@ -74,12 +225,244 @@ pub fn main() {
         (@setting SubcommandRequiredElseHelp)
         (@arg CONFIG: -c --config [FILE] "Path to config file")
         (@arg DRY_RUN: -D --("dry-run") "Don't commit changes to the wireguard interface")
+        (@arg OUTPUT: -o --output +takes_value +global possible_value[table json csv] default_value("table")
+            "Format to print results in")
+        (@arg INTERFACE: --interface +takes_value +global
+            "The interface to act on, needed when the config has several")
         (@subcommand new =>
-            (about: "Configure a new server (and create config)")
+            (about: "Configure a new server (and create config), or add an interface to an existing config")
             (@arg ("IP-RANGE"): * "IPv4 range for the VPN in CIDR notation")
             (@arg ("BIND-SOCKET-ADDR"): * "The IPv4 address and port to bind to (e.g. 127.0.0.1:51900), default port is 51900")
             (@arg ("INTERFACE-NAME"): * "The name of the interface")
         )
+        (@subcommand status =>
+            (about: "Show configured clients joined with live peer state from the interface")
+            (@arg ONLINE_THRESHOLD: --("online-threshold") +takes_value
+                "Seconds since the latest handshake for a peer to count as online [default: 180]")
+        )
+        (@subcommand metrics =>
+            (about: "Serve Prometheus metrics for the interface and its peers")
+            (@arg LISTEN: -l --listen +takes_value
+                "Address to serve /metrics on [default: 127.0.0.1:9586]")
+            (@arg TEXTFILE: --textfile +takes_value conflicts_with[LISTEN]
+                "Write metrics once to this file (for node_exporter's textfile collector) and exit")
+            (@arg ONLINE_THRESHOLD: --("online-threshold") +takes_value
+                "Seconds since the latest handshake for a peer to count as online [default: 180]")
+        )
+        (@subcommand daemon =>
+            (about: "Keep the interface in line with the config, reconciling on change and periodically")
+            (@arg INTERVAL: -i --interval +takes_value
+                "Seconds between periodic reconciliations [default: 60]")
+        )
+        (@subcommand plan =>
+            (about: "Show the changes that committing would make to the interface")
+        )
+        (@subcommand doctor =>
+            (about: "Check the host for problems that stop the VPN working as configured")
+        )
+        (@subcommand interface =>
+            (about: "Interface-related commands")
+            (@setting SubcommandRequiredElseHelp)
+            (@subcommand list =>
+                (about: "List the interfaces in the config")
+            )
+            (@subcommand down =>
+                (about: "Remove the firewall rules, NAT and forwarding that commits set up, until the next commit")
+                (@arg KEEP_FORWARDING: --("keep-forwarding")
+                    "Leave net.ipv4.ip_forward on, e.g. when the host routes other traffic")
+            )
+        )
+        (@subcommand serve =>
+            (about: "Serve the REST API for managing clients")
+            (@arg LISTEN: -l --listen +takes_value
+                "Loopback address to listen on [default: 127.0.0.1:9587]")
+            (@arg SOCKET: --socket +takes_value conflicts_with[LISTEN]
+                "Listen on this unix socket instead")
+        )
+        (@subcommand server =>
+            (about: "Server-related commands")
+            (@setting SubcommandRequiredElseHelp)
+            // `clap_app!` only accepts identifiers as subcommand names
+            (subcommand: clap_app!(@app (SubCommand::with_name("rotate-key"))
+                (about: "Replace the server's key, re-issuing configs for clients whose key is stored")
+                (@arg STAGE: --stage conflicts_with[SWITCH]
+                    "Only prepare the new key and configs, switching to the key later with --switch")
+                (@arg AT: --at +takes_value requires[STAGE]
+                    "With --stage, have the daemon switch at this time, e.g. 2026-12-31T18:00Z")
+                (@arg SWITCH: --switch "Switch to the staged key now")
+                (@arg BUNDLE: -b --bundle +takes_value
+                    "Write the re-issued configs to this directory, as <client>.conf")
+            ))
+            (@subcommand settings =>
+                (about: "Show the server's settings, changing any that are given")
+                (@arg DEFAULT_PSK: --("default-psk") +takes_value possible_value[on off]
+                    "Whether new clients get a preshared key unless told otherwise")
+                (@arg EXPIRY_GRACE: --("expiry-grace") +takes_value
+                    "How long expired clients stay disabled before being deleted, e.g. 7d, or never")
+                (@arg EGRESS_INTERFACE: --("egress-interface") +takes_value
+                    "Interface that traffic beyond the VPN leaves through, e.g. eth0, or none")
+                (@arg NAT: --nat +takes_value possible_value[off nftables iptables]
+                    "Whether commits enable forwarding and masquerade out of the egress interface, and with what")
+                (@arg MTU: --mtu +takes_value
+                    "The interface MTU, 1280 to 9000, or default to unset it")
+                (@arg KEEPALIVE: --keepalive +takes_value
+                    "Default seconds between keepalive packets from clients, off, or default to unset it")
+                (@arg FWMARK: --fwmark +takes_value
+                    "Firewall mark for the interface's packets, e.g. 0x51820, off, or default to unset it")
+                (@arg TABLE: --table +takes_value
+                    "Routing table for wg-quick's routes: off, auto, main or a number, or default to unset it")
+                (@arg POST_UP: --("post-up") +takes_value +multiple number_of_values(1)
+                    "A command for wg-quick to run once the interface is up, replacing any, empty to remove them, can be given multiple times")
+                (@arg POST_DOWN: --("post-down") +takes_value +multiple number_of_values(1)
+                    "A command for wg-quick to run once the interface is down, replacing any, empty to remove them, can be given multiple times")
+            )
+            (@subcommand config =>
+                (about: "Print a wg-quick config for the server, with every enabled client as a peer")
+            )
+        )
+        (@subcommand token =>
+            (about: "Manage bearer tokens for the REST API")
+            (@setting SubcommandRequiredElseHelp)
+            (@subcommand new =>
+                (about: "Create a new token, printing it once")
+                (@arg NAME: * "A unique name for the token")
+                (@arg ROLE: -r --role +takes_value possible_value[viewer issuer admin]
+                    default_value("viewer")
+                    "viewer: list and status, issuer: also create clients in --group, admin: everything")
+                (@arg GROUP: -g --group +takes_value "The group an issuer may create clients in")
+            )
+            (@subcommand list =>
+                (about: "List tokens")
+            )
+            (@subcommand delete =>
+                (about: "Delete a token")
+                (@arg NAME: * "The unique name of the token")
+            )
+        )
+        (@subcommand group =>
+            (about: "Manage groups, whose settings go into their members' configs")
+            (@setting SubcommandRequiredElseHelp)
+            (@subcommand set =>
+                (about: "Create or replace a group, printing its members' re-rendered configs")
+                (@arg NAME: * "The name of the group")
+                (@arg ALLOWED_IP: --("allowed-ip") +takes_value +multiple number_of_values(1)
+                    "A network for members to route through the VPN, on top of its own range, can be given multiple times")
+                (@arg DNS: --dns +takes_value +multiple number_of_values(1)
+                    "A DNS server for members to use, can be given multiple times")
+                (@arg MTU: --mtu +takes_value "The members' MTU, 1280 to 9000")
+                (@arg KEEPALIVE: --keepalive +takes_value
+                    "Seconds between keepalive packets from members, e.g. 25 to keep NAT mappings open, or off")
+                (@arg FWMARK: --fwmark +takes_value
+                    "Firewall mark for members' packets, e.g. 0x51820, or off")
+                (@arg TABLE: --table +takes_value
+                    "Routing table for members' routes: off, auto, main or a number")
+                (@arg POST_UP: --("post-up") +takes_value +multiple number_of_values(1)
+                    "A command for members' wg-quick to run once the interface is up, can be given multiple times")
+                (@arg POST_DOWN: --("post-down") +takes_value +multiple number_of_values(1)
+                    "A command for members' wg-quick to run once the interface is down, can be given multiple times")
+                (@arg ROUTE_ALL_EXCEPT: --("route-all-except") +takes_value +multiple number_of_values(1)
+                    "Have members route everything through the VPN except this network, can be given multiple times")
+                (@arg BUNDLE: -b --bundle +takes_value "Also write the configs to <BUNDLE>/<client>.conf")
+            )
+            (@subcommand render =>
+                (about: "Print the configs of a group's members")
+                (@arg NAME: * "The name of the group")
+                (@arg BUNDLE: -b --bundle +takes_value "Also write the configs to <BUNDLE>/<client>.conf")
+            )
+            (@subcommand list =>
+                (about: "List groups")
+            )
+            (@subcommand delete =>
+                (about: "Delete a group's settings, its members stay in it by name")
+                (@arg NAME: * "The name of the group")
+            )
+        )
+        (@subcommand mesh =>
+            (about: "Manage full-mesh networks, in which every node is a peer of every other")
+            (@setting SubcommandRequiredElseHelp)
+            (@subcommand new =>
+                (about: "Create a mesh")
+                (@arg NAME: * "A unique name for the mesh")
+                (@arg ("IP-RANGE"): * "IPv4 range for the mesh in CIDR notation")
+                (@arg KEEPALIVE: --keepalive +takes_value
+                    "Seconds between keepalive packets between nodes, e.g. 25 for nodes behind NAT")
+            )
+            (@subcommand add =>
+                (about: "Add a node, printing every node's config since they all change")
+                (@arg MESH: * "The name of the mesh")
+                (@arg NAME: * "A unique name for the node within the mesh")
+                (@arg IP: * "The node's IPv4 address in the mesh")
+                (@arg ENDPOINT: * "Where the other nodes reach it, as <host>:<port>, the port being its listen port")
+                (@arg PUBLIC_KEY: --("public-key") +takes_value
+                    "Use the node's own public key, rather than generating a keypair")
+                (@arg BUNDLE: -b --bundle +takes_value "Also write the configs to <BUNDLE>/<node>.conf")
+            )
+            (@subcommand remove =>
+                (about: "Remove a node, printing the remaining nodes' configs")
+                (@arg MESH: * "The name of the mesh")
+                (@arg NAME: * "The name of the node")
+                (@arg BUNDLE: -b --bundle +takes_value "Also write the configs to <BUNDLE>/<node>.conf")
+            )
+            (@subcommand render =>
+                (about: "Print every node's config")
+                (@arg MESH: * "The name of the mesh")
+                (@arg BUNDLE: -b --bundle +takes_value "Also write the configs to <BUNDLE>/<node>.conf")
+            )
+            (@subcommand list =>
+                (about: "List meshes")
+            )
+            (@subcommand delete =>
+                (about: "Delete a mesh and its nodes")
+                (@arg NAME: * "The name of the mesh")
+            )
+        )
+        (@subcommand policy =>
+            (about: "Manage which groups may reach what, enforced with nftables")
+            (@setting SubcommandRequiredElseHelp)
+            (@subcommand allow =>
+                (about: "Allow a group to reach another group or a network, the first rule stops clients reaching each other otherwise")
+                (@arg FROM: * "The group that traffic comes from")
+                (@arg TO: * "The group, or network in CIDR notation, that traffic goes to")
+                (@arg PORT: -p --port +takes_value +multiple number_of_values(1)
+                    "Only allow these ports, e.g. tcp/22 or udp/8000-8100, can be given multiple times")
+            )
+            (@subcommand list =>
+                (about: "List rules")
+            )
+            (@subcommand remove =>
+                (about: "Remove a rule, removing the last one stops enforcing the policy")
+                (@arg NUMBER: * "The rule's number, as shown by list")
+            )
+            (@subcommand render =>
+                (about: "Print the nftables ruleset that commits load")
+            )
+            (@subcommand check =>
+                (about: "Check whether the policy allows traffic from one client (or ip) to another")
+                (@arg FROM: * "Client name or ip that traffic comes from")
+                (@arg TO: * "Client name or ip that traffic goes to")
+                (@arg PORT: -p --port +takes_value "A port the traffic is to, e.g. tcp/22")
+            )
+        )
+        (@subcommand dns =>
+            (about: "Manage DNS records that name clients <client>.<domain>, for a resolver on the server")
+            (@setting SubcommandRequiredElseHelp)
+            (@subcommand set =>
+                (about: "Write records on commit, and point client configs at the server's resolver")
+                (@arg DOMAIN: * "The domain clients are named under, e.g. vpn.example.com")
+                (@arg SERVER_IP: --("server-ip") +takes_value +required
+                    "The resolver's address on the server, in the VPN's range")
+                (@arg FORMAT: --format +takes_value +required possible_value[hosts dnsmasq unbound zone]
+                    "hosts for a hosts file, dnsmasq or unbound for their config, or zone for zone files")
+                (@arg FILE: --file +takes_value +required
+                    "Where to write the records, zone also writes the reverse zone to <FILE>.rev")
+            )
+            (@subcommand render =>
+                (about: "Print the records that commits write")
+            )
+            (@subcommand off =>
+                (about: "Stop writing records, leaving the files as they are")
+            )
+        )
         (@subcommand client =>
             (about: "Client-related commands")
             (@setting SubcommandRequiredElseHelp)
@@ -87,18 +470,170 @@ pub fn main() {
                 (about: "Configure a new client")
                 (@arg NAME: * "A unique name for the client")
                 (@arg IP: * "The IPv4 address for the client")
+                (@arg GROUP: -g --group +takes_value +multiple number_of_values(1)
+                    "A group for the client to belong to, can be given multiple times")
+                (@arg PUBLIC_KEY: --("public-key") +takes_value
+                    "Use the client's own public key, rather than generating a keypair")
+                (@arg STORE_KEY: --("store-key") conflicts_with[PUBLIC_KEY]
+                    "Keep the generated private key in the config, so that the client's config can be re-issued")
+                (@arg PSK: --psk conflicts_with[NO_PSK] "Give the client a preshared key")
+                (@arg NO_PSK: --("no-psk") "Don't give the client a preshared key, even by default")
+                (@arg EXPIRES: --expires +takes_value conflicts_with[TTL]
+                    "When the client is disabled, e.g. 2026-12-31 or a unix timestamp")
+                (@arg TTL: --ttl +takes_value "How long until the client is disabled, e.g. 30d")
+                (@arg OWNER: --owner +takes_value "Who the client belongs to, e.g. an email address")
+                (@arg DESCRIPTION: --description +takes_value "A free-form description")
+                (@arg TAG: --tag +takes_value +multiple number_of_values(1)
+                    "A KEY=VALUE tag, can be given multiple times")
+                (@arg SUBNET: --subnet +takes_value +multiple number_of_values(1)
+                    "A network behind the client to route to it, e.g. a branch router's LAN, can be given multiple times")
+                (@arg ROUTE_ALL_EXCEPT: --("route-all-except") +takes_value +multiple number_of_values(1)
+                    "Route everything through the VPN except this network, e.g. a home LAN, can be given multiple times")
+                (@arg MTU: --mtu +takes_value "The client's MTU, 1280 to 9000, instead of what its groups or the server set")
+                (@arg KEEPALIVE: --keepalive +takes_value
+                    "Seconds between keepalive packets from the client, e.g. 25 to keep NAT mappings open, or off, instead of what its groups or the server set")
+                (@arg FWMARK: --fwmark +takes_value
+                    "Firewall mark for the client's packets, e.g. 0x51820, or off, instead of what its groups or the server set")
+                (@arg TABLE: --table +takes_value
+                    "Routing table for the client's routes: off, auto, main or a number, instead of what its groups or the server set")
+                (@arg POST_UP: --("post-up") +takes_value +multiple number_of_values(1)
+                    "A command for the client's wg-quick to run once the interface is up instead of its groups', can be given multiple times")
+                (@arg POST_DOWN: --("post-down") +takes_value +multiple number_of_values(1)
+                    "A command for the client's wg-quick to run once the interface is down instead of its groups', can be given multiple times")
+            )
+            (@subcommand edit =>
+                (about: "Change a client's owner, description, tags, groups, expiry date, subnets, routing or tuning")
+                (@arg NAME: * "The unique name of the client")
+                (@arg OWNER: --owner +takes_value "Who the client belongs to, empty to remove")
+                (@arg DESCRIPTION: --description +takes_value "A free-form description, empty to remove")
+                (@arg TAG: --tag +takes_value +multiple number_of_values(1)
+                    "A KEY=VALUE tag to add or overwrite, can be given multiple times")
+                (@arg UNTAG: --untag +takes_value +multiple number_of_values(1)
+                    "The KEY of a tag to remove, can be given multiple times")
+                (@arg ADD_GROUP: --("add-group") +takes_value +multiple number_of_values(1)
+                    "A group to add the client to, can be given multiple times")
+                (@arg REMOVE_GROUP: --("remove-group") +takes_value +multiple number_of_values(1)
+                    "A group to remove the client from, can be given multiple times")
+                (@arg EXPIRES: --expires +takes_value conflicts_with[TTL NEVER_EXPIRES]
+                    "A new expiry date, e.g. 2026-12-31 or a unix timestamp")
+                (@arg TTL: --ttl +takes_value conflicts_with[NEVER_EXPIRES]
+                    "A new time until the client is disabled, e.g. 30d")
+                (@arg NEVER_EXPIRES: --("never-expires") "Remove the client's expiry date")
+                (@arg ADD_SUBNET: --("add-subnet") +takes_value +multiple number_of_values(1)
+                    "A network behind the client to route to it, can be given multiple times")
+                (@arg REMOVE_SUBNET: --("remove-subnet") +takes_value +multiple number_of_values(1)
+                    "A network to stop routing to the client, can be given multiple times")
+                (@arg ROUTE_ALL_EXCEPT: --("route-all-except") +takes_value +multiple number_of_values(1)
+                    conflicts_with[NO_ROUTE_ALL_EXCEPT]
+                    "Route everything through the VPN except this network, replacing the client's own exceptions, can be given multiple times")
+                (@arg NO_ROUTE_ALL_EXCEPT: --("no-route-all-except")
+                    "Stop routing everything through the VPN, unless the client's groups do")
+                (@arg MTU: --mtu +takes_value "The client's MTU, 1280 to 9000, or default to inherit it again")
+                (@arg KEEPALIVE: --keepalive +takes_value
+                    "Seconds between keepalive packets from the client, e.g. 25 to keep NAT mappings open, or off, or default to inherit it again")
+                (@arg FWMARK: --fwmark +takes_value
+                    "Firewall mark for the client's packets, e.g. 0x51820, or off, or default to inherit it again")
+                (@arg TABLE: --table +takes_value
+                    "Routing table for the client's routes: off, auto, main or a number, or default to inherit it again")
+                (@arg POST_UP: --("post-up") +takes_value +multiple number_of_values(1)
+                    "A command for the client's wg-quick to run once the interface is up, replacing the client's own, empty to inherit them again, can be given multiple times")
+                (@arg POST_DOWN: --("post-down") +takes_value +multiple number_of_values(1)
+                    "A command for the client's wg-quick to run once the interface is down, replacing the client's own, empty to inherit them again, can be given multiple times")
+            )
+            (@subcommand rotate =>
+                (about: "Give a client a new keypair, keeping its name, ip and everything else")
+                (@arg NAME: required_unless[ALL] conflicts_with[ALL] "The unique name of the client")
+                (@arg ALL: --all "Rotate every client (see --older-than)")
+                (@arg OLDER_THAN: --("older-than") +takes_value requires[ALL]
+                    "With --all, only rotate keys older than this, e.g. 180d")
+                (@arg PUBLIC_KEY: --("public-key") +takes_value conflicts_with[ALL]
+                    "Use the client's own new public key, rather than generating a keypair")
+            )
+            (@subcommand psk =>
+                (about: "Give a client a new preshared key, replacing any it had")
+                (@arg NAME: * "The unique name of the client")
+            )
+            (@subcommand invite =>
+                (about: "Invite someone to set up a client themselves, through the REST API")
+                (@arg NAME: * "A unique name for the client")
+                (@arg IP: * "The IPv4 address for the client")
+                (@arg GROUP: -g --group +takes_value +multiple number_of_values(1)
+                    "A group for the client to belong to, can be given multiple times")
+                (@arg TTL: --ttl +takes_value default_value("24h")
+                    "How long the invite can be redeemed for, e.g. 30m, 24h or 7d")
+            )
+            (@subcommand disable =>
+                (about: "Take a client off the interface, keeping its ip, key and everything else")
+                (@arg NAME: * "The unique name of the client")
+                (@arg REASON: -r --reason +takes_value "Why the client is being disabled")
+            )
+            (@subcommand enable =>
+                (about: "Put a disabled client back on the interface")
+                (@arg NAME: * "The unique name of the client")
+                (@arg EXPIRES: --expires +takes_value conflicts_with[TTL]
+                    "A new expiry date, e.g. 2026-12-31 or a unix timestamp")
+                (@arg TTL: --ttl +takes_value "A new time until the client is disabled, e.g. 30d")
             )
             (@subcommand list =>
                 (about: "List configured clients")
+                (@arg LIVE: --live conflicts_with[EXPIRING]
+                    "Include live peer state from the interface (same as `status`)")
+                (@arg EXPIRING: --expiring +takes_value
+                    "Only list clients expiring within this long (or already expired), e.g. 14d")
+                (@arg IP: --ip +takes_value conflicts_with[LIVE] "Only list the client with this ip")
+                (@arg OWNER: --owner +takes_value conflicts_with[LIVE]
+                    "Only list clients with this owner (ignoring case)")
+                (@arg DESCRIPTION: --description +takes_value conflicts_with[LIVE]
+                    "Only list clients whose description contains this (ignoring case)")
+                (@arg TAG: --tag +takes_value +multiple number_of_values(1) conflicts_with[LIVE]
+                    "Only list clients with this KEY=VALUE tag, or any tag KEY, can be given multiple times")
+                (@arg CREATED_BY: --("created-by") +takes_value conflicts_with[LIVE]
+                    "Only list clients created by this user")
+                (@arg CREATED_AFTER: --("created-after") +takes_value conflicts_with[LIVE]
+                    "Only list clients created at or after this time, e.g. 2026-01-01")
+                (@arg CREATED_BEFORE: --("created-before") +takes_value conflicts_with[LIVE]
+                    "Only list clients created at or before this time")
+                (@arg UPDATED_AFTER: --("updated-after") +takes_value conflicts_with[LIVE]
+                    "Only list clients updated at or after this time")
+                (@arg UPDATED_BEFORE: --("updated-before") +takes_value conflicts_with[LIVE]
+                    "Only list clients updated at or before this time")
+                (@arg ONLINE_THRESHOLD: --("online-threshold") +takes_value
+                    "Seconds since the latest handshake for a peer to count as online [default: 180]")
+            )
+            (@subcommand show =>
+                (about: "Show a configured client")
+                (@arg NAME: * "The unique name of the client")
+            )
+            (@subcommand export =>
+                (about: "Print a client's config for wg-quick, systemd-networkd or NetworkManager")
+                (@arg NAME: * "The unique name of the client")
+                (@arg FORMAT: --format +takes_value default_value("wg-quick")
+                    "wg-quick, networkd for a .netdev and .network pair, or nmconnection for a NetworkManager keyfile")
+                (@arg IFNAME: --ifname +takes_value
+                    "The name of the client's interface, the server's by default")
+                (@arg BUNDLE: -b --bundle +takes_value "Also write the files to this directory")
             )
             (@subcommand delete =>
                 (about: "Delete a configured client")
                 (@arg NAME: * "The unique name of the client")
             )
-
         )
     );
-    let app_m = app.get_matches();
+    let app_m = match app.get_matches_safe() {
+        Ok(app_m) => app_m,
+        // Help and version aren't errors, and are printed as usual
+        Err(e)
+            if e.kind == clap::ErrorKind::HelpDisplayed
+                || e.kind == clap::ErrorKind::VersionDisplayed =>
+        {
+            e.exit()
+        }
+        // Scripts asking for JSON get argument errors as JSON too, like any other error
+        Err(e) => match OutputFormat::from_args(&std::env::args().collect::<Vec<String>>()) {
+            Some(OutputFormat::Json) => json_err(&CLIError::ClapError(e)),
+            _ => e.exit(),
+        },
+    };
 
     let config = match app_m.value_of("CONFIG") {
         Some(path) => Path::new(path),
@@ -107,35 +642,132 @@ pub fn main() {
     };
 
     let dry_run = app_m.is_present("DRY_RUN");
+    // clap has already checked the value against the possible values
+    let output = value_t!(app_m, "OUTPUT", OutputFormat).unwrap_or_else(|e| e.exit());
 
-    let cli = Cli { config, dry_run };
+    let interface = app_m.value_of("INTERFACE");
+
+    let cli = Cli {
+        config,
+        interface,
+        dry_run,
+        output,
+    };
 
     match cli.process_commands(&app_m) {
         Ok(()) => {}
+        Err(e) if output == OutputFormat::Json => json_err(&e),
         Err(e) => match e {
             CLIError::ClapError(e) => e.exit(),
-            other => err(&other.to_string()),
+            other => err(&other.to_string(), other.exit_code()),
+        },
+    };
+}
+
+fn json_err(e: &CLIError) -> ! {
+    let message = match e {
+        CLIError::ClapError(e) => e.message.clone(),
+        other => other.to_string(),
+    };
+    let error = ErrorOutput {
+        error: ErrorBody {
+            code: e.exit_code(),
+            kind: e.kind(),
+            message,
         },
     };
+
+    eprintln!(
+        "{}",
+        serde_json::to_string(&error).expect("error is always serializable")
+    );
+    std::process::exit(e.exit_code())
 }
 
-// Slight bodge in order to use clap's nicely formatted errors.
-fn err(msg: &str) {
-    clap::Error::with_description(msg, clap::ErrorKind::Io).exit()
+/// Prints a plain-text error the way clap formats its own (`error: <msg>`), then exits with
+/// `code`, the `CLIError::exit_code` of the error rather than clap's.
+fn err(msg: &str, code: i32) -> ! {
+    let e = clap::Error::with_description(msg, clap::ErrorKind::Io);
+    eprintln!("{}", e.message);
+    std::process::exit(code)
 }
 
 struct Cli<'a> {
     config: &'a Path,
+    /// The interface picked with `--interface`, if any
+    interface: Option<&'a str>,
     dry_run: bool,
+    output: OutputFormat,
 }
 
 impl<'a> Cli<'a> {
     fn process_commands(&self, app_m: &ArgMatches) -> CLIResult {
         match app_m.subcommand() {
             ("new", Some(sub_m)) => self.sub_new(sub_m)?,
+            ("status", Some(sub_m)) => self.sub_status(sub_m)?,
+            ("metrics", Some(sub_m)) => self.sub_metrics(sub_m)?,
+            ("daemon", Some(sub_m)) => self.sub_daemon(sub_m)?,
+            ("plan", Some(sub_m)) => self.sub_plan(sub_m)?,
+            ("doctor", Some(sub_m)) => self.sub_doctor(sub_m)?,
+            ("interface", Some(sub_m)) => match sub_m.subcommand() {
+                ("list", Some(sub_m)) => self.sub_interface_list(sub_m)?,
+                ("down", Some(sub_m)) => self.sub_interface_down(sub_m)?,
+                _ => panic!("Impossible"),
+            },
+            ("serve", Some(sub_m)) => self.sub_serve(sub_m)?,
+            ("server", Some(sub_m)) => match sub_m.subcommand() {
+                ("rotate-key", Some(sub_m)) => self.sub_server_rotate_key(sub_m)?,
+                ("settings", Some(sub_m)) => self.sub_server_settings(sub_m)?,
+                ("config", Some(sub_m)) => self.sub_server_config(sub_m)?,
+                _ => panic!("Impossible"),
+            },
+            ("token", Some(sub_m)) => match sub_m.subcommand() {
+                ("new", Some(sub_m)) => self.sub_token_new(sub_m)?,
+                ("list", Some(sub_m)) => self.sub_token_list(sub_m)?,
+                ("delete", Some(sub_m)) => self.sub_token_delete(sub_m)?,
+                _ => panic!("Impossible"),
+            },
+            ("group", Some(sub_m)) => match sub_m.subcommand() {
+                ("set", Some(sub_m)) => self.sub_group_set(sub_m)?,
+                ("render", Some(sub_m)) => self.sub_group_render(sub_m)?,
+                ("list", Some(sub_m)) => self.sub_group_list(sub_m)?,
+                ("delete", Some(sub_m)) => self.sub_group_delete(sub_m)?,
+                _ => panic!("Impossible"),
+            },
+            ("mesh", Some(sub_m)) => match sub_m.subcommand() {
+                ("new", Some(sub_m)) => self.sub_mesh_new(sub_m)?,
+                ("add", Some(sub_m)) => self.sub_mesh_add(sub_m)?,
+                ("remove", Some(sub_m)) => self.sub_mesh_remove(sub_m)?,
+                ("render", Some(sub_m)) => self.sub_mesh_render(sub_m)?,
+                ("list", Some(sub_m)) => self.sub_mesh_list(sub_m)?,
+                ("delete", Some(sub_m)) => self.sub_mesh_delete(sub_m)?,
+                _ => panic!("Impossible"),
+            },
+            ("policy", Some(sub_m)) => match sub_m.subcommand() {
+                ("allow", Some(sub_m)) => self.sub_policy_allow(sub_m)?,
+                ("list", Some(sub_m)) => self.sub_policy_list(sub_m)?,
+                ("remove", Some(sub_m)) => self.sub_policy_remove(sub_m)?,
+                ("render", Some(sub_m)) => self.sub_policy_render(sub_m)?,
+                ("check", Some(sub_m)) => self.sub_policy_check(sub_m)?,
+                _ => panic!("Impossible"),
+            },
+            ("dns", Some(sub_m)) => match sub_m.subcommand() {
+                ("set", Some(sub_m)) => self.sub_dns_set(sub_m)?,
+                ("render", Some(sub_m)) => self.sub_dns_render(sub_m)?,
+                ("off", Some(sub_m)) => self.sub_dns_off(sub_m)?,
+                _ => panic!("Impossible"),
+            },
             ("client", Some(sub_m)) => match sub_m.subcommand() {
                 ("new", Some(sub_m)) => self.sub_client_new(sub_m)?,
+                ("invite", Some(sub_m)) => self.sub_client_invite(sub_m)?,
+                ("rotate", Some(sub_m)) => self.sub_client_rotate(sub_m)?,
+                ("psk", Some(sub_m)) => self.sub_client_psk(sub_m)?,
+                ("edit", Some(sub_m)) => self.sub_client_edit(sub_m)?,
+                ("disable", Some(sub_m)) => self.sub_client_disable(sub_m)?,
+                ("enable", Some(sub_m)) => self.sub_client_enable(sub_m)?,
                 ("list", Some(sub_m)) => self.sub_client_list(sub_m)?,
+                ("show", Some(sub_m)) => self.sub_client_show(sub_m)?,
+                ("export", Some(sub_m)) => self.sub_client_export(sub_m)?,
                 ("delete", Some(sub_m)) => self.sub_client_delete(sub_m)?,
                 _ => panic!("Impossible"),
             },
@@ -150,95 +782,1321 @@ impl<'a> Cli<'a> {
         let endpoint = value_t!(sub_m, "BIND-SOCKET-ADDR", SocketAddrV4)?;
         let interface_name = value_t!(sub_m, "INTERFACE-NAME", String)?;
 
-        let manager = Manager::new(endpoint, ip_range, interface_name);
+        let manager = Manager::new(endpoint, ip_range, interface_name)?;
+        let server_output = ServerOutput::new(&manager);
+
         let lock = acquire_config_lock(self.config)?;
+        // Adding to an existing config keeps its other interfaces
+        let config = match self.config.exists() {
+            true => {
+                let mut config = load_config(self.config)?;
+                config.add_interface(manager)?;
+                config
+            }
+            false => Config::new(manager),
+        };
+        save_config(config, lock, self.config, !self.dry_run)?;
+
+        output::print(self.output, &server_output);
+        Ok(())
+    }
+
+    fn sub_status(&self, sub_m: &ArgMatches) -> CLIResult {
+        let online_threshold = online_threshold(sub_m)?;
+
+        output::print(self.output, &self.status(online_threshold)?);
+        Ok(())
+    }
+
+    fn status(&self, online_threshold: u64) -> Result<StatusOutput, CLIError> {
+        let manager = load_manager_no_lock(self.config, self.interface)?;
+        let now = utils::unix_now();
+
+        Ok(StatusOutput {
+            interface_name: manager.interface_name().clone(),
+            online_threshold_secs: online_threshold,
+            peers: manager
+                .status()?
+                .iter()
+                .map(|status| PeerStatusView::new(status, now, online_threshold))
+                .collect(),
+        })
+    }
+
+    fn sub_plan(&self, _sub_m: &ArgMatches) -> CLIResult {
+        output::print(self.output, &self.plan()?);
+        Ok(())
+    }
+
+    fn plan(&self) -> Result<PlanOutput, CLIError> {
+        let mut manager = load_manager_no_lock(self.config, self.interface)?;
+        // Commits disable expired clients first, which only shows in the plan as they're not saved
+        manager.expire_clients(utils::unix_now());
+
+        Ok(PlanOutput {
+            interface_name: manager.interface_name().clone(),
+            changes: manager.plan()?.iter().map(ChangeView::from).collect(),
+        })
+    }
+
+    fn sub_doctor(&self, _sub_m: &ArgMatches) -> CLIResult {
+        let manager = load_manager_no_lock(self.config, self.interface)?;
+
+        output::print(
+            self.output,
+            &DoctorOutput {
+                checks: doctor::run(&manager),
+            },
+        );
+        Ok(())
+    }
+
+    fn sub_interface_list(&self, _sub_m: &ArgMatches) -> CLIResult {
+        let config = load_config(self.config)?;
+
+        let interface_list_output = InterfaceListOutput {
+            interfaces: config.interfaces().iter().map(ServerOutput::new).collect(),
+        };
+
+        output::print(self.output, &interface_list_output);
+        Ok(())
+    }
+
+    fn sub_interface_down(&self, sub_m: &ArgMatches) -> CLIResult {
+        // Held so that the daemon doesn't set everything up again halfway through
+        let _lock = acquire_config_lock(self.config)?;
+        let config = load_config(self.config)?;
+        let manager = config.interface(self.interface)?;
+
+        // Forwarding is for the whole host, so it stays on while other interfaces need it
+        let others_forward = config.interfaces().iter().any(|other| {
+            other.interface_name() != manager.interface_name() && other.nat() != NatMode::Off
+        });
+        let forwarding_changes =
+            manager.plan_teardown(sub_m.is_present("KEEP_FORWARDING") || others_forward)?;
+        if !self.dry_run {
+            manager.remove_firewall()?;
+            manager.apply_forwarding(&forwarding_changes)?;
+        }
+
+        let interface_down_output = InterfaceDownOutput {
+            interface_name: manager.interface_name().clone(),
+            changes: std::iter::once(format!(
+                "remove nftables table inet {}",
+                manager.firewall_table()
+            ))
+            .chain(forwarding_changes.iter().map(|change| change.to_string()))
+            .collect(),
+        };
+
+        output::print(self.output, &interface_down_output);
+        Ok(())
+    }
+
+    fn sub_metrics(&self, sub_m: &ArgMatches) -> CLIResult {
+        let online_threshold = online_threshold(sub_m)?;
+
+        if let Some(path) = sub_m.value_of("TEXTFILE") {
+            let metrics = self.render_metrics(online_threshold)?;
+            return utils::write_atomic(Path::new(path), metrics.as_bytes())
+                .map_err(|e| CLIError::Other(format!("Failed to write '{}': {}", path, e)));
+        }
+
+        let listen = match sub_m.value_of("LISTEN") {
+            Some(_) => value_t!(sub_m, "LISTEN", SocketAddr)?,
+            None => SocketAddr::from(([127, 0, 0, 1], 9586)),
+        };
+        let listener = TcpListener::bind(listen)
+            .map_err(|e| CLIError::Other(format!("Failed to listen on {}: {}", listen, e)))?;
+        eprintln!("Serving metrics on http://{}/metrics", listen);
+
+        http::serve(listener.incoming(), |request| {
+            match (request.method.as_str(), request.path.as_str()) {
+                ("GET", "/metrics") => match self.render_metrics(online_threshold) {
+                    Ok(metrics) => http::Response {
+                        status: 200,
+                        content_type: "text/plain; version=0.0.4; charset=utf-8",
+                        body: metrics.into_bytes(),
+                    },
+                    Err(e) => http::Response::text(500, e.to_string()),
+                },
+                ("GET", _) => http::Response::text(404, "Not found, try /metrics"),
+                _ => http::Response::text(405, "Method not allowed"),
+            }
+        })
+        .map_err(|e| CLIError::Other(format!("Failed to serve metrics: {}", e)))
+    }
+
+    /// Renders metrics from the current config and interface state.
+    ///
+    /// Failing to read the interface is reported through the metrics rather than as an error.
+    fn render_metrics(&self, online_threshold: u64) -> Result<String, CLIError> {
+        let manager = load_manager_no_lock(self.config, self.interface)?;
+
+        let statuses = match manager.status() {
+            Ok(statuses) => Some(statuses),
+            Err(e) => {
+                eprintln!("Failed to read interface: {}", e);
+                None
+            }
+        };
+
+        Ok(metrics::render(
+            &manager,
+            statuses.as_deref(),
+            utils::unix_now(),
+            online_threshold,
+        ))
+    }
+
+    fn sub_daemon(&self, sub_m: &ArgMatches) -> CLIResult {
+        let interval = match sub_m.value_of("INTERVAL") {
+            Some(_) => value_t!(sub_m, "INTERVAL", u64)?,
+            None => 60,
+        };
+
+        daemon::Daemon::new(self.config, Duration::from_secs(interval), self.dry_run)
+            .run()
+            .map_err(|e| CLIError::Other(format!("Failed to start daemon: {}", e)))
+    }
+
+    fn sub_serve(&self, sub_m: &ArgMatches) -> CLIResult {
+        let api = api::Api::new(self);
+        let serve_err = |e: std::io::Error| CLIError::Other(format!("Failed to serve API: {}", e));
+
+        if let Some(path) = sub_m.value_of("SOCKET") {
+            let path = Path::new(path);
+            // A socket left behind by a previous run would make binding fail
+            if let Ok(metadata) = std::fs::symlink_metadata(path) {
+                if metadata.file_type().is_socket() {
+                    std::fs::remove_file(path).map_err(serve_err)?;
+                }
+            }
+
+            let listener = UnixListener::bind(path).map_err(|e| {
+                CLIError::Other(format!("Failed to listen on {}: {}", path.display(), e))
+            })?;
+            eprintln!("Serving API on unix socket {}", path.display());
+
+            return http::serve(listener.incoming(), |request| api.handle(request))
+                .map_err(serve_err);
+        }
+
+        let listen = match sub_m.value_of("LISTEN") {
+            Some(_) => value_t!(sub_m, "LISTEN", SocketAddr)?,
+            None => SocketAddr::from(([127, 0, 0, 1], 9587)),
+        };
+        if !listen.ip().is_loopback() {
+            return Err(CLIError::Other(format!(
+                "Refusing to serve API on non-loopback address {}",
+                listen
+            )));
+        }
+
+        let listener = TcpListener::bind(listen)
+            .map_err(|e| CLIError::Other(format!("Failed to listen on {}: {}", listen, e)))?;
+        eprintln!("Serving API on http://{}", listen);
+
+        http::serve(listener.incoming(), |request| api.handle(request)).map_err(serve_err)
+    }
+
+    fn sub_server_rotate_key(&self, sub_m: &ArgMatches) -> CLIResult {
+        let (mut manager, lock) = load_manager(self.config, self.interface)?;
+
+        let staged = sub_m.is_present("STAGE");
+        if staged {
+            let switch_at = match sub_m.value_of("AT") {
+                Some(at) => Some(utils::parse_time(at).map_err(CLIError::Other)?),
+                None => None,
+            };
+            manager.stage_server_key(switch_at)?;
+        } else if sub_m.is_present("SWITCH") {
+            if !manager.switch_server_key() {
+                return Err(CLIError::Other(
+                    "There is no staged key, stage one with --stage".into(),
+                ));
+            }
+        } else {
+            manager.rotate_server_key()?;
+        }
+
+        // Configs are rendered with the key that clients will need, staged or not
+        let public_key = match manager.staged_key() {
+            Some(staged_key) => staged_key.public_key().clone(),
+            None => manager.public_key().clone(),
+        };
+        let mut clients = manager.clients();
+        clients.sort_by_key(|client| client.name());
+
+        let mut configs = BTreeMap::new();
+        let mut reimport = Vec::new();
+        for client in clients {
+            match client.private_key() {
+                Some(private_key) => {
+                    let mut config = manager.client_config(client, Some(private_key.clone()));
+                    config.server_public_key = public_key.clone();
+                    configs.insert(client.name().clone(), config.to_wg_quick());
+                }
+                None => reimport.push(client.name().clone()),
+            }
+        }
+
+        let server_key_output = ServerKeyOutput {
+            public_key,
+            staged,
+            switch_at: manager
+                .staged_key()
+                .and_then(|staged_key| staged_key.switch_at()),
+            configs,
+            reimport,
+        };
+
+        if let Some(bundle) = sub_m.value_of("BUNDLE") {
+            write_bundle(Path::new(bundle), &server_key_output.configs)?;
+        }
+
+        // Staging doesn't change the interface, switching does
+        save_manager(manager, lock, self.config, !staged && !self.dry_run)?;
+
+        output::print(self.output, &server_key_output);
+        Ok(())
+    }
+
+    fn sub_server_settings(&self, sub_m: &ArgMatches) -> CLIResult {
+        let (mut manager, lock) = load_manager(self.config, self.interface)?;
+
+        if let Some(default_psk) = sub_m.value_of("DEFAULT_PSK") {
+            manager.set_default_psk(default_psk == "on");
+        }
+        match sub_m.value_of("EXPIRY_GRACE") {
+            Some("never") => manager.set_expiry_grace(None),
+            Some(grace) => manager
+                .set_expiry_grace(Some(utils::parse_duration(grace).map_err(CLIError::Other)?)),
+            None => {}
+        }
+
+        let egress_interface = match sub_m.value_of("EGRESS_INTERFACE") {
+            Some("none") => None,
+            Some(egress) => Some(egress.to_owned()),
+            None => manager.egress_interface().cloned(),
+        };
+        let nat = match sub_m.value_of("NAT") {
+            Some(_) => value_t!(sub_m, "NAT", NatMode)?,
+            None => manager.nat(),
+        };
+        if nat != NatMode::Off && egress_interface.is_none() {
+            return Err(CLIError::Other(
+                "NAT needs an --egress-interface to masquerade out of".into(),
+            ));
+        }
+
+        let nat_changed =
+            egress_interface.as_ref() != manager.egress_interface() || nat != manager.nat();
+        if nat_changed && !self.dry_run {
+            // An iptables rule for the old settings would otherwise be left behind
+            manager.apply_forwarding(&manager.plan_teardown(true)?)?;
+        }
+        manager.set_egress_interface(egress_interface);
+        manager.set_nat(nat);
+
+        let tuning = tuning(sub_m, manager.tuning().clone())?;
+        let link_changed =
+            tuning.mtu != manager.tuning().mtu || tuning.fwmark != manager.tuning().fwmark;
+        manager.set_tuning(tuning)?;
+
+        let settings_output = SettingsOutput {
+            default_psk: manager.default_psk(),
+            expiry_grace_secs: manager.expiry_grace(),
+            egress_interface: manager.egress_interface().cloned(),
+            nat: manager.nat(),
+            tuning: TuningView::from(manager.tuning()),
+        };
+
+        // Other settings only affect clients created later, or are applied by the daemon, but
+        // NAT, the MTU and the fwmark are set up by committing
+        let commit = (nat_changed || link_changed) && !self.dry_run;
+        save_manager(manager, lock, self.config, commit)?;
+
+        output::print(self.output, &settings_output);
+        Ok(())
+    }
+
+    fn sub_server_config(&self, _sub_m: &ArgMatches) -> CLIResult {
+        let manager = load_manager_no_lock(self.config, self.interface)?;
+
+        let server_config_output = ServerConfigOutput {
+            config: manager.server_config().to_wg_quick(),
+        };
+
+        output::print(self.output, &server_config_output);
+        Ok(())
+    }
+
+    fn sub_token_new(&self, sub_m: &ArgMatches) -> CLIResult {
+        let (mut manager, lock) = load_manager(self.config, self.interface)?;
+
+        let name = value_t!(sub_m, "NAME", String)?;
+        let role = value_t!(sub_m, "ROLE", Role)?;
+        let group = sub_m.value_of("GROUP").map(String::from);
+        match (role, &group) {
+            (Role::Issuer, None) => {
+                return Err(CLIError::Other("an issuer token needs a --group".into()))
+            }
+            (Role::Viewer, Some(_)) | (Role::Admin, Some(_)) => {
+                return Err(CLIError::Other("only issuer tokens have a --group".into()))
+            }
+            _ => {}
+        }
+
+        let secret = manager.new_api_token(name.clone(), role, group, utils::unix_now())?;
+        let new_token_output = NewTokenOutput {
+            token: TokenView::from(
+                manager
+                    .api_tokens()
+                    .into_iter()
+                    .find(|token| *token.name() == name)
+                    .expect("token was just created"),
+            ),
+            secret,
+        };
+
+        // Tokens don't affect the interface, so there's nothing to commit
+        save_manager(manager, lock, self.config, false)?;
+
+        output::print(self.output, &new_token_output);
+        Ok(())
+    }
+
+    fn sub_token_list(&self, _sub_m: &ArgMatches) -> CLIResult {
+        let manager = load_manager_no_lock(self.config, self.interface)?;
+
+        let mut tokens = manager.api_tokens();
+        tokens.sort_by_key(|token| token.name());
+
+        let token_list_output = TokenListOutput {
+            tokens: tokens.into_iter().map(TokenView::from).collect(),
+        };
+
+        output::print(self.output, &token_list_output);
+        Ok(())
+    }
+
+    fn sub_token_delete(&self, sub_m: &ArgMatches) -> CLIResult {
+        let (mut manager, lock) = load_manager(self.config, self.interface)?;
+
+        let name = value_t!(sub_m, "NAME", String)?;
+        let token = manager.delete_api_token(&name)?;
+        let deleted_token_output = DeletedTokenOutput {
+            deleted: TokenView::from(&token),
+        };
+
+        save_manager(manager, lock, self.config, false)?;
+
+        output::print(self.output, &deleted_token_output);
+        Ok(())
+    }
+
+    fn sub_group_set(&self, sub_m: &ArgMatches) -> CLIResult {
+        let (mut manager, lock) = load_manager(self.config, self.interface)?;
+
+        let name = value_t!(sub_m, "NAME", String)?;
+        let group = Group::new(
+            optional_values(sub_m, "ALLOWED_IP")?,
+            optional_values(sub_m, "DNS")?,
+            tuning(sub_m, Tuning::default())?,
+            optional_values(sub_m, "ROUTE_ALL_EXCEPT")?,
+        );
+        manager.set_group(name.clone(), group)?;
+        let group_output = group_output(&manager, &name)?;
+
+        if let Some(bundle) = sub_m.value_of("BUNDLE") {
+            write_bundle(Path::new(bundle), &group_output.configs)?;
+        }
+
+        // Groups only affect client configs, not the interface
+        save_manager(manager, lock, self.config, false)?;
+
+        output::print(self.output, &group_output);
+        Ok(())
+    }
+
+    fn sub_group_render(&self, sub_m: &ArgMatches) -> CLIResult {
+        let manager = load_manager_no_lock(self.config, self.interface)?;
+
+        let name = value_t!(sub_m, "NAME", String)?;
+        let group_output = group_output(&manager, &name)?;
+
+        if let Some(bundle) = sub_m.value_of("BUNDLE") {
+            write_bundle(Path::new(bundle), &group_output.configs)?;
+        }
+
+        output::print(self.output, &group_output);
+        Ok(())
+    }
+
+    fn sub_group_list(&self, _sub_m: &ArgMatches) -> CLIResult {
+        let manager = load_manager_no_lock(self.config, self.interface)?;
+
+        let group_list_output = GroupListOutput {
+            groups: manager
+                .groups()
+                .into_iter()
+                .map(|(name, group)| GroupView::new(name, group, &manager.group_members(name)))
+                .collect(),
+        };
+
+        output::print(self.output, &group_list_output);
+        Ok(())
+    }
+
+    fn sub_group_delete(&self, sub_m: &ArgMatches) -> CLIResult {
+        let (mut manager, lock) = load_manager(self.config, self.interface)?;
+
+        let name = value_t!(sub_m, "NAME", String)?;
+        let group = manager.delete_group(&name)?;
+        let deleted_group_output = DeletedGroupOutput {
+            deleted: GroupView::new(&name, &group, &manager.group_members(&name)),
+        };
+
+        save_manager(manager, lock, self.config, false)?;
+
+        output::print(self.output, &deleted_group_output);
+        Ok(())
+    }
+
+    fn sub_mesh_new(&self, sub_m: &ArgMatches) -> CLIResult {
+        let (mut manager, lock) = load_manager(self.config, self.interface)?;
+
+        let name = value_t!(sub_m, "NAME", String)?;
+        let persistent_keepalive = match sub_m.value_of("KEEPALIVE") {
+            Some(_) => Some(value_t!(sub_m, "KEEPALIVE", u16)?).filter(|keepalive| *keepalive != 0),
+            None => None,
+        };
+        let mesh = Mesh::new(value_t!(sub_m, "IP-RANGE", Ipv4Net)?, persistent_keepalive);
+        manager.new_mesh(name.clone(), mesh)?;
+        let mesh_output = mesh_output(&manager, &name)?;
+
+        // Meshes aren't on the interface
+        save_manager(manager, lock, self.config, false)?;
+
+        output::print(self.output, &mesh_output);
+        Ok(())
+    }
+
+    fn sub_mesh_add(&self, sub_m: &ArgMatches) -> CLIResult {
+        let (mut manager, lock) = load_manager(self.config, self.interface)?;
+
+        let mesh = value_t!(sub_m, "MESH", String)?;
+        manager.add_mesh_node(
+            &mesh,
+            value_t!(sub_m, "NAME", String)?,
+            value_t!(sub_m, "IP", Ipv4Addr)?,
+            value_t!(sub_m, "ENDPOINT", String)?,
+            sub_m.value_of("PUBLIC_KEY").map(String::from),
+        )?;
+        let mesh_output = mesh_output(&manager, &mesh)?;
+
+        if let Some(bundle) = sub_m.value_of("BUNDLE") {
+            write_bundle(Path::new(bundle), &mesh_output.configs)?;
+        }
+
+        save_manager(manager, lock, self.config, false)?;
+
+        output::print(self.output, &mesh_output);
+        Ok(())
+    }
+
+    fn sub_mesh_remove(&self, sub_m: &ArgMatches) -> CLIResult {
+        let (mut manager, lock) = load_manager(self.config, self.interface)?;
+
+        let mesh = value_t!(sub_m, "MESH", String)?;
+        manager.remove_mesh_node(&mesh, &value_t!(sub_m, "NAME", String)?)?;
+        let mesh_output = mesh_output(&manager, &mesh)?;
+
+        if let Some(bundle) = sub_m.value_of("BUNDLE") {
+            write_bundle(Path::new(bundle), &mesh_output.configs)?;
+        }
+
+        save_manager(manager, lock, self.config, false)?;
+
+        output::print(self.output, &mesh_output);
+        Ok(())
+    }
+
+    fn sub_mesh_render(&self, sub_m: &ArgMatches) -> CLIResult {
+        let manager = load_manager_no_lock(self.config, self.interface)?;
+
+        let mesh_output = mesh_output(&manager, &value_t!(sub_m, "MESH", String)?)?;
+
+        if let Some(bundle) = sub_m.value_of("BUNDLE") {
+            write_bundle(Path::new(bundle), &mesh_output.configs)?;
+        }
+
+        output::print(self.output, &mesh_output);
+        Ok(())
+    }
+
+    fn sub_mesh_list(&self, _sub_m: &ArgMatches) -> CLIResult {
+        let manager = load_manager_no_lock(self.config, self.interface)?;
+
+        let mesh_list_output = MeshListOutput {
+            meshes: manager
+                .meshes()
+                .into_iter()
+                .map(|(name, mesh)| MeshView::new(name, mesh))
+                .collect(),
+        };
+
+        output::print(self.output, &mesh_list_output);
+        Ok(())
+    }
+
+    fn sub_mesh_delete(&self, sub_m: &ArgMatches) -> CLIResult {
+        let (mut manager, lock) = load_manager(self.config, self.interface)?;
+
+        let name = value_t!(sub_m, "NAME", String)?;
+        let mesh = manager.delete_mesh(&name)?;
+        let deleted_mesh_output = DeletedMeshOutput {
+            deleted: MeshView::new(&name, &mesh),
+        };
+
+        save_manager(manager, lock, self.config, false)?;
+
+        output::print(self.output, &deleted_mesh_output);
+        Ok(())
+    }
+
+    fn sub_policy_allow(&self, sub_m: &ArgMatches) -> CLIResult {
+        let (mut manager, lock) = load_manager(self.config, self.interface)?;
+
+        let rule = Rule::new(
+            value_t!(sub_m, "FROM", String)?,
+            value_t!(sub_m, "TO", Target)?,
+            optional_values(sub_m, "PORT")?,
+        );
+        manager.add_rule(rule)?;
+        let rule_output = RuleOutput {
+            rule: RuleView::new(manager.rules().len(), manager.rules().last().unwrap()),
+        };
+
         save_manager(manager, lock, self.config, !self.dry_run)?;
+
+        output::print(self.output, &rule_output);
+        Ok(())
+    }
+
+    fn sub_policy_list(&self, _sub_m: &ArgMatches) -> CLIResult {
+        let manager = load_manager_no_lock(self.config, self.interface)?;
+
+        let policy_list_output = PolicyListOutput {
+            rules: manager
+                .rules()
+                .iter()
+                .enumerate()
+                .map(|(index, rule)| RuleView::new(index + 1, rule))
+                .collect(),
+        };
+
+        output::print(self.output, &policy_list_output);
+        Ok(())
+    }
+
+    fn sub_policy_remove(&self, sub_m: &ArgMatches) -> CLIResult {
+        let (mut manager, lock) = load_manager(self.config, self.interface)?;
+
+        let number = value_t!(sub_m, "NUMBER", usize)?;
+        let rule = manager.remove_rule(number)?;
+        let deleted_rule_output = DeletedRuleOutput {
+            deleted: RuleView::new(number, &rule),
+        };
+
+        save_manager(manager, lock, self.config, !self.dry_run)?;
+
+        output::print(self.output, &deleted_rule_output);
+        Ok(())
+    }
+
+    fn sub_policy_render(&self, _sub_m: &ArgMatches) -> CLIResult {
+        let manager = load_manager_no_lock(self.config, self.interface)?;
+
+        output::print(
+            self.output,
+            &PolicyRenderOutput {
+                ruleset: manager.firewall(),
+            },
+        );
+        Ok(())
+    }
+
+    fn sub_policy_check(&self, sub_m: &ArgMatches) -> CLIResult {
+        let manager = load_manager_no_lock(self.config, self.interface)?;
+
+        let address = |value: &str| match manager.client(value) {
+            Ok(client) => Ok(client.ip()),
+            Err(e) => value.parse::<Ipv4Addr>().map_err(|_| CLIError::from(e)),
+        };
+        let source = address(sub_m.value_of("FROM").unwrap())?;
+        let destination = address(sub_m.value_of("TO").unwrap())?;
+        let port = match sub_m.value_of("PORT") {
+            Some(_) => Some(value_t!(sub_m, "PORT", Ports)?),
+            None => None,
+        };
+        let single_port = match port {
+            Some(ports) => Some(ports.port().ok_or_else(|| {
+                CLIError::Other(format!("'{}' is a range, check a single port", ports))
+            })?),
+            None => None,
+        };
+
+        let verdict = manager.policy().check(source, destination, single_port);
+        let rule_view = |index: usize| RuleView::new(index + 1, &manager.rules()[index]);
+        let policy_check_output = PolicyCheckOutput {
+            source,
+            destination,
+            port: port.map(|port| port.to_string()),
+            allowed: !matches!(verdict, Verdict::Denied(_)),
+            managed: verdict != Verdict::Unmanaged,
+            rule: match verdict {
+                Verdict::Allowed(index) => Some(rule_view(index)),
+                _ => None,
+            },
+            allowed_ports: match &verdict {
+                Verdict::Denied(ports) => ports.iter().map(|ports| ports.to_string()).collect(),
+                _ => Vec::new(),
+            },
+        };
+
+        output::print(self.output, &policy_check_output);
+        Ok(())
+    }
+
+    fn sub_dns_set(&self, sub_m: &ArgMatches) -> CLIResult {
+        let (mut manager, lock) = load_manager(self.config, self.interface)?;
+
+        let dns = Dns::new(
+            sub_m.value_of("DOMAIN").unwrap(),
+            value_t!(sub_m, "SERVER_IP", Ipv4Addr)?,
+            value_t!(sub_m, "FORMAT", DnsFormat)?,
+            value_t!(sub_m, "FILE", PathBuf)?,
+        )
+        .map_err(ManagerError::from)?;
+        manager.set_dns(Some(dns))?;
+        let dns_output = DnsOutput::new(&manager);
+
+        // Committing writes the records
+        save_manager(manager, lock, self.config, !self.dry_run)?;
+
+        output::print(self.output, &dns_output);
+        Ok(())
+    }
+
+    fn sub_dns_render(&self, _sub_m: &ArgMatches) -> CLIResult {
+        let manager = load_manager_no_lock(self.config, self.interface)?;
+
+        output::print(
+            self.output,
+            &DnsRenderOutput {
+                files: manager
+                    .dns_files()
+                    .into_iter()
+                    .map(DnsFileView::from)
+                    .collect(),
+                skipped: manager.dns_skipped(),
+            },
+        );
+        Ok(())
+    }
+
+    fn sub_dns_off(&self, _sub_m: &ArgMatches) -> CLIResult {
+        let (mut manager, lock) = load_manager(self.config, self.interface)?;
+
+        manager.set_dns(None)?;
+        let dns_output = DnsOutput::new(&manager);
+
+        save_manager(manager, lock, self.config, false)?;
+
+        output::print(self.output, &dns_output);
         Ok(())
     }
 
     fn sub_client_new(&self, sub_m: &ArgMatches) -> CLIResult {
-        let (mut manager, lock) = load_manager(self.config)?;
+        let name = value_t!(sub_m, "NAME", String)?;
+        let ip = value_t!(sub_m, "IP", Ipv4Addr)?;
+        let options = ClientOptions {
+            groups: values_t!(sub_m, "GROUP", String).unwrap_or_default(),
+            public_key: sub_m.value_of("PUBLIC_KEY").map(String::from),
+            preshared_key: match (sub_m.is_present("PSK"), sub_m.is_present("NO_PSK")) {
+                (true, _) => Some(true),
+                (_, true) => Some(false),
+                _ => None,
+            },
+            store_key: sub_m.is_present("STORE_KEY"),
+            expires_at: expires_at(sub_m, utils::unix_now())?,
+            owner: sub_m.value_of("OWNER").map(String::from),
+            description: sub_m.value_of("DESCRIPTION").map(String::from),
+            tags: tags(sub_m)?,
+            subnets: optional_values(sub_m, "SUBNET")?,
+            route_all_except: optional_values(sub_m, "ROUTE_ALL_EXCEPT")?,
+            tuning: tuning(sub_m, Tuning::default())?,
+            created_by: utils::invoking_user(),
+        };
+
+        output::print(self.output, &self.client_new(name, ip, options)?);
+        Ok(())
+    }
+
+    fn client_new(
+        &self,
+        name: String,
+        ip: Ipv4Addr,
+        options: ClientOptions,
+    ) -> Result<NewClientOutput, CLIError> {
+        let (mut manager, lock) = load_manager(self.config, self.interface)?;
+
+        let (_, private_key) = manager.new_client(name.clone(), ip, options)?;
+        let new_client_output = new_client_output(&manager, &name, private_key)?;
+
+        save_manager(manager, lock, self.config, !self.dry_run)?;
+        Ok(new_client_output)
+    }
+
+    fn sub_client_invite(&self, sub_m: &ArgMatches) -> CLIResult {
+        let (mut manager, lock) = load_manager(self.config, self.interface)?;
 
         let name = value_t!(sub_m, "NAME", String)?;
         let ip = value_t!(sub_m, "IP", Ipv4Addr)?;
-        let endpoint = manager.endpoint();
+        let groups = values_t!(sub_m, "GROUP", String).unwrap_or_default();
+        let ttl = utils::parse_duration(sub_m.value_of("TTL").unwrap_or_default())
+            .map_err(CLIError::Other)?;
+
+        let token = manager.new_invite(name.clone(), ip, groups, utils::unix_now(), ttl)?;
+        let new_invite_output = NewInviteOutput {
+            invite: InviteView::from(
+                manager
+                    .invites()
+                    .into_iter()
+                    .find(|invite| *invite.name() == name)
+                    .expect("invite was just created"),
+            ),
+            token,
+        };
 
-        let (client, privkey) = manager.new_client(name, ip)?;
-        let pubkey = client.public_key();
+        // The client only reaches the interface once the invite is redeemed
+        save_manager(manager, lock, self.config, false)?;
+
+        output::print(self.output, &new_invite_output);
+        Ok(())
+    }
 
-        let config_string = create_client_config(ip, pubkey, &privkey, endpoint);
+    /// Redeems an invite, creating its client with `public_key`, or a generated keypair
+    fn invite_redeem(
+        &self,
+        token: &str,
+        public_key: Option<String>,
+    ) -> Result<NewClientOutput, CLIError> {
+        let (mut manager, lock) = load_manager(self.config, self.interface)?;
 
-        println!("Here is auto-generated config:\n{}", config_string);
+        let (client, private_key) = manager.redeem_invite(token, public_key, utils::unix_now())?;
+        let name = client.name().clone();
+        let new_client_output = new_client_output(&manager, &name, private_key)?;
 
         save_manager(manager, lock, self.config, !self.dry_run)?;
+        Ok(new_client_output)
+    }
+
+    fn sub_client_rotate(&self, sub_m: &ArgMatches) -> CLIResult {
+        let (mut manager, lock) = load_manager(self.config, self.interface)?;
+        let now = utils::unix_now();
+
+        if !sub_m.is_present("ALL") {
+            let name = value_t!(sub_m, "NAME", String)?;
+            let public_key = sub_m.value_of("PUBLIC_KEY").map(String::from);
+
+            let (_, private_key) = manager.rotate_client_key(&name, public_key, now)?;
+            let new_client_output = new_client_output(&manager, &name, private_key)?;
+
+            save_manager(manager, lock, self.config, !self.dry_run)?;
+
+            output::print(self.output, &new_client_output);
+            return Ok(());
+        }
+
+        let older_than = match sub_m.value_of("OLDER_THAN") {
+            Some(older_than) => Some(utils::parse_duration(older_than).map_err(CLIError::Other)?),
+            None => None,
+        };
+        let names = manager.clients_to_rotate(older_than, now);
+
+        let mut rotated = Vec::new();
+        for name in names {
+            let (_, private_key) = manager.rotate_client_key(&name, None, now)?;
+            rotated.push(new_client_output(&manager, &name, private_key)?);
+        }
+
+        save_manager(manager, lock, self.config, !self.dry_run)?;
+
+        output::print(self.output, &RotatedClientsOutput { rotated });
         Ok(())
     }
 
-    fn sub_client_list(&self, _sub_m: &ArgMatches) -> CLIResult {
-        let manager = load_manager_no_lock(self.config)?;
+    fn sub_client_psk(&self, sub_m: &ArgMatches) -> CLIResult {
+        let (mut manager, lock) = load_manager(self.config, self.interface)?;
+
+        let name = value_t!(sub_m, "NAME", String)?;
+        let client = manager.rotate_preshared_key(&name, utils::unix_now())?;
+        let preshared_key_output = PresharedKeyOutput {
+            client: ClientView::from(client),
+            preshared_key: client
+                .preshared_key()
+                .cloned()
+                .expect("preshared key was just set"),
+        };
 
-        manager.clients();
+        save_manager(manager, lock, self.config, !self.dry_run)?;
 
-        let mut table: Vec<Vec<&str>> = Vec::new();
+        output::print(self.output, &preshared_key_output);
+        Ok(())
+    }
+
+    fn sub_client_edit(&self, sub_m: &ArgMatches) -> CLIResult {
+        let (mut manager, lock) = load_manager(self.config, self.interface)?;
+
+        let name = value_t!(sub_m, "NAME", String)?;
+        let now = utils::unix_now();
+        let edit = ClientEdit {
+            owner: sub_m.value_of("OWNER").map(String::from),
+            description: sub_m.value_of("DESCRIPTION").map(String::from),
+            tags: tags(sub_m)?,
+            remove_tags: values_t!(sub_m, "UNTAG", String).unwrap_or_default(),
+            add_groups: values_t!(sub_m, "ADD_GROUP", String).unwrap_or_default(),
+            remove_groups: values_t!(sub_m, "REMOVE_GROUP", String).unwrap_or_default(),
+            expires_at: if sub_m.is_present("NEVER_EXPIRES") {
+                Some(None)
+            } else {
+                expires_at(sub_m, now)?.map(Some)
+            },
+            add_subnets: optional_values(sub_m, "ADD_SUBNET")?,
+            remove_subnets: optional_values(sub_m, "REMOVE_SUBNET")?,
+            route_all_except: match sub_m.is_present("ROUTE_ALL_EXCEPT") {
+                true => Some(values_t!(sub_m, "ROUTE_ALL_EXCEPT", IpNet)?),
+                false if sub_m.is_present("NO_ROUTE_ALL_EXCEPT") => Some(Vec::new()),
+                false => None,
+            },
+            tuning: match TUNING_ARGS.iter().any(|arg| sub_m.is_present(arg)) {
+                true => Some(tuning(sub_m, manager.client(&name)?.tuning().clone())?),
+                false => None,
+            },
+        };
+        // Subnets are routed on the interface, and groups decide who the policy lets through
+        let routing_changed = !edit.add_subnets.is_empty()
+            || !edit.remove_subnets.is_empty()
+            || !edit.add_groups.is_empty()
+            || !edit.remove_groups.is_empty();
+        let client = manager.edit_client(&name, edit, now)?;
+        let client_output = ClientOutput {
+            client: ClientView::from(client),
+        };
+
+        // The expiry date only affects the interface through the daemon. Groups and subnets also
+        // affect client configs, which have to be given out again.
+        save_manager(manager, lock, self.config, routing_changed && !self.dry_run)?;
+
+        output::print(self.output, &client_output);
+        Ok(())
+    }
+
+    fn sub_client_disable(&self, sub_m: &ArgMatches) -> CLIResult {
+        let (mut manager, lock) = load_manager(self.config, self.interface)?;
+
+        let name = value_t!(sub_m, "NAME", String)?;
+        let reason = sub_m.value_of("REASON").map(String::from);
+        let client = manager.disable_client(&name, reason, utils::unix_now())?;
+        let client_output = ClientOutput {
+            client: ClientView::from(client),
+        };
 
-        table.push(vec!["Name", "Pubkey"]);
-        for client in manager.clients() {
-            table.push(vec![client.name(), client.public_key()]);
+        save_manager(manager, lock, self.config, !self.dry_run)?;
+
+        output::print(self.output, &client_output);
+        Ok(())
+    }
+
+    fn sub_client_enable(&self, sub_m: &ArgMatches) -> CLIResult {
+        let (mut manager, lock) = load_manager(self.config, self.interface)?;
+
+        let name = value_t!(sub_m, "NAME", String)?;
+        let now = utils::unix_now();
+        let client = manager.enable_client(&name, expires_at(sub_m, now)?, now)?;
+        let client_output = ClientOutput {
+            client: ClientView::from(client),
+        };
+
+        save_manager(manager, lock, self.config, !self.dry_run)?;
+
+        output::print(self.output, &client_output);
+        Ok(())
+    }
+
+    fn sub_client_list(&self, sub_m: &ArgMatches) -> CLIResult {
+        if sub_m.is_present("LIVE") {
+            return self.sub_status(sub_m);
         }
 
-        let lines = cli_table(table);
+        let duration = |arg| -> Result<Option<u64>, CLIError> {
+            sub_m
+                .value_of(arg)
+                .map(utils::parse_duration)
+                .transpose()
+                .map_err(CLIError::Other)
+        };
+        let time = |arg| -> Result<Option<u64>, CLIError> {
+            sub_m
+                .value_of(arg)
+                .map(utils::parse_time)
+                .transpose()
+                .map_err(CLIError::Other)
+        };
+        let filter = ClientFilter {
+            ip: match sub_m.value_of("IP") {
+                Some(_) => Some(value_t!(sub_m, "IP", Ipv4Addr)?),
+                None => None,
+            },
+            owner: sub_m.value_of("OWNER").map(String::from),
+            description: sub_m.value_of("DESCRIPTION").map(String::from),
+            tags: values_t!(sub_m, "TAG", String)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|tag| match tag.split_once('=') {
+                    Some((key, value)) => (key.to_owned(), Some(value.to_owned())),
+                    None => (tag, None),
+                })
+                .collect(),
+            created_by: sub_m.value_of("CREATED_BY").map(String::from),
+            created_after: time("CREATED_AFTER")?,
+            created_before: time("CREATED_BEFORE")?,
+            updated_after: time("UPDATED_AFTER")?,
+            updated_before: time("UPDATED_BEFORE")?,
+            expiring: duration("EXPIRING")?,
+        };
+
+        output::print(self.output, &self.client_list(&filter)?);
+        Ok(())
+    }
+
+    /// Lists the clients that match `filter`
+    fn client_list(&self, filter: &ClientFilter) -> Result<ClientListOutput, CLIError> {
+        let manager = load_manager_no_lock(self.config, self.interface)?;
+
+        let now = utils::unix_now();
+        let mut clients: Vec<&Client> = manager
+            .clients()
+            .into_iter()
+            .filter(|client| filter.matches(client, now))
+            .collect();
+        clients.sort_by_key(|client| client.name());
+
+        Ok(ClientListOutput {
+            clients: clients.into_iter().map(ClientView::from).collect(),
+        })
+    }
+
+    fn sub_client_show(&self, sub_m: &ArgMatches) -> CLIResult {
+        let name = value_t!(sub_m, "NAME", String)?;
+
+        output::print(self.output, &self.client_show(&name)?);
+        Ok(())
+    }
+
+    fn client_show(&self, name: &str) -> Result<ClientOutput, CLIError> {
+        let manager = load_manager_no_lock(self.config, self.interface)?;
+
+        Ok(ClientOutput {
+            client: ClientView::from(manager.client(name)?),
+        })
+    }
+
+    fn sub_client_export(&self, sub_m: &ArgMatches) -> CLIResult {
+        let manager = load_manager_no_lock(self.config, self.interface)?;
 
-        for line in lines {
-            println!("{}", line);
+        let name = value_t!(sub_m, "NAME", String)?;
+        let format = value_t!(sub_m, "FORMAT", ExportFormat)?;
+        let interface = sub_m
+            .value_of("IFNAME")
+            .unwrap_or_else(|| manager.interface_name());
+        let client = manager.client(&name)?;
+        let files: BTreeMap<String, String> = manager
+            .client_config(client, client.private_key().cloned())
+            .export(format, interface)
+            .into_iter()
+            .map(|file| (file.name, file.contents))
+            .collect();
+
+        if let Some(bundle) = sub_m.value_of("BUNDLE") {
+            write_files(Path::new(bundle), &files)?;
         }
 
+        let export_output = ExportOutput {
+            name,
+            format,
+            files,
+        };
+
+        output::print(self.output, &export_output);
         Ok(())
     }
 
     fn sub_client_delete(&self, sub_m: &ArgMatches) -> CLIResult {
-        todo!();
+        let name = value_t!(sub_m, "NAME", String)?;
+
+        output::print(self.output, &self.client_delete(&name)?);
+        Ok(())
+    }
+
+    fn client_delete(&self, name: &str) -> Result<DeletedClientOutput, CLIError> {
+        let (mut manager, lock) = load_manager(self.config, self.interface)?;
+
+        let client = manager.delete_client(name)?;
+        let deleted_client_output = DeletedClientOutput {
+            deleted: ClientView::from(&client),
+        };
+
+        save_manager(manager, lock, self.config, !self.dry_run)?;
+        Ok(deleted_client_output)
+    }
+}
+
+/// Every value of an argument that may be left out, failing if any of them doesn't parse
+fn optional_values<T>(sub_m: &ArgMatches, name: &str) -> Result<Vec<T>, CLIError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    match sub_m.is_present(name) {
+        true => Ok(values_t!(sub_m, name, T)?),
+        false => Ok(Vec::new()),
     }
 }
 
-/// Loads manager from a file, providing a lock for it.
-fn load_manager(config_path: &Path) -> Result<(Manager, Lock), CLIError> {
+/// Expiry date given with `--expires` or `--ttl`, if any
+fn expires_at(sub_m: &ArgMatches, now: u64) -> Result<Option<u64>, CLIError> {
+    match (sub_m.value_of("EXPIRES"), sub_m.value_of("TTL")) {
+        (Some(expires), _) => utils::parse_time(expires)
+            .map(Some)
+            .map_err(CLIError::Other),
+        (_, Some(ttl)) => utils::parse_duration(ttl)
+            .map(|ttl| Some(now.saturating_add(ttl)))
+            .map_err(CLIError::Other),
+        _ => Ok(None),
+    }
+}
+
+/// A group along with the configs of all its members, templates where the private key isn't stored
+fn group_output(manager: &Manager, name: &str) -> Result<GroupOutput, CLIError> {
+    let group = manager.group(name)?;
+    let members = manager.group_members(name);
+
+    let configs = members
+        .iter()
+        .map(|client| {
+            let config = manager.client_config(client, client.private_key().cloned());
+            (client.name().clone(), config.to_wg_quick())
+        })
+        .collect();
+
+    Ok(GroupOutput {
+        group: GroupView::new(name, group, &members),
+        configs,
+    })
+}
+
+fn mesh_output(manager: &Manager, name: &str) -> Result<MeshOutput, CLIError> {
+    let mesh = manager.mesh(name)?;
+
+    Ok(MeshOutput {
+        mesh: MeshView::new(name, mesh),
+        configs: mesh.configs(),
+    })
+}
+
+const TUNING_ARGS: [&str; 6] = [
+    "MTU",
+    "KEEPALIVE",
+    "FWMARK",
+    "TABLE",
+    "POST_UP",
+    "POST_DOWN",
+];
+
+/// `tuning` with the options given to `server settings`, `group set` or `client new`/`edit`
+/// applied, where `default` unsets an option and an empty hook removes the hooks
+fn tuning(sub_m: &ArgMatches, mut tuning: Tuning) -> Result<Tuning, CLIError> {
+    match sub_m.value_of("MTU") {
+        Some("default") => tuning.mtu = None,
+        Some(_) => tuning.mtu = Some(value_t!(sub_m, "MTU", u16)?),
+        None => {}
+    }
+    match sub_m.value_of("KEEPALIVE") {
+        Some("default") => tuning.persistent_keepalive = None,
+        Some("off") => tuning.persistent_keepalive = Some(0),
+        Some(_) => tuning.persistent_keepalive = Some(value_t!(sub_m, "KEEPALIVE", u16)?),
+        None => {}
+    }
+    match sub_m.value_of("FWMARK") {
+        Some("default") => tuning.fwmark = None,
+        Some(fwmark) => {
+            tuning.fwmark = Some(tuning::parse_fwmark(fwmark).map_err(CLIError::Other)?)
+        }
+        None => {}
+    }
+    match sub_m.value_of("TABLE") {
+        Some("default") => tuning.table = None,
+        Some(_) => tuning.table = Some(value_t!(sub_m, "TABLE", Table)?),
+        None => {}
+    }
+    let hooks = |arg| -> Option<Vec<String>> {
+        sub_m.values_of(arg).map(|hooks| {
+            hooks
+                .filter(|hook| !hook.is_empty())
+                .map(String::from)
+                .collect()
+        })
+    };
+    if let Some(post_up) = hooks("POST_UP") {
+        tuning.post_up = post_up;
+    }
+    if let Some(post_down) = hooks("POST_DOWN") {
+        tuning.post_down = post_down;
+    }
+
+    Ok(tuning)
+}
+
+/// Tags given with `--tag KEY=VALUE`
+fn tags(sub_m: &ArgMatches) -> Result<BTreeMap<String, String>, CLIError> {
+    values_t!(sub_m, "TAG", String)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|tag| match tag.split_once('=') {
+            Some((key, value)) if !key.is_empty() => Ok((key.to_owned(), value.to_owned())),
+            _ => Err(CLIError::Other(format!(
+                "invalid tag '{}', expected KEY=VALUE",
+                tag
+            ))),
+        })
+        .collect()
+}
+
+/// Writes each client's config to `<directory>/<client>.conf`, readable only by the owner
+fn write_bundle(directory: &Path, configs: &BTreeMap<String, String>) -> CLIResult {
+    let files = configs
+        .iter()
+        .map(|(name, config)| (format!("{}.conf", name), config.clone()))
+        .collect();
+    write_files(directory, &files)
+}
+
+/// Writes files to `<directory>/<name>`, readable only by the owner since they hold keys
+fn write_files(directory: &Path, files: &BTreeMap<String, String>) -> CLIResult {
+    let write_err = |path: &Path, e: std::io::Error| {
+        CLIError::Other(format!("Failed to write '{}': {}", path.display(), e))
+    };
+
+    // Names come from clients (possibly over the API), and must not lead out of the directory
+    if let Some(name) = files
+        .keys()
+        .find(|name| name.is_empty() || name.contains(['/', '\\']) || name.contains(".."))
+    {
+        return Err(CLIError::Other(format!(
+            "refusing to write '{}', which isn't a plain file name",
+            name
+        )));
+    }
+
+    std::fs::create_dir_all(directory).map_err(|e| write_err(directory, e))?;
+    for (name, contents) in files {
+        let path = directory.join(name);
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&path)
+            .and_then(|mut file| file.write_all(contents.as_bytes()))
+            .map_err(|e| write_err(&path, e))?;
+    }
+
+    Ok(())
+}
+
+/// Output for a client that was just created, rendering its config (or a template for it)
+fn new_client_output(
+    manager: &Manager,
+    name: &str,
+    private_key: Option<String>,
+) -> Result<NewClientOutput, CLIError> {
+    let client = manager.client(name)?;
+    let config = manager.client_config(client, private_key.clone());
+
+    Ok(NewClientOutput {
+        client: ClientView::from(client),
+        private_key,
+        config: config.to_wg_quick(),
+    })
+}
+
+/// Parses `--online-threshold`, falling back to the default
+fn online_threshold(sub_m: &ArgMatches) -> Result<u64, CLIError> {
+    match sub_m.value_of("ONLINE_THRESHOLD") {
+        Some(_) => Ok(value_t!(sub_m, "ONLINE_THRESHOLD", u64)?),
+        None => Ok(status::DEFAULT_ONLINE_THRESHOLD),
+    }
+}
+
+/// Loads the manager of an interface (the only one if `interface` is `None`) from a file,
+/// providing a lock for it.
+fn load_manager(config_path: &Path, interface: Option<&str>) -> Result<(Manager, Lock), CLIError> {
     let lock = acquire_config_lock(config_path)?;
-    let manager = load_manager_no_lock(config_path)?;
+    let manager = load_config(config_path)?.take_interface(interface)?;
 
     Ok((manager, lock))
 }
 
-/// Loads manager from file, without a lock. Useful for read-only operations.
-fn load_manager_no_lock(config_path: &Path) -> Result<Manager, CLIError> {
-    Manager::from_config(config_path).map_err(|e| CLIError::FailedToLoadConfig(e))
+/// Loads the manager of an interface from file, without a lock. Useful for read-only operations.
+fn load_manager_no_lock(config_path: &Path, interface: Option<&str>) -> Result<Manager, CLIError> {
+    Ok(load_config(config_path)?.take_interface(interface)?)
 }
 
-/// Commits manager back to file, consuming a lock.
+fn load_config(config_path: &Path) -> Result<Config, CLIError> {
+    Config::from_config(config_path).map_err(CLIError::FailedToLoadConfig)
+}
+
+/// Puts manager back into the config file, consuming a lock.
+///
+/// Committing commits every interface, not only this one.
+fn save_manager(manager: Manager, lock: Lock, config_path: &Path, commit: bool) -> CLIResult {
+    // The lock has been held since the manager was loaded, so the other interfaces are unchanged
+    let mut config = load_config(config_path)?;
+    config.set_interface(manager);
+
+    save_config(config, lock, config_path, commit)
+}
+
+/// Commits config back to file, consuming a lock.
 // Note that `_lock` is dropped at the end of the scope, and so released
-fn save_manager(manager: Manager, _lock: Lock, config: &Path, commit: bool) -> CLIResult {
+fn save_config(mut config: Config, _lock: Lock, config_path: &Path, commit: bool) -> CLIResult {
     if commit {
-        manager.commit();
+        config.commit(utils::unix_now())?;
     }
 
-    manager
-        .save_config(config)
+    config
+        .save_config(config_path)
         // TODO: sort out some way to save yourself from this failure maybe????
-        .map_err(|e| CLIError::FailedToSaveConfig(e))
+        .map_err(CLIError::FailedToSaveConfig)
 }
 
 fn acquire_config_lock(config_path: &Path) -> Result<Lock, CLIError> {
     let lock_path = utils::lock_path(config_path);
-    let lock = Lock::acquire(lock_path).map_err(|e| CLIError::LockAcquisitionError(e))?;
+    let lock = Lock::acquire(lock_path).map_err(CLIError::LockAcquisitionError)?;
 
     Ok(lock)
 }
-
-// TODO: create a wg-quick style config
-fn create_client_config(
-    ip: Ipv4Addr,
-    pubkey: &String,
-    privkey: &String,
-    endpoint: SocketAddrV4,
-) -> String {
-    format!("placeholder({}, {}, {}, {})", ip, pubkey, privkey, endpoint)
-}
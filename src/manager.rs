@@ -1,21 +1,89 @@
-use ipnet::Ipv4Net;
+use ipnet::{IpNet, Ipv4Net};
 use serde::{Deserialize, Serialize};
 
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
+    fmt,
     io::Write,
-    net::{Ipv4Addr, SocketAddrV4},
-    path::Path,
+    net::{IpAddr, Ipv4Addr, SocketAddrV4},
+    path::{Path, PathBuf},
 };
 
-use crate::utils::{deserialize_ipv4net, serialize_ipv4net};
-use crate::wg::Wg;
+use crate::auth::{ApiToken, Role};
+use crate::client_config::ClientConfig;
+use crate::dns::{self, Dns, DnsError, DnsFile};
+use crate::forwarding::{self, ForwardingChange, ForwardingError, Iptables, NatMode, Sysctl};
+use crate::group::Group;
+use crate::invite::{Invite, InviteError};
+use crate::mesh::{Mesh, MeshError, Node};
+use crate::nft::{self, Nft, NftError};
+use crate::policy::{Policy, Rule, Target};
+use crate::server_config::{ServerConfig, ServerPeer};
+use crate::split_tunnel;
+use crate::status::{self, PeerStatus};
+use crate::tuning::{Link, LinkError, Tuning, TuningError};
+use crate::utils::{self, deserialize_ipv4net, serialize_ipv4net};
+use crate::wg::{self, Wg, WgError};
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug)]
 pub enum ManagerError {
     IOError(std::io::Error),
     SerializationError(serde_json::Error),
+    WgError(WgError),
     ClientNameExistsError(String),
+    ClientNotFoundError(String),
+    InvalidPublicKeyError(String),
+    PublicKeyExistsError(String),
+    ApiTokenExistsError(String),
+    ApiTokenNotFoundError(String),
+    GroupNotFoundError(String),
+    InviteError(InviteError),
+    NftError(NftError),
+    ForwardingError(ForwardingError),
+    /// Rules are numbered from 1, as `policy list` shows them
+    PolicyRuleNotFoundError(usize),
+    /// A client's subnet overlaps `other`, which describes what it overlaps
+    SubnetOverlapError {
+        subnet: Ipv4Net,
+        other: String,
+    },
+    MeshExistsError(String),
+    MeshNotFoundError(String),
+    MeshError(MeshError),
+    DnsError(DnsError),
+    TuningError(TuningError),
+    LinkError(LinkError),
+    InterfaceExistsError(String),
+    InterfaceNotFoundError(String),
+    /// The config has several interfaces, named here, and none was picked
+    InterfaceRequiredError(Vec<String>),
+    /// A new interface's address pool overlaps that of `interface`
+    IpRangeOverlapError {
+        ip_range: Ipv4Net,
+        interface: String,
+    },
+    ListenPortTakenError {
+        port: u16,
+        interface: String,
+    },
+    /// A new interface's name maps to the same nftables table as that of `interface`
+    FirewallTableTakenError {
+        table: String,
+        interface: String,
+    },
+    /// A name that can't be used in file names, see `utils::is_valid_name`
+    InvalidNameError(String),
+    /// A client's ip isn't a host address in the VPN's range
+    IpOutOfRangeError {
+        ip: Ipv4Addr,
+        ip_range: Ipv4Net,
+    },
+    /// A client's ip is already used, `by` describing what by
+    IpTakenError {
+        ip: Ipv4Addr,
+        by: String,
+    },
 }
 
 impl From<std::io::Error> for ManagerError {
@@ -30,14 +98,270 @@ impl From<serde_json::Error> for ManagerError {
     }
 }
 
-impl ToString for ManagerError {
-    fn to_string(&self) -> String {
-        match &self {
-            &ManagerError::IOError(e) => e.to_string(),
-            &ManagerError::SerializationError(e) => e.to_string(),
-            &ManagerError::ClientNameExistsError(name) => {
-                format!("client with name '{}' already exists", name)
+impl From<WgError> for ManagerError {
+    fn from(e: WgError) -> Self {
+        ManagerError::WgError(e)
+    }
+}
+
+impl From<NftError> for ManagerError {
+    fn from(e: NftError) -> Self {
+        ManagerError::NftError(e)
+    }
+}
+
+impl From<ForwardingError> for ManagerError {
+    fn from(e: ForwardingError) -> Self {
+        ManagerError::ForwardingError(e)
+    }
+}
+
+impl From<MeshError> for ManagerError {
+    fn from(e: MeshError) -> Self {
+        ManagerError::MeshError(e)
+    }
+}
+
+impl From<DnsError> for ManagerError {
+    fn from(e: DnsError) -> Self {
+        ManagerError::DnsError(e)
+    }
+}
+
+impl From<TuningError> for ManagerError {
+    fn from(e: TuningError) -> Self {
+        ManagerError::TuningError(e)
+    }
+}
+
+impl From<LinkError> for ManagerError {
+    fn from(e: LinkError) -> Self {
+        ManagerError::LinkError(e)
+    }
+}
+
+impl From<InviteError> for ManagerError {
+    fn from(e: InviteError) -> Self {
+        ManagerError::InviteError(e)
+    }
+}
+
+impl fmt::Display for ManagerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ManagerError::IOError(e) => write!(f, "{}", e),
+            ManagerError::SerializationError(e) => write!(f, "{}", e),
+            ManagerError::WgError(e) => write!(f, "{}", e),
+            ManagerError::ClientNameExistsError(name) => {
+                write!(f, "client with name '{}' already exists", name)
+            }
+            ManagerError::ClientNotFoundError(name) => {
+                write!(f, "client with name '{}' does not exist", name)
+            }
+            ManagerError::InvalidPublicKeyError(key) => {
+                write!(f, "'{}' is not a valid public key", key)
+            }
+            ManagerError::PublicKeyExistsError(name) => {
+                write!(f, "public key already belongs to client '{}'", name)
+            }
+            ManagerError::ApiTokenExistsError(name) => {
+                write!(f, "api token with name '{}' already exists", name)
+            }
+            ManagerError::ApiTokenNotFoundError(name) => {
+                write!(f, "api token with name '{}' does not exist", name)
+            }
+            ManagerError::GroupNotFoundError(name) => {
+                write!(f, "group with name '{}' does not exist", name)
             }
+            ManagerError::InviteError(e) => write!(f, "{}", e),
+            ManagerError::NftError(e) => write!(f, "{}", e),
+            ManagerError::ForwardingError(e) => write!(f, "{}", e),
+            ManagerError::PolicyRuleNotFoundError(number) => {
+                write!(f, "policy rule {} does not exist", number)
+            }
+            ManagerError::SubnetOverlapError { subnet, other } => {
+                write!(f, "subnet {} overlaps {}", subnet, other)
+            }
+            ManagerError::MeshExistsError(name) => {
+                write!(f, "mesh with name '{}' already exists", name)
+            }
+            ManagerError::MeshNotFoundError(name) => {
+                write!(f, "mesh with name '{}' does not exist", name)
+            }
+            ManagerError::MeshError(e) => write!(f, "{}", e),
+            ManagerError::DnsError(e) => write!(f, "{}", e),
+            ManagerError::TuningError(e) => write!(f, "{}", e),
+            ManagerError::LinkError(e) => write!(f, "{}", e),
+            ManagerError::InterfaceExistsError(name) => {
+                write!(f, "interface with name '{}' already exists", name)
+            }
+            ManagerError::InterfaceNotFoundError(name) => {
+                write!(f, "interface with name '{}' does not exist", name)
+            }
+            ManagerError::InterfaceRequiredError(names) => write!(
+                f,
+                "config has several interfaces ({}), pick one with --interface",
+                names.join(", ")
+            ),
+            ManagerError::IpRangeOverlapError {
+                ip_range,
+                interface,
+            } => write!(
+                f,
+                "ip range {} overlaps that of interface '{}'",
+                ip_range, interface
+            ),
+            ManagerError::ListenPortTakenError { port, interface } => {
+                write!(
+                    f,
+                    "port {} is already used by interface '{}'",
+                    port, interface
+                )
+            }
+            ManagerError::FirewallTableTakenError { table, interface } => write!(
+                f,
+                "nftables table {} is already used by interface '{}'",
+                table, interface
+            ),
+            ManagerError::InvalidNameError(name) => write!(
+                f,
+                "invalid name '{}', expected letters, digits, '.', '_' or '-', not starting with '.'",
+                name
+            ),
+            ManagerError::IpOutOfRangeError { ip, ip_range } => {
+                write!(f, "{} is not a host address in the VPN's range {}", ip, ip_range)
+            }
+            ManagerError::IpTakenError { ip, by } => write!(f, "{} is already used by {}", ip, by),
+        }
+    }
+}
+
+/// What happened to a client whose expiry date has passed
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expiry {
+    Disabled(String),
+    Deleted(String),
+}
+
+impl fmt::Display for Expiry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expiry::Disabled(name) => write!(f, "disable expired client '{}'", name),
+            Expiry::Deleted(name) => write!(f, "delete expired client '{}'", name),
+        }
+    }
+}
+
+/// A single change to the WireGuard interface, needed to bring it in line with the config
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    SetPrivateKey,
+    SetListenPort(u16),
+    /// Sets the interface's firewall mark, `0` turning it off
+    SetFwMark(u32),
+    SetMtu(u16),
+    AddPeer {
+        name: String,
+        public_key: String,
+        allowed_ips: Vec<IpNet>,
+    },
+    UpdatePeer {
+        name: String,
+        public_key: String,
+        allowed_ips: Vec<IpNet>,
+    },
+    /// Swaps the key of a peer in one step, i.e. after a client's key has been rotated
+    ReplacePeer {
+        name: String,
+        old_public_key: String,
+        public_key: String,
+        allowed_ips: Vec<IpNet>,
+        preshared_key: Option<String>,
+    },
+    /// Sets the preshared key of a peer, or removes it if `preshared_key` is `None`
+    SetPresharedKey {
+        name: String,
+        public_key: String,
+        preshared_key: Option<String>,
+    },
+    /// Removes the peer of a client that is disabled
+    RemoveDisabledPeer {
+        name: String,
+        public_key: String,
+    },
+    RemovePeer {
+        public_key: String,
+    },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |allowed_ips: &Vec<IpNet>| {
+            allowed_ips
+                .iter()
+                .map(|ip| ip.to_string())
+                .collect::<Vec<String>>()
+                .join(",")
+        };
+
+        match self {
+            Change::SetPrivateKey => write!(f, "set private key"),
+            Change::SetListenPort(port) => write!(f, "set listen port to {}", port),
+            Change::SetFwMark(0) => write!(f, "turn fwmark off"),
+            Change::SetFwMark(fwmark) => write!(f, "set fwmark to 0x{:x}", fwmark),
+            Change::SetMtu(mtu) => write!(f, "set MTU to {}", mtu),
+            Change::AddPeer {
+                name,
+                public_key,
+                allowed_ips,
+            } => write!(
+                f,
+                "add peer '{}' ({}) with allowed ips {}",
+                name,
+                public_key,
+                join(allowed_ips)
+            ),
+            Change::UpdatePeer {
+                name,
+                public_key,
+                allowed_ips,
+            } => write!(
+                f,
+                "set allowed ips of peer '{}' ({}) to {}",
+                name,
+                public_key,
+                join(allowed_ips)
+            ),
+            Change::ReplacePeer {
+                name,
+                old_public_key,
+                public_key,
+                ..
+            } => write!(
+                f,
+                "replace key of peer '{}' ({}) with {}",
+                name, old_public_key, public_key
+            ),
+            Change::SetPresharedKey {
+                name,
+                public_key,
+                preshared_key: Some(_),
+            } => write!(f, "set preshared key of peer '{}' ({})", name, public_key),
+            Change::SetPresharedKey {
+                name,
+                public_key,
+                preshared_key: None,
+            } => write!(
+                f,
+                "remove preshared key of peer '{}' ({})",
+                name, public_key
+            ),
+            Change::RemoveDisabledPeer { name, public_key } => write!(
+                f,
+                "remove peer of disabled client '{}' ({})",
+                name, public_key
+            ),
+            Change::RemovePeer { public_key } => write!(f, "remove unknown peer {}", public_key),
         }
     }
 }
@@ -57,15 +381,87 @@ pub struct Manager {
 
     clients: HashMap<String, Client>,
     wg: Wg,
+
+    #[serde(default)]
+    api_tokens: HashMap<String, ApiToken>,
+    /// Keyed by the name of the client they will create
+    #[serde(default)]
+    invites: HashMap<String, Invite>,
+    #[serde(default)]
+    groups: BTreeMap<String, Group>,
+    /// Full-mesh networks, whose configs are rendered but which aren't on the interface
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    meshes: BTreeMap<String, Mesh>,
+    /// Which groups may reach what, enforced with nftables once there are any rules
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    policy: Vec<Rule>,
+    #[serde(default)]
+    nft: Nft,
+
+    /// Interface that traffic beyond the VPN leaves through, for NAT
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    egress_interface: Option<String>,
+    /// Commits enable forwarding, and masquerade out of `egress_interface`, unless this is off
+    #[serde(default)]
+    nat: NatMode,
+    #[serde(default)]
+    sysctl: Sysctl,
+    #[serde(default)]
+    iptables: Iptables,
+
+    /// DNS records for clients, written by commits, `None` if there aren't any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    dns: Option<Dns>,
+
+    /// The interface's own tuning, the MTU and keepalive also being defaults for clients
+    #[serde(flatten)]
+    tuning: Tuning,
+    #[serde(default)]
+    link: Link,
+
+    /// Whether new clients get a preshared key unless told otherwise
+    #[serde(default)]
+    default_psk: bool,
+
+    /// How long expired clients stay disabled before being deleted, `None` meaning forever
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expiry_grace: Option<u64>,
+
+    /// Server key that has been staged, to replace `private_key` later
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    staged_key: Option<StagedKey>,
+}
+
+/// A new server key, prepared ahead of switching to it so that client configs can be re-issued
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StagedKey {
+    private_key: String,
+    public_key: String,
+    /// Unix timestamp after which the daemon switches to the key, if any
+    switch_at: Option<u64>,
+}
+
+impl StagedKey {
+    pub fn public_key(&self) -> &String {
+        &self.public_key
+    }
+
+    pub fn switch_at(&self) -> Option<u64> {
+        self.switch_at
+    }
 }
 
 impl Manager {
-    pub fn new(endpoint: SocketAddrV4, ip_range: Ipv4Net, interface_name: String) -> Self {
+    pub fn new(
+        endpoint: SocketAddrV4,
+        ip_range: Ipv4Net,
+        interface_name: String,
+    ) -> Result<Self, ManagerError> {
         let wg = Wg::new("wg".into());
-        let private_key = wg.genkey();
-        let public_key = wg.pubkey(&private_key);
+        let private_key = wg.genkey()?;
+        let public_key = wg.pubkey(&private_key)?;
 
-        Manager {
+        Ok(Manager {
             interface_name,
             private_key,
             public_key,
@@ -73,118 +469,2490 @@ impl Manager {
             ip_range,
             clients: HashMap::new(),
             wg,
-        }
+            api_tokens: HashMap::new(),
+            invites: HashMap::new(),
+            groups: BTreeMap::new(),
+            meshes: BTreeMap::new(),
+            policy: Vec::new(),
+            nft: Nft::default(),
+            egress_interface: None,
+            nat: NatMode::Off,
+            sysctl: Sysctl::default(),
+            iptables: Iptables::default(),
+            dns: None,
+            tuning: Tuning::default(),
+            link: Link::default(),
+            default_psk: false,
+            expiry_grace: None,
+            staged_key: None,
+        })
     }
 
-    /// Produces `Manager` struct from the contents of a config file
+    /// Commits changes to WireGuard interface, returning the changes that were made.
     ///
-    /// NOTE: `from_config` and `save_config` do not handle file locking
-    pub fn from_config(path: &Path) -> Result<Self, ManagerError> {
-        let data = std::fs::read(path)?;
-        let manager: Manager = serde_json::from_slice(&data)?;
-        Ok(manager)
+    /// Clients that have expired by `now` are disabled (or deleted) first, as the daemon does, and
+    /// the firewall, forwarding, NAT and DNS records are brought in line too.
+    pub fn commit(&mut self, now: u64) -> Result<Vec<Change>, ManagerError> {
+        self.expire_clients(now);
+        let changes = self.plan()?;
+        self.apply(&changes)?;
+        self.apply_firewall()?;
+        self.apply_forwarding(&self.plan_forwarding()?)?;
+        self.write_dns()?;
+        Ok(changes)
+    }
+
+    /// The nftables ruleset for the config, if there is anything to enforce or masquerade
+    pub fn firewall(&self) -> Option<String> {
+        let mut body = String::new();
+        if !self.policy.is_empty() {
+            body.push_str(&self.policy().render());
+        }
+        if let (NatMode::Nftables, Some(egress)) = (self.nat, &self.egress_interface) {
+            body.push_str(&forwarding::masquerade_chain(self.ip_range, egress));
+        }
+
+        if body.is_empty() {
+            None
+        } else {
+            Some(nft::replace_table(&self.firewall_table(), &body))
+        }
+    }
+
+    /// Loads the ruleset from `firewall` (atomically replacing the table), or removes the table
+    /// if there is no ruleset
+    pub fn apply_firewall(&self) -> Result<(), ManagerError> {
+        match self.firewall() {
+            Some(ruleset) => Ok(self.nft.apply(&ruleset)?),
+            None => self.remove_firewall(),
+        }
+    }
+
+    /// The nftables table that `apply_firewall` loads, in the `inet` family
+    pub fn firewall_table(&self) -> String {
+        nft::table(&self.interface_name)
     }
 
-    /// Commits changes to WireGuard interface
-    pub fn commit(&self) {
+    /// Removes the table that `apply_firewall` loads, if it exists
+    pub fn remove_firewall(&self) -> Result<(), ManagerError> {
+        match self.nft.delete_table("inet", &self.firewall_table()) {
+            // Without nftables there can't be a table to remove
+            Ok(()) | Err(NftError::NotInstalled { .. }) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Works out which changes are needed for forwarding and iptables NAT, when NAT is on
+    pub fn plan_forwarding(&self) -> Result<Vec<ForwardingChange>, ManagerError> {
+        let mut changes = Vec::new();
+        if self.nat == NatMode::Off {
+            return Ok(changes);
+        }
+
+        if !self.forwarding_enabled()? {
+            changes.push(ForwardingChange::EnableForwarding);
+        }
+        if let (NatMode::Iptables, Some(egress)) = (self.nat, &self.egress_interface) {
+            if !self.iptables.has_masquerade(self.ip_range, egress)? {
+                changes.push(ForwardingChange::AddMasquerade {
+                    source: self.ip_range,
+                    egress: egress.clone(),
+                });
+            }
+        }
+
+        Ok(changes)
+    }
+
+    /// Works out which changes undo `plan_forwarding`, leaving forwarding on if `keep_forwarding`
+    pub fn plan_teardown(
+        &self,
+        keep_forwarding: bool,
+    ) -> Result<Vec<ForwardingChange>, ManagerError> {
+        let mut changes = Vec::new();
+
+        if let (NatMode::Iptables, Some(egress)) = (self.nat, &self.egress_interface) {
+            if self.iptables.has_masquerade(self.ip_range, egress)? {
+                changes.push(ForwardingChange::RemoveMasquerade {
+                    source: self.ip_range,
+                    egress: egress.clone(),
+                });
+            }
+        }
+        if !keep_forwarding && self.forwarding_enabled()? {
+            changes.push(ForwardingChange::DisableForwarding);
+        }
+
+        Ok(changes)
+    }
+
+    /// Applies changes from `plan_forwarding` or `plan_teardown`
+    pub fn apply_forwarding(&self, changes: &[ForwardingChange]) -> Result<(), ManagerError> {
+        for change in changes {
+            match change {
+                ForwardingChange::EnableForwarding => {
+                    self.sysctl.set(forwarding::IP_FORWARD, "1")?
+                }
+                ForwardingChange::DisableForwarding => {
+                    self.sysctl.set(forwarding::IP_FORWARD, "0")?
+                }
+                ForwardingChange::AddMasquerade { source, egress } => {
+                    self.iptables.add_masquerade(*source, egress)?
+                }
+                ForwardingChange::RemoveMasquerade { source, egress } => {
+                    self.iptables.remove_masquerade(*source, egress)?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn forwarding_enabled(&self) -> Result<bool, ManagerError> {
+        Ok(self.sysctl.get(forwarding::IP_FORWARD)? == "1")
+    }
+
+    /// Enabled clients that route all IPv4 traffic through the server, or all but some ranges,
+    /// sorted by name
+    pub fn full_tunnel_clients(&self) -> Vec<&Client> {
+        let mut clients: Vec<&Client> = self
+            .clients
+            .values()
+            .filter(|client| client.is_enabled())
+            .filter(|client| {
+                !self.client_settings(client).route_all_except().is_empty()
+                    || self.client_config(client, None).allowed_ips.iter().any(
+                        |allowed_ip| matches!(allowed_ip, IpNet::V4(net) if net.prefix_len() == 0),
+                    )
+            })
+            .collect();
+        clients.sort_by_key(|client| client.name());
+        clients
+    }
+
+    /// Works out which changes are needed to bring the WireGuard interface in line with the config
+    pub fn plan(&self) -> Result<Vec<Change>, ManagerError> {
         // Check values for server
-        let private_key = self.wg.show_private_key(&self.interface_name);
-        let listen_port = self.wg.show_listen_port(&self.interface_name);
+        let private_key = self.wg.show_private_key(&self.interface_name)?;
+        let listen_port = self.wg.show_listen_port(&self.interface_name)?;
 
         // Check values for peers (clients)
-        // TODO: Ipv4Net parsing may fail, since there may be comma-separate ip ranges, but I haven't checked this yet
-        let mut peers_allowed_ips: HashMap<String, Ipv4Net> = self
-            .wg
-            .show_allowed_ips(&self.interface_name)
-            .into_iter()
-            .map(|mut row| (row.pop().unwrap(), row.pop().unwrap().parse().unwrap()))
-            .collect();
+        let peers_allowed_ips = self.wg.show_allowed_ips(&self.interface_name)?;
+        let peers_preshared_keys = self.wg.show_preshared_keys(&self.interface_name)?;
+
+        let mut changes = self.diff(
+            private_key,
+            listen_port,
+            peers_allowed_ips,
+            peers_preshared_keys,
+        );
+
+        // The firewall mark and MTU are left alone unless the config sets them
+        if let Some(fwmark) = self.tuning.fwmark {
+            if self.wg.show_fwmark(&self.interface_name)? != fwmark {
+                changes.push(Change::SetFwMark(fwmark));
+            }
+        }
+        if let Some(mtu) = self.tuning.mtu {
+            if self.link.mtu(&self.interface_name)? != mtu {
+                changes.push(Change::SetMtu(mtu));
+            }
+        }
+
+        Ok(changes)
+    }
+
+    /// Applies changes from `plan` to the WireGuard interface
+    pub fn apply(&self, changes: &[Change]) -> Result<(), ManagerError> {
+        for change in changes {
+            match change {
+                Change::SetPrivateKey => {
+                    let mut temp_file = tempfile::NamedTempFile::new()?;
+                    writeln!(temp_file, "{}", self.private_key)?;
+                    self.wg
+                        .set_private_key(&self.interface_name, temp_file.path())?;
+                    temp_file.close()?;
+                }
+                Change::SetListenPort(port) => {
+                    self.wg.set_listen_port(&self.interface_name, *port)?;
+                }
+                Change::SetFwMark(fwmark) => {
+                    self.wg.set_fwmark(&self.interface_name, *fwmark)?;
+                }
+                Change::SetMtu(mtu) => {
+                    self.link.set_mtu(&self.interface_name, *mtu)?;
+                }
+                Change::AddPeer {
+                    public_key,
+                    allowed_ips,
+                    ..
+                }
+                | Change::UpdatePeer {
+                    public_key,
+                    allowed_ips,
+                    ..
+                } => {
+                    self.wg
+                        .set_peer_allowed_ips(&self.interface_name, public_key, allowed_ips)?;
+                }
+                Change::ReplacePeer {
+                    old_public_key,
+                    public_key,
+                    allowed_ips,
+                    preshared_key,
+                    ..
+                } => {
+                    let temp_file = match preshared_key {
+                        Some(preshared_key) => {
+                            let mut temp_file = tempfile::NamedTempFile::new()?;
+                            writeln!(temp_file, "{}", preshared_key)?;
+                            Some(temp_file)
+                        }
+                        None => None,
+                    };
+                    self.wg.replace_peer(
+                        &self.interface_name,
+                        old_public_key,
+                        public_key,
+                        allowed_ips,
+                        temp_file.as_ref().map(|temp_file| temp_file.path()),
+                    )?;
+                    if let Some(temp_file) = temp_file {
+                        temp_file.close()?;
+                    }
+                }
+                Change::SetPresharedKey {
+                    public_key,
+                    preshared_key,
+                    ..
+                } => match preshared_key {
+                    Some(preshared_key) => {
+                        let mut temp_file = tempfile::NamedTempFile::new()?;
+                        writeln!(temp_file, "{}", preshared_key)?;
+                        self.wg.set_peer_preshared_key(
+                            &self.interface_name,
+                            public_key,
+                            temp_file.path(),
+                        )?;
+                        temp_file.close()?;
+                    }
+                    None => self.wg.set_peer_preshared_key(
+                        &self.interface_name,
+                        public_key,
+                        Path::new("/dev/null"),
+                    )?,
+                },
+                Change::RemoveDisabledPeer { public_key, .. }
+                | Change::RemovePeer { public_key } => {
+                    self.wg.remove_peer(&self.interface_name, public_key)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Compares the config against the current state of the interface
+    fn diff(
+        &self,
+        private_key: Option<String>,
+        listen_port: u16,
+        peers_allowed_ips: Vec<(String, Vec<IpNet>)>,
+        peers_preshared_keys: Vec<(String, Option<String>)>,
+    ) -> Vec<Change> {
+        let mut changes = Vec::new();
+        let mut peers_allowed_ips: HashMap<String, Vec<IpNet>> =
+            peers_allowed_ips.into_iter().collect();
+        let peers_preshared_keys: HashMap<String, Option<String>> =
+            peers_preshared_keys.into_iter().collect();
 
-        // Check for differences and apply
         // Note that checking for public key is not needed, as this is derived from private key
-        if private_key != self.private_key {
-            // TODO: error handling
-            let mut temp_file = tempfile::NamedTempFile::new().unwrap();
-            writeln!(temp_file, "{}", self.private_key).unwrap();
-            self.wg
-                .set_private_key(&self.interface_name, temp_file.path());
-            temp_file.close().unwrap();
+        if private_key.as_ref() != Some(&self.private_key) {
+            changes.push(Change::SetPrivateKey);
         }
         if listen_port != self.endpoint().port() {
-            self.wg
-                .set_listen_port(&self.interface_name, self.endpoint().port())
+            changes.push(Change::SetListenPort(self.endpoint().port()));
         }
         // TODO: check/update listen ip????
 
-        for (_name, client) in &self.clients {
-            let pubkey = client.public_key();
-            if let Some(current_allowed_ips) = peers_allowed_ips.remove(pubkey) {
-                todo!("compare allowed ips, if not the same, update them");
-            } else {
-                todo!("add client")
+        let mut clients = self.clients();
+        clients.sort_by_key(|client| client.name());
+
+        let client_keys: Vec<&String> = clients.iter().map(|client| client.public_key()).collect();
+
+        let (clients, disabled): (Vec<&Client>, Vec<&Client>) =
+            clients.into_iter().partition(|client| client.is_enabled());
+        for client in clients {
+            let name = client.name().clone();
+            let public_key = client.public_key().clone();
+            let allowed_ips = client.allowed_ips();
+
+            // A missing peer with the same allowed ips as an unknown one is a client whose key was
+            // rotated, so the peer's key is swapped rather than a new peer briefly sharing them
+            if !peers_allowed_ips.contains_key(&public_key) {
+                let mut replaced: Vec<&String> = peers_allowed_ips
+                    .iter()
+                    .filter(|(key, _)| !client_keys.contains(key))
+                    .filter(|(_, current_allowed_ips)| {
+                        let mut current_allowed_ips = (*current_allowed_ips).clone();
+                        current_allowed_ips.sort();
+                        current_allowed_ips == allowed_ips
+                    })
+                    .map(|(key, _)| key)
+                    .collect();
+                replaced.sort();
+
+                if let Some(old_public_key) = replaced.first().map(|key| (*key).clone()) {
+                    peers_allowed_ips.remove(&old_public_key);
+                    changes.push(Change::ReplacePeer {
+                        name,
+                        old_public_key,
+                        public_key,
+                        allowed_ips,
+                        preshared_key: client.preshared_key().cloned(),
+                    });
+                    continue;
+                }
+            }
+
+            match peers_allowed_ips.remove(&public_key) {
+                Some(mut current_allowed_ips) => {
+                    current_allowed_ips.sort();
+                    if current_allowed_ips != allowed_ips {
+                        changes.push(Change::UpdatePeer {
+                            name: name.clone(),
+                            public_key: public_key.clone(),
+                            allowed_ips,
+                        });
+                    }
+                }
+                None => changes.push(Change::AddPeer {
+                    name: name.clone(),
+                    public_key: public_key.clone(),
+                    allowed_ips,
+                }),
+            }
+
+            // A peer that was just added has no preshared key yet
+            let current_preshared_key = peers_preshared_keys.get(&public_key).cloned().flatten();
+            if current_preshared_key.as_ref() != client.preshared_key() {
+                changes.push(Change::SetPresharedKey {
+                    name,
+                    public_key,
+                    preshared_key: client.preshared_key().cloned(),
+                });
             }
         }
 
-        for (public_key, _allowed_ips) in peers_allowed_ips {
-            todo!("remove public key")
+        // Disabled clients keep everything but their peer
+        for client in disabled {
+            if peers_allowed_ips.remove(client.public_key()).is_some() {
+                changes.push(Change::RemoveDisabledPeer {
+                    name: client.name().clone(),
+                    public_key: client.public_key().clone(),
+                });
+            }
         }
 
-        todo!();
-    }
+        // Anything left over is a peer that isn't a configured client
+        let mut unknown: Vec<String> = peers_allowed_ips.into_keys().collect();
+        unknown.sort();
+        changes.extend(
+            unknown
+                .into_iter()
+                .map(|public_key| Change::RemovePeer { public_key }),
+        );
 
-    /// Save `Manager` struct to the contents of a config file
-    ///
-    /// NOTE: `from_config` and `save_config` do not handle file locking
-    pub fn save_config(self, path: &Path) -> Result<(), ManagerError> {
-        let data = serde_json::to_vec(&self)?;
-        std::fs::write(path, data)?;
-        Ok(())
+        changes
     }
 
-    // Creates new client and returns private key
+    /// Creates a new client, returning its private key if the keypair was generated
     pub fn new_client(
         &mut self,
         name: String,
         ip: Ipv4Addr,
-    ) -> Result<(&Client, String), ManagerError> {
-        if self.clients.contains_key(&name) {
-            Err(ManagerError::ClientNameExistsError(name))
-        } else {
-            let private_key = self.wg.genkey();
-            let public_key = self.wg.pubkey(&private_key);
+        options: ClientOptions,
+    ) -> Result<(&Client, Option<String>), ManagerError> {
+        let now = utils::unix_now();
+        if !utils::is_valid_name(&name) {
+            return Err(ManagerError::InvalidNameError(name));
+        }
+        if self.name_taken(&name, now) {
+            return Err(ManagerError::ClientNameExistsError(name));
+        }
+        self.check_ip(&name, ip, now)?;
+        check_names(&options.groups)?;
 
-            let client = Client {
-                name: name.clone(),
-                public_key,
-                ip,
+        let subnets: Vec<Ipv4Net> = options.subnets.iter().map(Ipv4Net::trunc).collect();
+        self.check_subnets(&name, &subnets)?;
+        options.tuning.validate()?;
+        let (public_key, private_key) = self.keypair(options.public_key)?;
+
+        let mut client = Client::new(name.clone(), public_key, ip);
+        client.groups = options.groups;
+        client.subnets = subnets;
+        client.route_all_except = options.route_all_except;
+        client.tuning = options.tuning;
+        client.key_created_at = Some(now);
+        client.expires_at = options.expires_at;
+        client.owner = options.owner;
+        client.description = options.description;
+        client.tags = options.tags;
+        client.created_at = Some(now);
+        client.created_by = options.created_by;
+        if options.store_key {
+            client.private_key = private_key.clone();
+        }
+        if options.preshared_key.unwrap_or(self.default_psk) {
+            client.preshared_key = Some(self.wg.genpsk()?);
+        }
+
+        self.clients.insert(name.clone(), client);
+        Ok((self.clients.get(&name).unwrap(), private_key))
+    }
+
+    /// Checks a public key supplied for a new client, or generates a keypair if there isn't one
+    fn keypair(
+        &self,
+        public_key: Option<String>,
+    ) -> Result<(String, Option<String>), ManagerError> {
+        match public_key {
+            Some(public_key) => {
+                if !wg::is_valid_key(&public_key) {
+                    return Err(ManagerError::InvalidPublicKeyError(public_key));
+                }
+                if let Some(client) = self
+                    .clients
+                    .values()
+                    .find(|client| *client.public_key() == public_key)
+                {
+                    return Err(ManagerError::PublicKeyExistsError(client.name().clone()));
+                }
+                Ok((public_key, None))
+            }
+            None => {
+                let private_key = self.wg.genkey()?;
+                Ok((self.wg.pubkey(&private_key)?, Some(private_key)))
+            }
+        }
+    }
+
+    /// Checks that a client's subnets overlap neither the VPN's range, each other, nor the subnets
+    /// of any other client
+    fn check_subnets(&self, name: &str, subnets: &[Ipv4Net]) -> Result<(), ManagerError> {
+        let overlaps = |a: &Ipv4Net, b: &Ipv4Net| a.contains(b) || b.contains(a);
+
+        for (index, subnet) in subnets.iter().enumerate() {
+            let overlap = |other: String| ManagerError::SubnetOverlapError {
+                subnet: *subnet,
+                other,
             };
 
-            self.clients.insert(name.clone(), client);
-            Ok((self.clients.get(&name).unwrap(), private_key))
+            if overlaps(subnet, &self.ip_range) {
+                return Err(overlap(format!("the VPN's range {}", self.ip_range)));
+            }
+            if let Some(other) = subnets[..index]
+                .iter()
+                .find(|other| overlaps(subnet, other))
+            {
+                return Err(overlap(other.to_string()));
+            }
+            for client in self.clients.values().filter(|client| client.name() != name) {
+                if let Some(other) = client
+                    .subnets()
+                    .iter()
+                    .find(|other| overlaps(subnet, other))
+                {
+                    return Err(overlap(format!("{} of client '{}'", other, client.name())));
+                }
+            }
         }
+
+        Ok(())
     }
 
-    pub fn clients(&self) -> Vec<&Client> {
-        self.clients.values().into_iter().collect()
+    /// The server's own address in the VPN's range, by convention the range's first host
+    pub fn server_ip(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::from(self.ip_range.network()) + 1)
     }
 
-    pub fn endpoint(&self) -> SocketAddrV4 {
-        self.endpoint
+    /// Checks that `ip` can go to the client (or invite) `name`: a host address in the VPN's
+    /// range that neither the server, another client nor another pending invite uses. WireGuard
+    /// moves an address to whichever peer got it last, so a duplicate would take over the other
+    /// client's traffic.
+    fn check_ip(&self, name: &str, ip: Ipv4Addr, now: u64) -> Result<(), ManagerError> {
+        let range = self.ip_range;
+        // /31 and /32 ranges have no network or broadcast address to leave out
+        let reserved =
+            range.prefix_len() < 31 && (ip == range.network() || ip == range.broadcast());
+        if !range.contains(&ip) || reserved {
+            return Err(ManagerError::IpOutOfRangeError {
+                ip,
+                ip_range: range,
+            });
+        }
+
+        let taken = |by: String| Err(ManagerError::IpTakenError { ip, by });
+        if ip == self.server_ip() {
+            return taken("the server".into());
+        }
+        if let Some(dns) = self.dns.as_ref().filter(|dns| dns.server() == ip) {
+            return taken(format!("the DNS server for {}", dns.domain()));
+        }
+        if let Some(client) = self
+            .clients
+            .values()
+            .find(|client| client.ip() == ip && client.name() != name)
+        {
+            return taken(format!("client '{}'", client.name()));
+        }
+        if let Some(invite) = self
+            .invites
+            .values()
+            .find(|invite| invite.ip() == ip && invite.name() != name && invite.is_pending(now))
+        {
+            return taken(format!("the invite for '{}'", invite.name()));
+        }
+
+        Ok(())
     }
-}
 
-#[derive(Serialize, Deserialize)]
-pub struct Client {
-    name: String,
-    public_key: String,
-    ip: Ipv4Addr,
-}
+    /// Whether `name` belongs to a client, or is reserved by a pending invite
+    fn name_taken(&self, name: &str, now: u64) -> bool {
+        self.clients.contains_key(name)
+            || self
+                .invites
+                .get(name)
+                .is_some_and(|invite| invite.is_pending(now))
+    }
 
-impl Client {
-    pub fn name(&self) -> &String {
-        &self.name
+    /// Creates an invite for a client, returning the invite's token, which is not stored
+    pub fn new_invite(
+        &mut self,
+        name: String,
+        ip: Ipv4Addr,
+        groups: Vec<String>,
+        now: u64,
+        ttl: u64,
+    ) -> Result<String, ManagerError> {
+        if !utils::is_valid_name(&name) {
+            return Err(ManagerError::InvalidNameError(name));
+        }
+        if self.name_taken(&name, now) {
+            return Err(ManagerError::ClientNameExistsError(name));
+        }
+        self.check_ip(&name, ip, now)?;
+        check_names(&groups)?;
+
+        // Replaces any expired or used invite for the same name
+        let (invite, secret) = Invite::generate(name.clone(), ip, groups, now, ttl)?;
+        self.invites.insert(name, invite);
+        Ok(secret)
     }
-    pub fn public_key(&self) -> &String {
-        &self.public_key
+
+    /// Turns the invite that `secret` belongs to into a client.
+    ///
+    /// The client gets `public_key` if one is given, otherwise a keypair is generated and the
+    /// private key returned.
+    pub fn redeem_invite(
+        &mut self,
+        secret: &str,
+        public_key: Option<String>,
+        now: u64,
+    ) -> Result<(&Client, Option<String>), ManagerError> {
+        let name = self
+            .invites
+            .values()
+            .find(|invite| invite.matches(secret))
+            .ok_or(InviteError::NotFound)?
+            .name()
+            .clone();
+        // Redeemed on a copy, so the invite stays unused if the client can't be created. A client
+        // of the same name can only exist if the invite is no longer pending, which `redeem`
+        // catches
+        let mut invite = self.invites[&name].clone();
+        invite.redeem(now)?;
+        self.check_ip(&name, invite.ip(), now)?;
+
+        let (public_key, private_key) = self.keypair(public_key)?;
+
+        let mut client = Client::new(invite.name().clone(), public_key, invite.ip());
+        client.groups = invite.groups().clone();
+        client.key_created_at = Some(now);
+        client.created_at = Some(now);
+        client.created_by = Some("invite".into());
+        if self.default_psk {
+            client.preshared_key = Some(self.wg.genpsk()?);
+        }
+
+        self.invites.insert(name.clone(), invite);
+        self.clients.insert(name.clone(), client);
+        Ok((self.clients.get(&name).unwrap(), private_key))
+    }
+
+    /// Gives a client a new public key (or keypair, in which case the private key is returned),
+    /// keeping everything else about it
+    pub fn rotate_client_key(
+        &mut self,
+        name: &str,
+        public_key: Option<String>,
+        now: u64,
+    ) -> Result<(&Client, Option<String>), ManagerError> {
+        if !self.clients.contains_key(name) {
+            return Err(ManagerError::ClientNotFoundError(name.to_owned()));
+        }
+
+        let (public_key, private_key) = self.keypair(public_key)?;
+
+        let client = self.clients.get_mut(name).unwrap();
+        client.public_key = public_key;
+        client.key_created_at = Some(now);
+        client.updated_at = Some(now);
+        // A stored key is replaced, unless the client now keeps its own
+        if client.private_key.is_some() {
+            client.private_key = private_key.clone();
+        }
+        Ok((client, private_key))
+    }
+
+    /// Names of clients whose keys are older than `older_than` seconds, sorted, or of every
+    /// client if `older_than` is `None`
+    pub fn clients_to_rotate(&self, older_than: Option<u64>, now: u64) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .clients
+            .values()
+            .filter(|client| older_than.is_none_or(|age| client.key_older_than(age, now)))
+            .map(|client| client.name.clone())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Replaces the server's key with a new one straight away, discarding any staged key
+    pub fn rotate_server_key(&mut self) -> Result<(), ManagerError> {
+        let private_key = self.wg.genkey()?;
+        self.public_key = self.wg.pubkey(&private_key)?;
+        self.private_key = private_key;
+        self.staged_key = None;
+        Ok(())
+    }
+
+    /// Generates a new server key without switching to it yet, replacing any staged key
+    pub fn stage_server_key(&mut self, switch_at: Option<u64>) -> Result<&StagedKey, ManagerError> {
+        let private_key = self.wg.genkey()?;
+        let public_key = self.wg.pubkey(&private_key)?;
+
+        Ok(self.staged_key.insert(StagedKey {
+            private_key,
+            public_key,
+            switch_at,
+        }))
+    }
+
+    /// Switches to the staged server key, returning whether there was one
+    pub fn switch_server_key(&mut self) -> bool {
+        match self.staged_key.take() {
+            Some(staged_key) => {
+                self.private_key = staged_key.private_key;
+                self.public_key = staged_key.public_key;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn staged_key(&self) -> Option<&StagedKey> {
+        self.staged_key.as_ref()
+    }
+
+    /// Whether a staged key is due to be switched to
+    pub fn switch_due(&self, now: u64) -> bool {
+        self.staged_key
+            .as_ref()
+            .and_then(|staged_key| staged_key.switch_at)
+            .is_some_and(|switch_at| now >= switch_at)
+    }
+
+    /// Replaces a client's preshared key with a new one, leaving everything else as it is
+    pub fn rotate_preshared_key(&mut self, name: &str, now: u64) -> Result<&Client, ManagerError> {
+        let preshared_key = self.wg.genpsk()?;
+        let client = self
+            .clients
+            .get_mut(name)
+            .ok_or_else(|| ManagerError::ClientNotFoundError(name.to_owned()))?;
+
+        client.preshared_key = Some(preshared_key);
+        client.updated_at = Some(now);
+        Ok(client)
+    }
+
+    pub fn invites(&self) -> Vec<&Invite> {
+        self.invites.values().collect()
+    }
+
+    pub fn client(&self, name: &str) -> Result<&Client, ManagerError> {
+        self.clients
+            .get(name)
+            .ok_or_else(|| ManagerError::ClientNotFoundError(name.to_owned()))
+    }
+
+    /// The settings of a client's groups merged, along with the client's own. Its tuning is
+    /// what the client ends up with, its own settings winning over its groups', which win over
+    /// the server's.
+    fn client_settings(&self, client: &Client) -> Group {
+        let groups = Group::merge(
+            client
+                .groups()
+                .iter()
+                .filter_map(|name| self.groups.get(name)),
+        );
+        let tuning = client
+            .tuning
+            .or(groups.tuning())
+            .or(&self.tuning.inherited());
+
+        let mut route_all_except = groups.route_all_except().clone();
+        for excluded in &client.route_all_except {
+            if !route_all_except.contains(excluded) {
+                route_all_except.push(*excluded);
+            }
+        }
+
+        Group::new(
+            groups.allowed_ips().clone(),
+            groups.dns().clone(),
+            tuning,
+            route_all_except,
+        )
+    }
+
+    /// Config for a client to connect to the server, given the client's private key if it is known.
+    ///
+    /// The settings of the client's groups are merged in, on top of the VPN's own range, along
+    /// with the subnets of other clients that the policy lets it talk to (in either direction,
+    /// since replies need routing too). With DNS records, the server's resolver comes first.
+    pub fn client_config(&self, client: &Client, private_key: Option<String>) -> ClientConfig {
+        let group = self.client_settings(client);
+
+        let mut allowed_ips = vec![IpNet::from(self.ip_range)];
+        allowed_ips.extend(
+            group
+                .allowed_ips()
+                .iter()
+                .filter(|allowed_ip| **allowed_ip != IpNet::from(self.ip_range)),
+        );
+
+        if !self.policy.is_empty() {
+            let policy = self.policy();
+            let mut others: Vec<&Client> = self
+                .clients
+                .values()
+                .filter(|other| other.name() != client.name() && other.is_enabled())
+                .collect();
+            others.sort_by_key(|other| other.name());
+
+            for subnet in others.iter().flat_map(|other| other.subnets()) {
+                let routed = client
+                    .networks()
+                    .into_iter()
+                    .any(|network| policy.connects(network, *subnet));
+                if routed && !allowed_ips.contains(&IpNet::from(*subnet)) {
+                    allowed_ips.push(IpNet::from(*subnet));
+                }
+            }
+        }
+
+        if !group.route_all_except().is_empty() {
+            let mut excluded = group.route_all_except().clone();
+            // wg-quick only keeps traffic to the server out of the tunnel when routing a /0, so
+            // the server is excluded too
+            if !self.endpoint.ip().is_unspecified() {
+                excluded.push(IpNet::from(Ipv4Net::from(*self.endpoint.ip())));
+            }
+            allowed_ips = split_tunnel::route_all_except(&excluded, &allowed_ips);
+        }
+
+        let mut dns = group.dns().clone();
+        let mut search_domains = Vec::new();
+        if let Some(settings) = &self.dns {
+            let server = IpAddr::from(settings.server());
+            dns.retain(|ip| *ip != server);
+            dns.insert(0, server);
+            search_domains.push(settings.domain().clone());
+        }
+
+        ClientConfig {
+            private_key,
+            address: Ipv4Net::from(client.ip()),
+            dns,
+            search_domains,
+            server_public_key: self.public_key.clone(),
+            preshared_key: client.preshared_key().cloned(),
+            endpoint: self.endpoint,
+            allowed_ips,
+            persistent_keepalive: group.tuning().keepalive(),
+            mtu: group.tuning().mtu,
+            fwmark: group.tuning().mark(),
+            table: group.tuning().table,
+            post_up: group.tuning().post_up.clone(),
+            post_down: group.tuning().post_down.clone(),
+        }
+    }
+
+    /// Config for bringing the interface up with `wg-quick`, with every enabled client as a peer
+    pub fn server_config(&self) -> ServerConfig {
+        let mut clients: Vec<&Client> = self.clients.values().filter(|c| c.is_enabled()).collect();
+        clients.sort_by_key(|client| client.name());
+
+        ServerConfig {
+            private_key: self.private_key.clone(),
+            listen_port: self.endpoint.port(),
+            mtu: self.tuning.mtu,
+            fwmark: self.tuning.mark(),
+            table: self.tuning.table,
+            post_up: self.tuning.post_up.clone(),
+            post_down: self.tuning.post_down.clone(),
+            peers: clients
+                .into_iter()
+                .map(|client| ServerPeer {
+                    name: client.name().clone(),
+                    public_key: client.public_key().clone(),
+                    preshared_key: client.preshared_key().cloned(),
+                    allowed_ips: client.allowed_ips(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Creates or replaces a group
+    pub fn set_group(&mut self, name: String, group: Group) -> Result<&Group, ManagerError> {
+        check_names(std::slice::from_ref(&name))?;
+        group.tuning().validate()?;
+        self.groups.insert(name.clone(), group);
+        Ok(self.groups.get(&name).unwrap())
+    }
+
+    pub fn group(&self, name: &str) -> Result<&Group, ManagerError> {
+        self.groups
+            .get(name)
+            .ok_or_else(|| ManagerError::GroupNotFoundError(name.to_owned()))
+    }
+
+    /// Groups, sorted by name
+    pub fn groups(&self) -> Vec<(&String, &Group)> {
+        self.groups.iter().collect()
+    }
+
+    /// Deletes a group's settings, its members keep belonging to it by name
+    pub fn delete_group(&mut self, name: &str) -> Result<Group, ManagerError> {
+        self.groups
+            .remove(name)
+            .ok_or_else(|| ManagerError::GroupNotFoundError(name.to_owned()))
+    }
+
+    pub fn new_mesh(&mut self, name: String, mesh: Mesh) -> Result<&Mesh, ManagerError> {
+        if !utils::is_valid_name(&name) {
+            return Err(ManagerError::InvalidNameError(name));
+        }
+        if self.meshes.contains_key(&name) {
+            return Err(ManagerError::MeshExistsError(name));
+        }
+        self.meshes.insert(name.clone(), mesh);
+        Ok(self.meshes.get(&name).unwrap())
+    }
+
+    pub fn mesh(&self, name: &str) -> Result<&Mesh, ManagerError> {
+        self.meshes
+            .get(name)
+            .ok_or_else(|| ManagerError::MeshNotFoundError(name.to_owned()))
+    }
+
+    /// Meshes, sorted by name
+    pub fn meshes(&self) -> Vec<(&String, &Mesh)> {
+        self.meshes.iter().collect()
+    }
+
+    pub fn delete_mesh(&mut self, name: &str) -> Result<Mesh, ManagerError> {
+        self.meshes
+            .remove(name)
+            .ok_or_else(|| ManagerError::MeshNotFoundError(name.to_owned()))
+    }
+
+    /// Adds a node to a mesh, generating a keypair for it unless it brings its own public key
+    pub fn add_mesh_node(
+        &mut self,
+        mesh: &str,
+        name: String,
+        ip: Ipv4Addr,
+        endpoint: String,
+        public_key: Option<String>,
+    ) -> Result<&Node, ManagerError> {
+        self.mesh(mesh)?;
+        if !utils::is_valid_name(&name) {
+            return Err(ManagerError::InvalidNameError(name));
+        }
+        let (public_key, private_key) = match public_key {
+            Some(public_key) if !wg::is_valid_key(&public_key) => {
+                return Err(ManagerError::InvalidPublicKeyError(public_key))
+            }
+            Some(public_key) => (public_key, None),
+            None => {
+                let private_key = self.wg.genkey()?;
+                (self.wg.pubkey(&private_key)?, Some(private_key))
+            }
+        };
+        let node = Node::new(ip, endpoint, public_key, private_key)?;
+
+        let mesh = self.meshes.get_mut(mesh).expect("mesh was just found");
+        Ok(mesh.add_node(name, node)?)
+    }
+
+    pub fn remove_mesh_node(&mut self, mesh: &str, name: &str) -> Result<Node, ManagerError> {
+        Ok(self
+            .meshes
+            .get_mut(mesh)
+            .ok_or_else(|| ManagerError::MeshNotFoundError(mesh.to_owned()))?
+            .remove_node(name)?)
+    }
+
+    /// Access policy rules, in the order they are evaluated
+    pub fn rules(&self) -> &Vec<Rule> {
+        &self.policy
+    }
+
+    /// Adds a rule, whose groups must have settings or members, since otherwise it would match
+    /// nothing
+    pub fn add_rule(&mut self, rule: Rule) -> Result<&Rule, ManagerError> {
+        let to = match rule.to() {
+            Target::Group(group) => Some(group),
+            Target::Network(_) => None,
+        };
+        for group in std::iter::once(rule.from()).chain(to) {
+            if !self.group_exists(group) {
+                return Err(ManagerError::GroupNotFoundError(group.clone()));
+            }
+        }
+        self.policy.push(rule);
+        Ok(self.policy.last().unwrap())
+    }
+
+    /// Whether a group has settings, or any client is a member of it
+    fn group_exists(&self, name: &str) -> bool {
+        self.groups.contains_key(name)
+            || self
+                .clients
+                .values()
+                .any(|client| client.groups.iter().any(|group| group == name))
+    }
+
+    /// Removes a rule by its number, counting from 1
+    pub fn remove_rule(&mut self, number: usize) -> Result<Rule, ManagerError> {
+        if number == 0 || number > self.policy.len() {
+            return Err(ManagerError::PolicyRuleNotFoundError(number));
+        }
+        Ok(self.policy.remove(number - 1))
+    }
+
+    /// The access policy along with the addresses of every group's enabled members
+    pub fn policy(&self) -> Policy<'_> {
+        let mut members: BTreeMap<String, Vec<Ipv4Net>> = BTreeMap::new();
+        let mut clients: Vec<&Client> = self
+            .clients
+            .values()
+            .filter(|client| client.is_enabled())
+            .collect();
+        clients.sort_by_key(|client| client.ip());
+
+        let mut subnets = Vec::new();
+        for client in clients {
+            for group in client.groups() {
+                members
+                    .entry(group.clone())
+                    .or_default()
+                    .extend(client.networks());
+            }
+            subnets.extend(client.subnets().iter().copied());
+        }
+
+        Policy {
+            interface: &self.interface_name,
+            ip_range: self.ip_range,
+            subnets,
+            rules: &self.policy,
+            members,
+        }
+    }
+
+    /// Clients in a group, sorted by name
+    pub fn group_members(&self, name: &str) -> Vec<&Client> {
+        let mut members: Vec<&Client> = self
+            .clients
+            .values()
+            .filter(|client| client.groups().iter().any(|group| group == name))
+            .collect();
+        members.sort_by_key(|client| client.name());
+        members
+    }
+
+    /// Disables a client, which keeps its ip, key and everything else, but not its peer
+    pub fn disable_client(
+        &mut self,
+        name: &str,
+        reason: Option<String>,
+        now: u64,
+    ) -> Result<&Client, ManagerError> {
+        let client = self
+            .clients
+            .get_mut(name)
+            .ok_or_else(|| ManagerError::ClientNotFoundError(name.to_owned()))?;
+
+        client.disabled = Some(Disabled { reason, at: now });
+        client.updated_at = Some(now);
+        Ok(client)
+    }
+
+    /// Enables a client, optionally with a new expiry date. An expired client's old expiry date is
+    /// dropped, otherwise it would just be disabled again.
+    pub fn enable_client(
+        &mut self,
+        name: &str,
+        expires_at: Option<u64>,
+        now: u64,
+    ) -> Result<&Client, ManagerError> {
+        let client = self
+            .clients
+            .get_mut(name)
+            .ok_or_else(|| ManagerError::ClientNotFoundError(name.to_owned()))?;
+
+        client.disabled = None;
+        if expires_at.is_some() || client.is_expired(now) {
+            client.expires_at = expires_at;
+        }
+        client.updated_at = Some(now);
+        Ok(client)
+    }
+
+    /// Changes a client's metadata, groups and expiry date
+    pub fn edit_client(
+        &mut self,
+        name: &str,
+        edit: ClientEdit,
+        now: u64,
+    ) -> Result<&Client, ManagerError> {
+        let mut subnets = self.client(name)?.subnets.clone();
+        subnets.retain(|subnet| {
+            !edit
+                .remove_subnets
+                .iter()
+                .any(|removed| removed.trunc() == *subnet)
+        });
+        for subnet in edit.add_subnets.iter().map(Ipv4Net::trunc) {
+            if !subnets.contains(&subnet) {
+                subnets.push(subnet);
+            }
+        }
+        self.check_subnets(name, &subnets)?;
+        check_names(&edit.add_groups)?;
+        if let Some(tuning) = &edit.tuning {
+            tuning.validate()?;
+        }
+
+        let client = self
+            .clients
+            .get_mut(name)
+            .ok_or_else(|| ManagerError::ClientNotFoundError(name.to_owned()))?;
+
+        client.subnets = subnets;
+        // Setting an empty owner or description removes it
+        if let Some(owner) = edit.owner {
+            client.owner = Some(owner).filter(|owner| !owner.is_empty());
+        }
+        if let Some(description) = edit.description {
+            client.description = Some(description).filter(|description| !description.is_empty());
+        }
+        for key in edit.remove_tags {
+            client.tags.remove(&key);
+        }
+        client.tags.extend(edit.tags);
+        let remove_groups = edit.remove_groups;
+        client.groups.retain(|group| !remove_groups.contains(group));
+        for group in edit.add_groups {
+            if !client.groups.contains(&group) {
+                client.groups.push(group);
+            }
+        }
+        if let Some(expires_at) = edit.expires_at {
+            client.expires_at = expires_at;
+        }
+        if let Some(route_all_except) = edit.route_all_except {
+            client.route_all_except = route_all_except;
+        }
+        if let Some(tuning) = edit.tuning {
+            client.tuning = tuning;
+        }
+
+        client.updated_at = Some(now);
+        Ok(client)
+    }
+
+    /// Disables clients that have expired, and deletes those that have been expired for longer
+    /// than the grace period
+    pub fn expire_clients(&mut self, now: u64) -> Vec<Expiry> {
+        let mut expired: Vec<&mut Client> = self
+            .clients
+            .values_mut()
+            .filter(|client| client.is_expired(now))
+            .collect();
+        expired.sort_by_key(|client| client.name.clone());
+
+        let mut expiries = Vec::new();
+        for client in expired {
+            let expires_at = client.expires_at.unwrap_or_default();
+            let past_grace = self
+                .expiry_grace
+                .is_some_and(|grace| now >= expires_at.saturating_add(grace));
+
+            if past_grace {
+                expiries.push(Expiry::Deleted(client.name.clone()));
+            } else if client.is_enabled() {
+                client.disabled = Some(Disabled {
+                    reason: Some("expired".into()),
+                    at: now,
+                });
+                client.updated_at = Some(now);
+                expiries.push(Expiry::Disabled(client.name.clone()));
+            }
+        }
+
+        for expiry in &expiries {
+            if let Expiry::Deleted(name) = expiry {
+                self.clients.remove(name);
+            }
+        }
+        expiries
+    }
+
+    pub fn delete_client(&mut self, name: &str) -> Result<Client, ManagerError> {
+        self.clients
+            .remove(name)
+            .ok_or_else(|| ManagerError::ClientNotFoundError(name.to_owned()))
+    }
+
+    /// Joins configured clients with the live peers on the interface
+    pub fn status(&self) -> Result<Vec<PeerStatus<'_>>, ManagerError> {
+        let peers = self.wg.show_dump(&self.interface_name)?;
+        Ok(status::join(self.clients(), peers))
+    }
+
+    pub fn clients(&self) -> Vec<&Client> {
+        self.clients.values().collect()
+    }
+
+    /// Creates a new API token, returning the secret, which is not stored
+    pub fn new_api_token(
+        &mut self,
+        name: String,
+        role: Role,
+        group: Option<String>,
+        now: u64,
+    ) -> Result<String, ManagerError> {
+        if self.api_tokens.contains_key(&name) {
+            return Err(ManagerError::ApiTokenExistsError(name));
+        }
+        check_names(group.as_slice())?;
+
+        let (token, secret) = ApiToken::generate(name.clone(), role, group, now)?;
+        self.api_tokens.insert(name, token);
+        Ok(secret)
+    }
+
+    pub fn delete_api_token(&mut self, name: &str) -> Result<ApiToken, ManagerError> {
+        self.api_tokens
+            .remove(name)
+            .ok_or_else(|| ManagerError::ApiTokenNotFoundError(name.to_owned()))
+    }
+
+    pub fn api_tokens(&self) -> Vec<&ApiToken> {
+        self.api_tokens.values().collect()
+    }
+
+    /// Finds the API token that `secret` belongs to
+    pub fn authenticate(&self, secret: &str) -> Option<&ApiToken> {
+        self.api_tokens.values().find(|token| token.matches(secret))
+    }
+
+    pub fn endpoint(&self) -> SocketAddrV4 {
+        self.endpoint
+    }
+
+    pub fn interface_name(&self) -> &String {
+        &self.interface_name
+    }
+
+    pub fn public_key(&self) -> &String {
+        &self.public_key
+    }
+
+    pub fn ip_range(&self) -> Ipv4Net {
+        self.ip_range
+    }
+
+    pub fn default_psk(&self) -> bool {
+        self.default_psk
+    }
+
+    pub fn set_default_psk(&mut self, default_psk: bool) {
+        self.default_psk = default_psk;
+    }
+
+    pub fn expiry_grace(&self) -> Option<u64> {
+        self.expiry_grace
+    }
+
+    pub fn set_expiry_grace(&mut self, expiry_grace: Option<u64>) {
+        self.expiry_grace = expiry_grace;
+    }
+
+    pub fn egress_interface(&self) -> Option<&String> {
+        self.egress_interface.as_ref()
+    }
+
+    pub fn set_egress_interface(&mut self, egress_interface: Option<String>) {
+        self.egress_interface = egress_interface;
+    }
+
+    pub fn nat(&self) -> NatMode {
+        self.nat
+    }
+
+    pub fn set_nat(&mut self, nat: NatMode) {
+        self.nat = nat;
+    }
+
+    pub fn tuning(&self) -> &Tuning {
+        &self.tuning
+    }
+
+    pub fn set_tuning(&mut self, tuning: Tuning) -> Result<(), ManagerError> {
+        tuning.validate()?;
+        self.tuning = tuning;
+        Ok(())
+    }
+
+    pub fn dns(&self) -> Option<&Dns> {
+        self.dns.as_ref()
+    }
+
+    /// Sets where DNS records are written, or stops writing them, leaving the files alone
+    pub fn set_dns(&mut self, dns: Option<Dns>) -> Result<(), ManagerError> {
+        if let Some(dns) = &dns {
+            if !self.ip_range.contains(&dns.server()) {
+                return Err(DnsError::ServerOutOfRange {
+                    server: dns.server(),
+                    ip_range: self.ip_range,
+                }
+                .into());
+            }
+        }
+
+        self.dns = dns;
+        Ok(())
+    }
+
+    /// Hosts for the DNS records, the server first, named after the interface, and then enabled
+    /// clients by name. Also returns the clients left out since their names can't be used in DNS
+    /// (or clash with another name once lowercased).
+    fn dns_hosts(&self, server: Ipv4Addr) -> (Vec<(String, Ipv4Addr)>, Vec<String>) {
+        let mut hosts = vec![(self.interface_name.to_lowercase(), server)];
+        let mut skipped = Vec::new();
+
+        let mut clients: Vec<&Client> = self.clients.values().filter(|c| c.is_enabled()).collect();
+        clients.sort_by_key(|client| client.name());
+        for client in clients {
+            let label = client.name().to_lowercase();
+            if dns::is_valid_label(&label) && hosts.iter().all(|(other, _)| *other != label) {
+                hosts.push((label, client.ip()));
+            } else {
+                skipped.push(client.name().clone());
+            }
+        }
+
+        (hosts, skipped)
+    }
+
+    /// The files of DNS records that commits write, none if there aren't any records
+    pub fn dns_files(&self) -> Vec<DnsFile> {
+        match &self.dns {
+            Some(dns) => {
+                let (hosts, _) = self.dns_hosts(dns.server());
+                // Zone serials are the time of writing, which fits in 32 bits until 2106
+                dns.render(self.ip_range, &hosts, utils::unix_now() as u32)
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// Clients left out of the DNS records, since their names can't be used in DNS
+    pub fn dns_skipped(&self) -> Vec<String> {
+        match &self.dns {
+            Some(dns) => self.dns_hosts(dns.server()).1,
+            None => Vec::new(),
+        }
+    }
+
+    /// Writes the files from `dns_files` whose records are out of date, returning their paths
+    pub fn write_dns(&self) -> Result<Vec<PathBuf>, ManagerError> {
+        let mut written = Vec::new();
+        for file in self.dns_files() {
+            if !file.is_current()? {
+                file.write()?;
+                written.push(file.path);
+            }
+        }
+        Ok(written)
+    }
+}
+
+/// Fails with the first of `names` that `utils::is_valid_name` rejects, e.g. of groups
+fn check_names(names: &[String]) -> Result<(), ManagerError> {
+    match names.iter().find(|name| !utils::is_valid_name(name)) {
+        Some(name) => Err(ManagerError::InvalidNameError(name.clone())),
+        None => Ok(()),
+    }
+}
+
+/// Optional settings for a new client
+#[derive(Debug, Default)]
+pub struct ClientOptions {
+    pub groups: Vec<String>,
+    /// The client's own public key, otherwise a keypair is generated
+    pub public_key: Option<String>,
+    /// Whether to generate a preshared key, `None` meaning the server's default
+    pub preshared_key: Option<bool>,
+    /// Whether to keep a generated private key, so that configs can be re-issued
+    pub store_key: bool,
+    /// Unix timestamp after which the client is disabled
+    pub expires_at: Option<u64>,
+    pub owner: Option<String>,
+    pub description: Option<String>,
+    pub tags: BTreeMap<String, String>,
+    /// Networks behind the client, routed to it
+    pub subnets: Vec<Ipv4Net>,
+    /// Route everything through the VPN except these
+    pub route_all_except: Vec<IpNet>,
+    /// MTU, keepalive and so on, on top of what the client inherits
+    pub tuning: Tuning,
+    /// Whoever created the client, e.g. the user running the command
+    pub created_by: Option<String>,
+}
+
+/// Changes to a client's metadata, `None` leaving a field as it is
+#[derive(Debug, Default)]
+pub struct ClientEdit {
+    /// Empty to remove the owner
+    pub owner: Option<String>,
+    /// Empty to remove the description
+    pub description: Option<String>,
+    /// Tags to add or overwrite
+    pub tags: BTreeMap<String, String>,
+    pub remove_tags: Vec<String>,
+    pub add_groups: Vec<String>,
+    pub remove_groups: Vec<String>,
+    /// `Some(None)` to remove the expiry date
+    pub expires_at: Option<Option<u64>>,
+    pub add_subnets: Vec<Ipv4Net>,
+    pub remove_subnets: Vec<Ipv4Net>,
+    /// Replaces the ranges the client excludes from routing everything, `Some(vec![])` to stop
+    pub route_all_except: Option<Vec<IpNet>>,
+    /// Replaces the client's own tuning
+    pub tuning: Option<Tuning>,
+}
+
+/// Criteria for listing clients, all of which must match
+#[derive(Debug, Default)]
+pub struct ClientFilter {
+    pub ip: Option<Ipv4Addr>,
+    /// Case-insensitive
+    pub owner: Option<String>,
+    /// Case-insensitive substring
+    pub description: Option<String>,
+    /// Tags the client must have, with the given value if there is one
+    pub tags: Vec<(String, Option<String>)>,
+    pub created_by: Option<String>,
+    /// Unix timestamps, inclusive
+    pub created_after: Option<u64>,
+    pub created_before: Option<u64>,
+    pub updated_after: Option<u64>,
+    pub updated_before: Option<u64>,
+    /// Seconds within which the client must expire (or have already)
+    pub expiring: Option<u64>,
+}
+
+impl ClientFilter {
+    pub fn matches(&self, client: &Client, now: u64) -> bool {
+        let eq_ignore_case =
+            |a: &Option<String>, b: &String| a.as_ref().is_some_and(|a| a.eq_ignore_ascii_case(b));
+        // Clients without a timestamp never match a bound on it
+        let within = |ts: Option<u64>, after: Option<u64>, before: Option<u64>| {
+            (after.is_none() && before.is_none())
+                || ts.is_some_and(|ts| {
+                    after.is_none_or(|after| ts >= after)
+                        && before.is_none_or(|before| ts <= before)
+                })
+        };
+
+        self.ip.is_none_or(|ip| client.ip == ip)
+            && self
+                .owner
+                .as_ref()
+                .is_none_or(|owner| eq_ignore_case(&client.owner, owner))
+            && self.description.as_ref().is_none_or(|description| {
+                client
+                    .description
+                    .as_ref()
+                    .is_some_and(|client_description| {
+                        client_description
+                            .to_lowercase()
+                            .contains(&description.to_lowercase())
+                    })
+            })
+            && self.tags.iter().all(|(key, value)| match value {
+                Some(value) => client.tags.get(key) == Some(value),
+                None => client.tags.contains_key(key),
+            })
+            && self
+                .created_by
+                .as_ref()
+                .is_none_or(|created_by| client.created_by.as_ref() == Some(created_by))
+            && within(client.created_at, self.created_after, self.created_before)
+            && within(client.updated_at, self.updated_after, self.updated_before)
+            && self
+                .expiring
+                .is_none_or(|expiring| client.expires_within(expiring, now))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Client {
+    name: String,
+    public_key: String,
+    ip: Ipv4Addr,
+
+    #[serde(default)]
+    groups: Vec<String>,
+    /// Networks behind the client (such as a branch router's LAN), routed to it along with its ip
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        serialize_with = "utils::serialize_ipnets",
+        deserialize_with = "utils::deserialize_ipnets"
+    )]
+    subnets: Vec<Ipv4Net>,
+    /// If any, the client routes everything through the VPN except these, on top of any its
+    /// groups exclude
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        serialize_with = "utils::serialize_ipnets",
+        deserialize_with = "utils::deserialize_ipnets"
+    )]
+    route_all_except: Vec<IpNet>,
+    /// MTU, keepalive and so on, on top of what the client inherits
+    #[serde(flatten)]
+    tuning: Tuning,
+    /// Kept in plaintext, like the server's private key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    preshared_key: Option<String>,
+    /// Only kept if asked for, otherwise the private key is handed out once and forgotten
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    private_key: Option<String>,
+    /// Unix timestamp of when the client got its current public key, `None` for clients created
+    /// before this was recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key_created_at: Option<u64>,
+    /// Set while the client is disabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    disabled: Option<Disabled>,
+    /// Unix timestamp after which the client is disabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
+
+    /// Who the client belongs to, e.g. an email address
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    owner: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    tags: BTreeMap<String, String>,
+    /// Unix timestamps, `None` for clients created before these were recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    updated_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created_by: Option<String>,
+}
+
+/// Why and when a client was disabled
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Disabled {
+    reason: Option<String>,
+    /// Unix timestamp
+    at: u64,
+}
+
+impl Disabled {
+    pub fn reason(&self) -> Option<&String> {
+        self.reason.as_ref()
+    }
+
+    pub fn at(&self) -> u64 {
+        self.at
+    }
+}
+
+impl Client {
+    pub fn new(name: String, public_key: String, ip: Ipv4Addr) -> Self {
+        Client {
+            name,
+            public_key,
+            ip,
+            groups: Vec::new(),
+            subnets: Vec::new(),
+            route_all_except: Vec::new(),
+            tuning: Tuning::default(),
+            preshared_key: None,
+            private_key: None,
+            key_created_at: None,
+            disabled: None,
+            expires_at: None,
+            owner: None,
+            description: None,
+            tags: BTreeMap::new(),
+            created_at: None,
+            updated_at: None,
+            created_by: None,
+        }
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+    pub fn public_key(&self) -> &String {
+        &self.public_key
+    }
+    pub fn ip(&self) -> Ipv4Addr {
+        self.ip
+    }
+    pub fn groups(&self) -> &Vec<String> {
+        &self.groups
+    }
+    pub fn subnets(&self) -> &Vec<Ipv4Net> {
+        &self.subnets
+    }
+    pub fn route_all_except(&self) -> &Vec<IpNet> {
+        &self.route_all_except
+    }
+    /// The client's own tuning, leaving aside what it inherits
+    pub fn tuning(&self) -> &Tuning {
+        &self.tuning
+    }
+    pub fn preshared_key(&self) -> Option<&String> {
+        self.preshared_key.as_ref()
+    }
+    pub fn private_key(&self) -> Option<&String> {
+        self.private_key.as_ref()
+    }
+    /// Whether the client's peer should be on the interface
+    pub fn is_enabled(&self) -> bool {
+        self.disabled.is_none()
+    }
+    pub fn disabled(&self) -> Option<&Disabled> {
+        self.disabled.as_ref()
+    }
+    pub fn expires_at(&self) -> Option<u64> {
+        self.expires_at
+    }
+    pub fn owner(&self) -> Option<&String> {
+        self.owner.as_ref()
+    }
+    pub fn description(&self) -> Option<&String> {
+        self.description.as_ref()
+    }
+    pub fn tags(&self) -> &BTreeMap<String, String> {
+        &self.tags
+    }
+    pub fn created_at(&self) -> Option<u64> {
+        self.created_at
+    }
+    pub fn updated_at(&self) -> Option<u64> {
+        self.updated_at
+    }
+    pub fn created_by(&self) -> Option<&String> {
+        self.created_by.as_ref()
+    }
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
+    /// Whether the client expires within `duration` seconds, or already has
+    pub fn expires_within(&self, duration: u64, now: u64) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= now.saturating_add(duration))
+    }
+    /// Whether the client's key is older than `age` seconds, which keys of unknown age always are
+    pub fn key_older_than(&self, age: u64, now: u64) -> bool {
+        self.key_created_at
+            .is_none_or(|created_at| now.saturating_sub(created_at) > age)
+    }
+
+    /// Allowed ips for the client's peer on the server, sorted
+    pub fn allowed_ips(&self) -> Vec<IpNet> {
+        let mut allowed_ips: Vec<IpNet> = std::iter::once(Ipv4Net::from(self.ip))
+            .chain(self.subnets.iter().copied())
+            .map(IpNet::from)
+            .collect();
+        allowed_ips.sort();
+        allowed_ips
+    }
+
+    /// The client's ip and subnets
+    pub fn networks(&self) -> Vec<Ipv4Net> {
+        std::iter::once(Ipv4Net::from(self.ip))
+            .chain(self.subnets.iter().copied())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tuning::Table;
+
+    fn manager() -> Manager {
+        serde_json::from_str(
+            r#"{
+                "interface_name": "wg0",
+                "private_key": "server-private",
+                "public_key": "server-public",
+                "endpoint": "1.2.3.4:51900",
+                "ip_range": "10.0.0.0/24",
+                "clients": {
+                    "alice": {"name": "alice", "public_key": "A=", "ip": "10.0.0.2", "preshared_key": "PA="},
+                    "bob": {"name": "bob", "public_key": "B=", "ip": "10.0.0.3"},
+                    "carol": {"name": "carol", "public_key": "C=", "ip": "10.0.0.4"}
+                },
+                "wg": {"binary_path": "wg"}
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_diff_in_sync() {
+        let manager = manager();
+
+        let changes = manager.diff(
+            Some("server-private".into()),
+            51900,
+            vec![
+                ("A=".into(), vec!["10.0.0.2/32".parse().unwrap()]),
+                ("B=".into(), vec!["10.0.0.3/32".parse().unwrap()]),
+                ("C=".into(), vec!["10.0.0.4/32".parse().unwrap()]),
+            ],
+            vec![
+                ("A=".into(), Some("PA=".into())),
+                ("B=".into(), None),
+                ("C=".into(), None),
+            ],
+        );
+
+        assert_eq!(changes, vec![]);
+    }
+
+    #[test]
+    fn test_diff() {
+        let manager = manager();
+
+        let changes = manager.diff(
+            None,
+            0,
+            vec![
+                ("A=".into(), vec!["10.0.0.2/32".parse().unwrap()]),
+                ("B=".into(), vec!["10.0.0.99/32".parse().unwrap()]),
+                ("X=".into(), vec![]),
+            ],
+            vec![
+                ("A=".into(), None),
+                ("B=".into(), Some("PB=".into())),
+                ("X=".into(), None),
+            ],
+        );
+
+        assert_eq!(
+            changes,
+            vec![
+                Change::SetPrivateKey,
+                Change::SetListenPort(51900),
+                Change::SetPresharedKey {
+                    name: "alice".into(),
+                    public_key: "A=".into(),
+                    preshared_key: Some("PA=".into()),
+                },
+                Change::UpdatePeer {
+                    name: "bob".into(),
+                    public_key: "B=".into(),
+                    allowed_ips: vec!["10.0.0.3/32".parse().unwrap()],
+                },
+                Change::SetPresharedKey {
+                    name: "bob".into(),
+                    public_key: "B=".into(),
+                    preshared_key: None,
+                },
+                Change::AddPeer {
+                    name: "carol".into(),
+                    public_key: "C=".into(),
+                    allowed_ips: vec!["10.0.0.4/32".parse().unwrap()],
+                },
+                Change::RemovePeer {
+                    public_key: "X=".into()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_rotated_key() {
+        let manager = manager();
+
+        let changes = manager.diff(
+            Some("server-private".into()),
+            51900,
+            vec![
+                ("OLD=".into(), vec!["10.0.0.2/32".parse().unwrap()]),
+                ("B=".into(), vec!["10.0.0.3/32".parse().unwrap()]),
+                ("C=".into(), vec!["10.0.0.4/32".parse().unwrap()]),
+            ],
+            vec![
+                ("OLD=".into(), Some("PA=".into())),
+                ("B=".into(), None),
+                ("C=".into(), None),
+            ],
+        );
+
+        assert_eq!(
+            changes,
+            vec![Change::ReplacePeer {
+                name: "alice".into(),
+                old_public_key: "OLD=".into(),
+                public_key: "A=".into(),
+                allowed_ips: vec!["10.0.0.2/32".parse().unwrap()],
+                preshared_key: Some("PA=".into()),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_diff_disabled() {
+        let mut manager = manager();
+        manager
+            .disable_client("bob", Some("on leave".into()), 1000)
+            .unwrap();
+
+        let changes = manager.diff(
+            Some("server-private".into()),
+            51900,
+            vec![
+                ("A=".into(), vec!["10.0.0.2/32".parse().unwrap()]),
+                ("B=".into(), vec!["10.0.0.3/32".parse().unwrap()]),
+                ("C=".into(), vec!["10.0.0.4/32".parse().unwrap()]),
+            ],
+            vec![
+                ("A=".into(), Some("PA=".into())),
+                ("B=".into(), None),
+                ("C=".into(), None),
+            ],
+        );
+        assert_eq!(
+            changes,
+            vec![Change::RemoveDisabledPeer {
+                name: "bob".into(),
+                public_key: "B=".into()
+            }]
+        );
+
+        // Bob keeps his ip and key, and gets his peer back once enabled
+        manager.enable_client("bob", None, 1000).unwrap();
+        let bob = manager.client("bob").unwrap();
+        assert!(bob.is_enabled());
+        assert_eq!(bob.public_key(), "B=");
+    }
+
+    #[test]
+    fn test_expire_clients() {
+        let mut manager = manager();
+        manager.clients.get_mut("alice").unwrap().expires_at = Some(1000);
+        manager.clients.get_mut("bob").unwrap().expires_at = Some(2000);
+
+        assert_eq!(manager.expire_clients(999), vec![]);
+        assert_eq!(
+            manager.expire_clients(1000),
+            vec![Expiry::Disabled("alice".into())]
+        );
+        // Already disabled, so nothing more happens without a grace period
+        assert_eq!(
+            manager.expire_clients(5000),
+            vec![Expiry::Disabled("bob".into())]
+        );
+        assert!(!manager.client("alice").unwrap().is_enabled());
+
+        manager.set_expiry_grace(Some(3600));
+        assert_eq!(manager.expire_clients(4599), vec![]);
+        assert_eq!(
+            manager.expire_clients(4600),
+            vec![Expiry::Deleted("alice".into())]
+        );
+        assert!(manager.client("alice").is_err());
+
+        // Enabling an expired client drops its expiry, so it isn't disabled straight away
+        manager.enable_client("bob", None, 4600).unwrap();
+        assert_eq!(manager.expire_clients(9000), vec![]);
+        assert!(manager.client("bob").unwrap().is_enabled());
+    }
+
+    #[test]
+    fn test_client_filter() {
+        let mut manager = manager();
+        let edit = ClientEdit {
+            owner: Some("Alice@example.com".into()),
+            description: Some("Work laptop".into()),
+            tags: BTreeMap::from([("os".to_owned(), "linux".to_owned())]),
+            ..ClientEdit::default()
+        };
+        manager.edit_client("alice", edit, 1000).unwrap();
+        let alice = manager.client("alice").unwrap();
+        let bob = manager.client("bob").unwrap();
+
+        let filter = |filter: ClientFilter| (filter.matches(alice, 0), filter.matches(bob, 0));
+
+        assert_eq!(filter(ClientFilter::default()), (true, true));
+        assert_eq!(
+            filter(ClientFilter {
+                ip: Some(Ipv4Addr::new(10, 0, 0, 3)),
+                ..ClientFilter::default()
+            }),
+            (false, true)
+        );
+        assert_eq!(
+            filter(ClientFilter {
+                owner: Some("alice@EXAMPLE.com".into()),
+                description: Some("laptop".into()),
+                tags: vec![("os".into(), Some("linux".into()))],
+                ..ClientFilter::default()
+            }),
+            (true, false)
+        );
+        assert_eq!(
+            filter(ClientFilter {
+                tags: vec![("os".into(), Some("windows".into()))],
+                ..ClientFilter::default()
+            }),
+            (false, false)
+        );
+        // Bob was never updated, so has no timestamp to compare
+        assert_eq!(
+            filter(ClientFilter {
+                updated_after: Some(1000),
+                ..ClientFilter::default()
+            }),
+            (true, false)
+        );
+        assert_eq!(
+            filter(ClientFilter {
+                updated_before: Some(999),
+                ..ClientFilter::default()
+            }),
+            (false, false)
+        );
+    }
+
+    #[test]
+    fn test_client_config_groups() {
+        let mut manager = manager();
+        manager
+            .set_group(
+                "engineering".into(),
+                Group::new(
+                    vec![
+                        "10.0.0.0/24".parse().unwrap(),
+                        "10.0.0.0/8".parse().unwrap(),
+                    ],
+                    vec!["10.0.0.1".parse().unwrap()],
+                    Tuning {
+                        persistent_keepalive: Some(25),
+                        ..Tuning::default()
+                    },
+                    vec![],
+                ),
+            )
+            .unwrap();
+        manager.clients.get_mut("alice").unwrap().groups =
+            vec!["engineering".into(), "undefined".into()];
+
+        let alice = manager.client("alice").unwrap();
+        let config = manager.client_config(alice, None);
+        assert_eq!(
+            config.allowed_ips,
+            vec![
+                "10.0.0.0/24".parse::<IpNet>().unwrap(),
+                "10.0.0.0/8".parse().unwrap()
+            ]
+        );
+        assert_eq!(
+            config.dns,
+            vec!["10.0.0.1".parse::<std::net::IpAddr>().unwrap()]
+        );
+        assert_eq!(config.persistent_keepalive, Some(25));
+
+        let bob = manager.client("bob").unwrap();
+        let config = manager.client_config(bob, None);
+        assert_eq!(
+            config.allowed_ips,
+            vec!["10.0.0.0/24".parse::<IpNet>().unwrap()]
+        );
+        assert!(config.dns.is_empty());
+        assert_eq!(manager.group_members("engineering").len(), 1);
+    }
+
+    #[test]
+    fn test_client_config_tuning() {
+        let mut manager = manager();
+        manager
+            .set_tuning(Tuning {
+                mtu: Some(1420),
+                persistent_keepalive: Some(25),
+                fwmark: Some(0x51820),
+                ..Tuning::default()
+            })
+            .unwrap();
+        let group = Tuning {
+            mtu: Some(1380),
+            table: Some(Table::Off),
+            ..Tuning::default()
+        };
+        manager
+            .set_group("roaming".into(), Group::new(vec![], vec![], group, vec![]))
+            .unwrap();
+        let alice = manager.clients.get_mut("alice").unwrap();
+        alice.groups = vec!["roaming".into()];
+        alice.tuning.persistent_keepalive = Some(0);
+
+        // The group's MTU beats the server's, the client turns keepalive off, and the server's
+        // fwmark is only for its own interface
+        let alice = manager.client("alice").unwrap();
+        let config = manager.client_config(alice, None);
+        assert_eq!(config.mtu, Some(1380));
+        assert_eq!(config.persistent_keepalive, None);
+        assert_eq!(config.fwmark, None);
+        assert_eq!(config.table, Some(Table::Off));
+
+        let bob = manager.client("bob").unwrap();
+        let config = manager.client_config(bob, None);
+        assert_eq!(config.mtu, Some(1420));
+        assert_eq!(config.persistent_keepalive, Some(25));
+        assert_eq!(config.table, None);
+
+        let invalid = Tuning {
+            mtu: Some(576),
+            ..Tuning::default()
+        };
+        assert!(manager.set_tuning(invalid).is_err());
+    }
+
+    #[test]
+    fn test_firewall() {
+        let mut manager = manager();
+        assert_eq!(manager.firewall(), None);
+
+        manager.set_nat(NatMode::Nftables);
+        manager.set_egress_interface(Some("eth0".into()));
+        let firewall = manager.firewall().unwrap();
+        assert!(firewall.starts_with("table inet wg_manager_wg0\n"));
+        assert!(firewall.contains("\t\tip saddr 10.0.0.0/24 oifname \"eth0\" masquerade\n"));
+        assert!(!firewall.contains("chain forward"));
+
+        // Masquerading with iptables leaves nothing for the table
+        manager.set_nat(NatMode::Iptables);
+        assert_eq!(manager.firewall(), None);
+
+        let rule = || Rule::new("engineering".into(), "10.0.0.0/24".parse().unwrap(), vec![]);
+        // A group nobody is in, that has no settings, would match nothing
+        assert!(matches!(
+            manager.add_rule(rule()),
+            Err(ManagerError::GroupNotFoundError(_))
+        ));
+        manager
+            .set_group("engineering".into(), Group::default())
+            .unwrap();
+        manager.add_rule(rule()).unwrap();
+        assert!(manager.firewall().unwrap().contains("chain forward"));
+    }
+
+    #[test]
+    fn test_subnets() {
+        let mut manager = manager();
+        let edit = |subnet: &str| ClientEdit {
+            add_subnets: vec![subnet.parse().unwrap()],
+            ..ClientEdit::default()
+        };
+
+        manager
+            .edit_client("bob", edit("192.168.50.1/24"), 0)
+            .unwrap();
+        assert_eq!(
+            manager.client("bob").unwrap().allowed_ips(),
+            vec![
+                "10.0.0.3/32".parse::<IpNet>().unwrap(),
+                "192.168.50.0/24".parse().unwrap()
+            ]
+        );
+
+        assert!(matches!(
+            manager.edit_client("carol", edit("10.0.0.128/25"), 0),
+            Err(ManagerError::SubnetOverlapError { .. })
+        ));
+        assert!(matches!(
+            manager.edit_client("carol", edit("192.168.0.0/16"), 0),
+            Err(ManagerError::SubnetOverlapError { .. })
+        ));
+        // A client's own subnets don't overlap themselves
+        manager
+            .edit_client("bob", edit("192.168.50.0/24"), 0)
+            .unwrap();
+        assert_eq!(manager.client("bob").unwrap().subnets().len(), 1);
+    }
+
+    #[test]
+    fn test_client_config_subnets() {
+        let mut manager = manager();
+        manager.clients.get_mut("alice").unwrap().groups = vec!["engineering".into()];
+        manager.clients.get_mut("bob").unwrap().groups = vec!["branch".into()];
+        manager.clients.get_mut("bob").unwrap().subnets = vec!["192.168.50.0/24".parse().unwrap()];
+        manager.clients.get_mut("carol").unwrap().groups = vec!["branch".into()];
+        manager.clients.get_mut("carol").unwrap().subnets =
+            vec!["192.168.60.0/24".parse().unwrap()];
+        let allowed_ips = |manager: &Manager, name: &str| -> Vec<String> {
+            manager
+                .client_config(manager.client(name).unwrap(), None)
+                .allowed_ips
+                .iter()
+                .map(IpNet::to_string)
+                .collect()
+        };
+
+        // Without a policy, subnets are only routed on the server
+        assert_eq!(allowed_ips(&manager, "alice"), vec!["10.0.0.0/24"]);
+
+        manager
+            .add_rule(Rule::new(
+                "engineering".into(),
+                "branch".parse().unwrap(),
+                vec![],
+            ))
+            .unwrap();
+        assert_eq!(
+            allowed_ips(&manager, "alice"),
+            vec!["10.0.0.0/24", "192.168.50.0/24", "192.168.60.0/24"]
+        );
+        // Branches aren't allowed to reach each other
+        assert_eq!(allowed_ips(&manager, "bob"), vec!["10.0.0.0/24"]);
+
+        manager
+            .add_rule(Rule::new(
+                "branch".into(),
+                "192.168.60.0/24".parse().unwrap(),
+                vec![],
+            ))
+            .unwrap();
+        assert_eq!(
+            allowed_ips(&manager, "bob"),
+            vec!["10.0.0.0/24", "192.168.60.0/24"]
+        );
+        // The reverse route, so that carol's LAN can reply to bob's
+        assert_eq!(
+            allowed_ips(&manager, "carol"),
+            vec!["10.0.0.0/24", "192.168.50.0/24"]
+        );
+    }
+
+    #[test]
+    fn test_full_tunnel_clients() {
+        let mut manager = manager();
+        assert!(manager.full_tunnel_clients().is_empty());
+
+        manager
+            .set_group(
+                "everything".into(),
+                Group::new(
+                    vec!["0.0.0.0/0".parse().unwrap()],
+                    vec![],
+                    Tuning::default(),
+                    vec![],
+                ),
+            )
+            .unwrap();
+        manager.clients.get_mut("bob").unwrap().groups = vec!["everything".into()];
+        manager.clients.get_mut("carol").unwrap().route_all_except =
+            vec!["192.168.0.0/16".parse().unwrap()];
+        let names: Vec<&String> = manager
+            .full_tunnel_clients()
+            .into_iter()
+            .map(Client::name)
+            .collect();
+        assert_eq!(names, vec!["bob", "carol"]);
+    }
+
+    #[test]
+    fn test_client_config_split_tunnel() {
+        let mut manager = manager();
+        manager
+            .set_group(
+                "home".into(),
+                Group::new(
+                    vec![],
+                    vec![],
+                    Tuning::default(),
+                    vec!["10.0.0.0/8".parse().unwrap()],
+                ),
+            )
+            .unwrap();
+        let alice = manager.clients.get_mut("alice").unwrap();
+        alice.groups = vec!["home".into()];
+        alice.route_all_except = vec!["fd00::/8".parse().unwrap()];
+
+        let alice = manager.client("alice").unwrap();
+        let allowed_ips = manager.client_config(alice, None).allowed_ips;
+        let routed = |ip: &str| {
+            let ip: std::net::IpAddr = ip.parse().unwrap();
+            allowed_ips
+                .iter()
+                .any(|allowed_ip| allowed_ip.contains(&ip))
+        };
+
+        // The VPN's own range is routed even inside an excluded range, the server never is
+        assert!(routed("10.0.0.9"));
+        assert!(!routed("10.1.0.1"));
+        assert!(!routed("1.2.3.4"));
+        assert!(routed("1.2.3.5"));
+        assert!(routed("8.8.8.8"));
+        assert!(routed("2001:db8::1"));
+        assert!(!routed("fd12::1"));
+        assert_eq!(allowed_ips, IpNet::aggregate(&allowed_ips));
+    }
+
+    #[test]
+    fn test_dns() {
+        let mut manager = manager();
+        let dns = |server: &str| {
+            Dns::new(
+                "vpn.example.com",
+                server.parse().unwrap(),
+                dns::DnsFormat::Hosts,
+                "/etc/hosts.d/wg0".into(),
+            )
+            .unwrap()
+        };
+        assert!(matches!(
+            manager.set_dns(Some(dns("10.1.0.1"))),
+            Err(ManagerError::DnsError(DnsError::ServerOutOfRange { .. }))
+        ));
+        manager.set_dns(Some(dns("10.0.0.1"))).unwrap();
+
+        manager.clients.get_mut("bob").unwrap().name = "Bob_Laptop".into();
+        manager.clients.get_mut("carol").unwrap().disabled = Some(Disabled {
+            reason: None,
+            at: 1000,
+        });
+        assert_eq!(
+            manager.dns_files()[0].contents,
+            "10.0.0.1\twg0.vpn.example.com\n\
+             10.0.0.2\talice.vpn.example.com\n"
+        );
+        assert_eq!(manager.dns_skipped(), ["Bob_Laptop"]);
+
+        // Clients ask the server first, and look short names up in the VPN's domain
+        let dns = vec!["1.1.1.1".parse().unwrap(), "10.0.0.1".parse().unwrap()];
+        manager
+            .set_group(
+                "dns".into(),
+                Group::new(vec![], dns, Tuning::default(), vec![]),
+            )
+            .unwrap();
+        manager.clients.get_mut("alice").unwrap().groups = vec!["dns".into()];
+        let config = manager.client_config(manager.client("alice").unwrap(), None);
+        assert_eq!(
+            config.dns,
+            [
+                "10.0.0.1".parse::<IpAddr>().unwrap(),
+                "1.1.1.1".parse().unwrap()
+            ]
+        );
+        assert_eq!(config.search_domains, ["vpn.example.com"]);
+    }
+
+    #[test]
+    fn test_key_older_than() {
+        let mut client = Client::new("alice".into(), "A=".into(), Ipv4Addr::new(10, 0, 0, 2));
+        assert!(client.key_older_than(180 * 86400, 1000));
+
+        client.key_created_at = Some(1000);
+        assert!(!client.key_older_than(60, 1060));
+        assert!(client.key_older_than(60, 1061));
+    }
+
+    #[test]
+    fn test_clients_to_rotate() {
+        let mut manager = manager();
+        manager.clients.get_mut("alice").unwrap().key_created_at = Some(1000);
+        manager.clients.get_mut("bob").unwrap().key_created_at = Some(2000);
+
+        // Without an age every client is rotated, even one whose key was created this second
+        assert_eq!(
+            manager.clients_to_rotate(None, 2000),
+            ["alice", "bob", "carol"]
+        );
+        assert_eq!(
+            manager.clients_to_rotate(Some(500), 2000),
+            ["alice", "carol"]
+        );
+        assert_eq!(manager.clients_to_rotate(Some(0), 2000), ["alice", "carol"]);
+    }
+
+    #[test]
+    fn test_new_client_ip() {
+        let mut manager = manager();
+        let options = || ClientOptions {
+            public_key: Some("YNqHbfBQKaGvzefSSTlTb4rFNH5DF9UQ0UV0YYuG1XU=".into()),
+            ..ClientOptions::default()
+        };
+        let new_client = |manager: &mut Manager, ip: &str| {
+            manager
+                .new_client("dave".into(), ip.parse().unwrap(), options())
+                .map(|_| ())
+        };
+
+        for ip in ["10.0.1.2", "10.0.0.0", "10.0.0.255"] {
+            assert!(matches!(
+                new_client(&mut manager, ip),
+                Err(ManagerError::IpOutOfRangeError { .. })
+            ));
+        }
+        let taken = |result| match result {
+            Err(ManagerError::IpTakenError { by, .. }) => by,
+            other => panic!("expected the ip to be taken, got {:?}", other),
+        };
+        assert_eq!(taken(new_client(&mut manager, "10.0.0.1")), "the server");
+        assert_eq!(taken(new_client(&mut manager, "10.0.0.3")), "client 'bob'");
+
+        let now = utils::unix_now();
+        manager
+            .new_invite(
+                "erin".into(),
+                "10.0.0.5".parse().unwrap(),
+                vec![],
+                now,
+                3600,
+            )
+            .unwrap();
+        assert_eq!(
+            taken(new_client(&mut manager, "10.0.0.5")),
+            "the invite for 'erin'"
+        );
+        assert!(matches!(
+            manager.new_invite(
+                "frank".into(),
+                "10.0.0.2".parse().unwrap(),
+                vec![],
+                now,
+                3600
+            ),
+            Err(ManagerError::IpTakenError { .. })
+        ));
+
+        new_client(&mut manager, "10.0.0.6").unwrap();
+        assert_eq!(
+            manager.client("dave").unwrap().ip(),
+            Ipv4Addr::new(10, 0, 0, 6)
+        );
+    }
+
+    #[test]
+    fn test_invalid_names() {
+        let mut manager = manager();
+        let options = ClientOptions {
+            public_key: Some("YNqHbfBQKaGvzefSSTlTb4rFNH5DF9UQ0UV0YYuG1XU=".into()),
+            ..ClientOptions::default()
+        };
+        assert!(matches!(
+            manager.new_client(
+                "../../etc/cron.d/x".into(),
+                "10.0.0.9".parse().unwrap(),
+                options
+            ),
+            Err(ManagerError::InvalidNameError(_))
+        ));
+        assert!(matches!(
+            manager.new_invite(
+                ".hidden".into(),
+                "10.0.0.9".parse().unwrap(),
+                vec![],
+                utils::unix_now(),
+                3600
+            ),
+            Err(ManagerError::InvalidNameError(_))
+        ));
+        assert!(matches!(
+            manager.new_mesh(
+                "a/b".into(),
+                Mesh::new("10.1.0.0/24".parse().unwrap(), None)
+            ),
+            Err(ManagerError::InvalidNameError(_))
+        ));
+
+        // Group names go into nftables rulesets
+        assert!(matches!(
+            manager.set_group("ops team".into(), Group::default()),
+            Err(ManagerError::InvalidNameError(_))
+        ));
+        let edit = ClientEdit {
+            add_groups: vec!["ops\"; flush ruleset".into()],
+            ..ClientEdit::default()
+        };
+        assert!(matches!(
+            manager.edit_client("alice", edit, 0),
+            Err(ManagerError::InvalidNameError(_))
+        ));
+        assert!(manager.client("alice").unwrap().groups().is_empty());
+    }
+
+    #[test]
+    fn test_commit_expires_clients() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // A wg with an interface that has no peers yet, logging what it's asked to set
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        let binary = dir.path().join("wg");
+        std::fs::write(
+            &binary,
+            format!(
+                "#!/bin/sh\n\
+                 [ \"$1 $3\" = \"show listen-port\" ] && echo 51900 && exit 0\n\
+                 [ \"$1 $3\" = \"show fwmark\" ] && echo off && exit 0\n\
+                 [ \"$1\" = show ] && exit 0\n\
+                 echo \"$@\" >> {log}\n",
+                log = log.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&binary, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut manager = manager();
+        manager.wg = Wg::new(binary.to_string_lossy().into_owned());
+        manager.clients.get_mut("alice").unwrap().expires_at = Some(1000);
+
+        manager.commit(1000).unwrap();
+        assert!(!manager.client("alice").unwrap().is_enabled());
+        let log = std::fs::read_to_string(&log).unwrap();
+        assert!(!log.contains("peer A= "), "{}", log);
+        assert!(log.contains("peer B= "), "{}", log);
+    }
+
+    #[test]
+    fn test_preshared_keys() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // A wg that generates a fixed preshared key, and logs what it's asked to set along with
+        // the contents of any key file
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        let binary = dir.path().join("wg");
+        std::fs::write(
+            &binary,
+            format!(
+                "#!/bin/sh\n\
+                 [ \"$1\" = genpsk ] && echo NEWPSK= && exit 0\n\
+                 echo \"$@\" >> {log}\n\
+                 for arg; do [ -f \"$arg\" ] && cat \"$arg\" >> {log}; done\n\
+                 exit 0\n",
+                log = log.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&binary, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut manager = manager();
+        manager.wg = Wg::new(binary.to_string_lossy().into_owned());
+        let options = |preshared_key| ClientOptions {
+            public_key: Some("YNqHbfBQKaGvzefSSTlTb4rFNH5DF9UQ0UV0YYuG1XU=".into()),
+            preshared_key,
+            ..ClientOptions::default()
+        };
+
+        // Clients only get a preshared key when asked, unless that's the default
+        let (dave, _) = manager
+            .new_client("dave".into(), "10.0.0.10".parse().unwrap(), options(None))
+            .unwrap();
+        assert_eq!(dave.preshared_key(), None);
+        manager.set_default_psk(true);
+        manager.delete_client("dave").unwrap();
+        let (dave, _) = manager
+            .new_client("dave".into(), "10.0.0.10".parse().unwrap(), options(None))
+            .unwrap();
+        assert_eq!(dave.preshared_key().map(String::as_str), Some("NEWPSK="));
+        manager.delete_client("dave").unwrap();
+        let (dave, _) = manager
+            .new_client(
+                "dave".into(),
+                "10.0.0.10".parse().unwrap(),
+                options(Some(false)),
+            )
+            .unwrap();
+        assert_eq!(dave.preshared_key(), None);
+
+        // Rotating replaces only the preshared key
+        let bob = manager.rotate_preshared_key("bob", 1000).unwrap();
+        assert_eq!(bob.preshared_key().map(String::as_str), Some("NEWPSK="));
+        assert_eq!(bob.public_key(), "B=");
+        assert_eq!(bob.updated_at, Some(1000));
+        assert!(matches!(
+            manager.rotate_preshared_key("nobody", 1000),
+            Err(ManagerError::ClientNotFoundError(_))
+        ));
+
+        let alice = manager.client("alice").unwrap();
+        let config = manager.client_config(alice, None);
+        assert_eq!(config.preshared_key.as_deref(), Some("PA="));
+        assert!(config.to_wg_quick().contains("\nPresharedKey = PA=\n"));
+
+        // Keys are handed to wg in a file, and removed by setting /dev/null
+        manager
+            .apply(&[
+                Change::SetPresharedKey {
+                    name: "bob".into(),
+                    public_key: "B=".into(),
+                    preshared_key: Some("NEWPSK=".into()),
+                },
+                Change::SetPresharedKey {
+                    name: "carol".into(),
+                    public_key: "C=".into(),
+                    preshared_key: None,
+                },
+            ])
+            .unwrap();
+        let log = std::fs::read_to_string(&log).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 3, "{}", log);
+        assert!(lines[0].starts_with("set wg0 peer B= preshared-key /"));
+        assert_eq!(lines[1], "NEWPSK=");
+        assert_eq!(lines[2], "set wg0 peer C= preshared-key /dev/null");
     }
 }
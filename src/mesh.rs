@@ -0,0 +1,356 @@
+/// Full-mesh networks, in which every node is a peer of every other.
+///
+/// Meshes stand apart from the hub-and-spoke VPN that the rest of the config describes: their
+/// nodes (servers in different datacenters, say) aren't peers of the server's interface, and
+/// `wg-manager` only keeps track of them and renders each node's config. Adding or removing a node
+/// changes every other node's config, so the configs of the whole mesh are rendered every time.
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Write},
+    net::Ipv4Addr,
+};
+
+use ipnet::Ipv4Net;
+use serde::{Deserialize, Serialize};
+
+use crate::client_config::PRIVATE_KEY_PLACEHOLDER;
+use crate::utils::{deserialize_ipv4net, serialize_ipv4net};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MeshError {
+    NodeExists(String),
+    NodeNotFound(String),
+    IpOutOfRange { ip: Ipv4Addr, ip_range: Ipv4Net },
+    IpTaken { ip: Ipv4Addr, node: String },
+    InvalidEndpoint(String),
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MeshError::NodeExists(name) => write!(f, "node with name '{}' already exists", name),
+            MeshError::NodeNotFound(name) => {
+                write!(f, "node with name '{}' does not exist", name)
+            }
+            MeshError::IpOutOfRange { ip, ip_range } => {
+                write!(f, "{} is not in the mesh's range {}", ip, ip_range)
+            }
+            MeshError::IpTaken { ip, node } => {
+                write!(f, "{} already belongs to node '{}'", ip, node)
+            }
+            MeshError::InvalidEndpoint(endpoint) => {
+                write!(f, "invalid endpoint '{}', expected <host>:<port>", endpoint)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Mesh {
+    #[serde(
+        serialize_with = "serialize_ipv4net",
+        deserialize_with = "deserialize_ipv4net"
+    )]
+    ip_range: Ipv4Net,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    persistent_keepalive: Option<u16>,
+    #[serde(default)]
+    nodes: BTreeMap<String, Node>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Node {
+    ip: Ipv4Addr,
+    /// Where the other nodes reach this one, as `<host>:<port>`, the port being its listen port
+    endpoint: String,
+    public_key: String,
+    /// `None` if the node brought its own key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    private_key: Option<String>,
+}
+
+impl Node {
+    pub fn new(
+        ip: Ipv4Addr,
+        endpoint: String,
+        public_key: String,
+        private_key: Option<String>,
+    ) -> Result<Self, MeshError> {
+        listen_port(&endpoint)?;
+        Ok(Node {
+            ip,
+            endpoint,
+            public_key,
+            private_key,
+        })
+    }
+
+    pub fn ip(&self) -> Ipv4Addr {
+        self.ip
+    }
+
+    pub fn endpoint(&self) -> &String {
+        &self.endpoint
+    }
+
+    pub fn public_key(&self) -> &String {
+        &self.public_key
+    }
+
+    pub fn private_key(&self) -> Option<&String> {
+        self.private_key.as_ref()
+    }
+}
+
+impl Mesh {
+    pub fn new(ip_range: Ipv4Net, persistent_keepalive: Option<u16>) -> Self {
+        Mesh {
+            ip_range: ip_range.trunc(),
+            persistent_keepalive,
+            nodes: BTreeMap::new(),
+        }
+    }
+
+    pub fn ip_range(&self) -> Ipv4Net {
+        self.ip_range
+    }
+
+    pub fn persistent_keepalive(&self) -> Option<u16> {
+        self.persistent_keepalive
+    }
+
+    /// Nodes, sorted by name
+    pub fn nodes(&self) -> &BTreeMap<String, Node> {
+        &self.nodes
+    }
+
+    pub fn add_node(&mut self, name: String, node: Node) -> Result<&Node, MeshError> {
+        if self.nodes.contains_key(&name) {
+            return Err(MeshError::NodeExists(name));
+        }
+        if !self.ip_range.contains(&node.ip) {
+            return Err(MeshError::IpOutOfRange {
+                ip: node.ip,
+                ip_range: self.ip_range,
+            });
+        }
+        if let Some((other, _)) = self.nodes.iter().find(|(_, other)| other.ip == node.ip) {
+            return Err(MeshError::IpTaken {
+                ip: node.ip,
+                node: other.clone(),
+            });
+        }
+
+        self.nodes.insert(name.clone(), node);
+        Ok(self.nodes.get(&name).unwrap())
+    }
+
+    pub fn remove_node(&mut self, name: &str) -> Result<Node, MeshError> {
+        self.nodes
+            .remove(name)
+            .ok_or_else(|| MeshError::NodeNotFound(name.to_owned()))
+    }
+
+    /// Config for a node, with every other node as a peer
+    pub fn node_config(&self, name: &str) -> Result<NodeConfig, MeshError> {
+        let node = self
+            .nodes
+            .get(name)
+            .ok_or_else(|| MeshError::NodeNotFound(name.to_owned()))?;
+
+        Ok(NodeConfig {
+            private_key: node.private_key.clone(),
+            address: Ipv4Net::new(node.ip, self.ip_range.prefix_len())
+                .expect("the prefix length comes from a valid network"),
+            listen_port: listen_port(&node.endpoint)?,
+            peers: self
+                .nodes
+                .iter()
+                .filter(|(other, _)| *other != name)
+                .map(|(other, peer)| NodePeer {
+                    name: other.clone(),
+                    public_key: peer.public_key.clone(),
+                    endpoint: peer.endpoint.clone(),
+                    allowed_ips: vec![Ipv4Net::from(peer.ip)],
+                    persistent_keepalive: self.persistent_keepalive,
+                })
+                .collect(),
+        })
+    }
+
+    /// Every node's config, rendered for `wg-quick`, by node name
+    pub fn configs(&self) -> BTreeMap<String, String> {
+        self.nodes
+            .keys()
+            .filter_map(|name| {
+                let config = self.node_config(name).ok()?;
+                Some((name.clone(), config.to_wg_quick()))
+            })
+            .collect()
+    }
+}
+
+/// The port of a `<host>:<port>` endpoint
+fn listen_port(endpoint: &str) -> Result<u16, MeshError> {
+    endpoint
+        .rsplit_once(':')
+        .filter(|(host, _)| !host.is_empty())
+        .and_then(|(_, port)| port.parse::<u16>().ok())
+        .filter(|port| *port != 0)
+        .ok_or_else(|| MeshError::InvalidEndpoint(endpoint.to_owned()))
+}
+
+/// Everything needed to render a node's config
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeConfig {
+    /// `None` if only the node knows its private key
+    pub private_key: Option<String>,
+    pub address: Ipv4Net,
+    pub listen_port: u16,
+    pub peers: Vec<NodePeer>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodePeer {
+    pub name: String,
+    pub public_key: String,
+    pub endpoint: String,
+    pub allowed_ips: Vec<Ipv4Net>,
+    pub persistent_keepalive: Option<u16>,
+}
+
+impl NodeConfig {
+    /// Renders the config in the format read by `wg-quick`
+    pub fn to_wg_quick(&self) -> String {
+        let mut out = String::new();
+
+        // Writing to a `String` cannot fail
+        let _ = writeln!(out, "[Interface]");
+        let _ = writeln!(
+            out,
+            "PrivateKey = {}",
+            self.private_key
+                .as_deref()
+                .unwrap_or(PRIVATE_KEY_PLACEHOLDER)
+        );
+        let _ = writeln!(out, "Address = {}", self.address);
+        let _ = writeln!(out, "ListenPort = {}", self.listen_port);
+
+        for peer in &self.peers {
+            let allowed_ips: Vec<String> =
+                peer.allowed_ips.iter().map(Ipv4Net::to_string).collect();
+            let _ = writeln!(out);
+            let _ = writeln!(out, "# {}", peer.name);
+            let _ = writeln!(out, "[Peer]");
+            let _ = writeln!(out, "PublicKey = {}", peer.public_key);
+            let _ = writeln!(out, "Endpoint = {}", peer.endpoint);
+            let _ = writeln!(out, "AllowedIPs = {}", allowed_ips.join(", "));
+            if let Some(persistent_keepalive) = peer.persistent_keepalive {
+                let _ = writeln!(out, "PersistentKeepalive = {}", persistent_keepalive);
+            }
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(ip: &str, endpoint: &str, key: &str) -> Node {
+        Node::new(
+            ip.parse().unwrap(),
+            endpoint.into(),
+            format!("{}-public", key),
+            Some(format!("{}-private", key)),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_node_config() {
+        let mut mesh = Mesh::new("10.1.0.0/24".parse().unwrap(), Some(25));
+        mesh.add_node(
+            "ams".into(),
+            node("10.1.0.1", "ams.example.com:51820", "ams"),
+        )
+        .unwrap();
+        mesh.add_node("fra".into(), node("10.1.0.2", "192.0.2.7:51821", "fra"))
+            .unwrap();
+
+        assert_eq!(
+            mesh.node_config("ams").unwrap().to_wg_quick(),
+            "[Interface]\n\
+             PrivateKey = ams-private\n\
+             Address = 10.1.0.1/24\n\
+             ListenPort = 51820\n\
+             \n\
+             # fra\n\
+             [Peer]\n\
+             PublicKey = fra-public\n\
+             Endpoint = 192.0.2.7:51821\n\
+             AllowedIPs = 10.1.0.2/32\n\
+             PersistentKeepalive = 25\n"
+        );
+
+        // A new node shows up in every existing node's config
+        mesh.add_node(
+            "nyc".into(),
+            node("10.1.0.3", "nyc.example.com:51820", "nyc"),
+        )
+        .unwrap();
+        let configs = mesh.configs();
+        assert_eq!(configs.len(), 3);
+        for (name, config) in &configs {
+            assert_eq!(config.matches("[Peer]").count(), 2, "{}", name);
+        }
+        assert!(configs["ams"].contains("# nyc\n"));
+        assert!(configs["fra"].contains("# nyc\n"));
+    }
+
+    #[test]
+    fn test_add_node() {
+        let mut mesh = Mesh::new("10.1.0.0/24".parse().unwrap(), None);
+        mesh.add_node(
+            "ams".into(),
+            node("10.1.0.1", "ams.example.com:51820", "ams"),
+        )
+        .unwrap();
+
+        assert_eq!(
+            mesh.add_node("ams".into(), node("10.1.0.2", "a:1", "a"))
+                .unwrap_err(),
+            MeshError::NodeExists("ams".into())
+        );
+        assert!(matches!(
+            mesh.add_node("b".into(), node("10.2.0.1", "b:1", "b")),
+            Err(MeshError::IpOutOfRange { .. })
+        ));
+        assert!(matches!(
+            mesh.add_node("c".into(), node("10.1.0.1", "c:1", "c")),
+            Err(MeshError::IpTaken { .. })
+        ));
+        for endpoint in ["nyc.example.com", ":51820", "nyc:0", "nyc:port"] {
+            assert!(
+                Node::new(
+                    "10.1.0.9".parse().unwrap(),
+                    endpoint.into(),
+                    "k".into(),
+                    None
+                )
+                .is_err(),
+                "{}",
+                endpoint
+            );
+        }
+        // IPv6 endpoints are bracketed, so the port comes after the last colon
+        assert!(Node::new(
+            "10.1.0.9".parse().unwrap(),
+            "[2001:db8::1]:51820".into(),
+            "k".into(),
+            None
+        )
+        .is_ok());
+    }
+}
@@ -0,0 +1,275 @@
+/// Prometheus metrics for the interface and its peers.
+///
+/// Per-peer series are labelled with the client's name, peers that aren't configured are only
+/// counted, since a public key on its own means nothing to whoever is reading the dashboard.
+use std::fmt::Write;
+
+use crate::manager::Manager;
+use crate::status::{PeerState, PeerStatus};
+
+/// Renders metrics in the Prometheus text exposition format.
+///
+/// `statuses` is `None` if the live state of the interface could not be read, in which case only
+/// metrics derived from the config are rendered, and `wg_manager_up` is `0`.
+pub fn render(
+    manager: &Manager,
+    statuses: Option<&[PeerStatus]>,
+    now: u64,
+    online_threshold: u64,
+) -> String {
+    let mut out = String::new();
+    let interface = escape_label(manager.interface_name());
+
+    let pool_size = manager.ip_range().hosts().count();
+    let pool_used = manager
+        .clients()
+        .iter()
+        .filter(|client| manager.ip_range().contains(&client.ip()))
+        .count();
+
+    metric_header(
+        &mut out,
+        "up",
+        "gauge",
+        "Whether the interface could be read",
+    );
+    sample(&mut out, "up", &interface, &[], statuses.is_some() as u64);
+
+    metric_header(&mut out, "clients", "gauge", "Number of configured clients");
+    sample(
+        &mut out,
+        "clients",
+        &interface,
+        &[],
+        manager.clients().len(),
+    );
+
+    metric_header(
+        &mut out,
+        "ip_pool_size",
+        "gauge",
+        "Usable addresses in the ip range",
+    );
+    sample(&mut out, "ip_pool_size", &interface, &[], pool_size);
+
+    metric_header(
+        &mut out,
+        "ip_pool_used",
+        "gauge",
+        "Addresses in the ip range assigned to clients",
+    );
+    sample(&mut out, "ip_pool_used", &interface, &[], pool_used);
+
+    metric_header(
+        &mut out,
+        "ip_pool_utilization_ratio",
+        "gauge",
+        "Fraction of the ip range assigned to clients",
+    );
+    let utilization = if pool_size == 0 {
+        0.0
+    } else {
+        pool_used as f64 / pool_size as f64
+    };
+    sample(
+        &mut out,
+        "ip_pool_utilization_ratio",
+        &interface,
+        &[],
+        utilization,
+    );
+
+    let statuses = match statuses {
+        Some(statuses) => statuses,
+        None => return out,
+    };
+
+    metric_header(&mut out, "peers", "gauge", "Number of peers by state");
+    for state in &[
+        PeerState::Online,
+        PeerState::Offline,
+        PeerState::Missing,
+        PeerState::Unknown,
+    ] {
+        let count = statuses
+            .iter()
+            .filter(|status| status.state(now, online_threshold) == *state)
+            .count();
+        sample(
+            &mut out,
+            "peers",
+            &interface,
+            &[("state", state.to_string())],
+            count,
+        );
+    }
+
+    // Everything below is per configured client that is on the interface
+    let live: Vec<(String, &PeerStatus)> = statuses
+        .iter()
+        .filter(|status| status.peer.is_some())
+        .filter_map(|status| {
+            status
+                .client
+                .map(|client| (escape_label(client.name()), status))
+        })
+        .collect();
+
+    metric_header(
+        &mut out,
+        "peer_receive_bytes_total",
+        "counter",
+        "Bytes received from the peer",
+    );
+    for (name, status) in &live {
+        let rx_bytes = status.peer.as_ref().map_or(0, |peer| peer.rx_bytes);
+        sample(
+            &mut out,
+            "peer_receive_bytes_total",
+            &interface,
+            &[("name", name.clone())],
+            rx_bytes,
+        );
+    }
+
+    metric_header(
+        &mut out,
+        "peer_transmit_bytes_total",
+        "counter",
+        "Bytes sent to the peer",
+    );
+    for (name, status) in &live {
+        let tx_bytes = status.peer.as_ref().map_or(0, |peer| peer.tx_bytes);
+        sample(
+            &mut out,
+            "peer_transmit_bytes_total",
+            &interface,
+            &[("name", name.clone())],
+            tx_bytes,
+        );
+    }
+
+    metric_header(
+        &mut out,
+        "peer_last_handshake_age_seconds",
+        "gauge",
+        "Seconds since the latest handshake, absent if there has never been one",
+    );
+    for (name, status) in &live {
+        if let Some(age) = status.handshake_age(now) {
+            sample(
+                &mut out,
+                "peer_last_handshake_age_seconds",
+                &interface,
+                &[("name", name.clone())],
+                age,
+            );
+        }
+    }
+
+    metric_header(
+        &mut out,
+        "peer_online",
+        "gauge",
+        "Whether the peer has handshaked within the online threshold",
+    );
+    for (name, status) in &live {
+        let online = status.state(now, online_threshold) == PeerState::Online;
+        sample(
+            &mut out,
+            "peer_online",
+            &interface,
+            &[("name", name.clone())],
+            online as u64,
+        );
+    }
+
+    metric_header(
+        &mut out,
+        "peer_allowed_ips_drift",
+        "gauge",
+        "Whether the peer's allowed ips on the interface differ from the config",
+    );
+    for (name, status) in &live {
+        let drift = match (status.client, &status.peer) {
+            (Some(client), Some(peer)) => {
+                let mut allowed_ips = peer.allowed_ips.clone();
+                allowed_ips.sort();
+                allowed_ips != client.allowed_ips()
+            }
+            _ => false,
+        };
+        sample(
+            &mut out,
+            "peer_allowed_ips_drift",
+            &interface,
+            &[("name", name.clone())],
+            drift as u64,
+        );
+    }
+
+    out
+}
+
+fn metric_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    // Writing to a `String` cannot fail
+    let _ = writeln!(out, "# HELP wg_manager_{} {}", name, help);
+    let _ = writeln!(out, "# TYPE wg_manager_{} {}", name, kind);
+}
+
+fn sample(
+    out: &mut String,
+    name: &str,
+    interface: &str,
+    labels: &[(&str, String)],
+    value: impl std::fmt::Display,
+) {
+    let labels: String = labels
+        .iter()
+        .map(|(key, value)| format!(",{}=\"{}\"", key, value))
+        .collect();
+    let _ = writeln!(
+        out,
+        "wg_manager_{}{{interface=\"{}\"{}}} {}",
+        name, interface, labels, value
+    );
+}
+
+/// Escapes a label value as required by the exposition format
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_label() {
+        assert_eq!(
+            escape_label("alice \"laptop\"\n\\"),
+            "alice \\\"laptop\\\"\\n\\\\"
+        );
+    }
+
+    #[test]
+    fn test_sample() {
+        let mut out = String::new();
+
+        sample(
+            &mut out,
+            "peer_online",
+            "wg0",
+            &[("name", "alice".into())],
+            1,
+        );
+
+        assert_eq!(
+            out,
+            "wg_manager_peer_online{interface=\"wg0\",name=\"alice\"} 1\n"
+        );
+    }
+}
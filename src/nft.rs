@@ -0,0 +1,124 @@
+/// Minimal bindings to the `nft` binary, for the firewall rules that go along with the interface.
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::utils::{self, CommandError};
+
+/// Prefix of the tables that hold every rule `wg-manager` loads, in the `inet` family
+const TABLE_PREFIX: &str = "wg_manager";
+
+/// The table for an interface's rules, e.g. `wg_manager_wg0`.
+///
+/// Each interface has a table of its own, so that each can be replaced without touching the
+/// others. Characters that nft doesn't allow in names become `_`, so `Config::add_interface`
+/// refuses interfaces whose tables would be the same, like `wg-0` and `wg_0`.
+pub fn table(interface: &str) -> String {
+    let interface: String = interface
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c,
+            false => '_',
+        })
+        .collect();
+    format!("{}_{}", TABLE_PREFIX, interface)
+}
+
+/// A ruleset that replaces `table` with one containing `body` (sets and chains, indented once)
+pub fn replace_table(table: &str, body: &str) -> String {
+    // Declaring then deleting the table means it is replaced, whether or not it exists
+    format!(
+        "table inet {0}\ndelete table inet {0}\ntable inet {0} {{\n{1}}}\n",
+        table, body
+    )
+}
+
+/// An error from invoking the `nft` binary, recording the command line that was run
+#[derive(Debug)]
+pub enum NftError {
+    /// The binary could not be found
+    NotInstalled { command: String },
+    /// The command exited with a non-zero status
+    CommandFailed {
+        command: String,
+        code: Option<i32>,
+        stderr: String,
+    },
+    /// Spawning, or communicating with, the process failed
+    IOError {
+        command: String,
+        error: std::io::Error,
+    },
+}
+
+impl fmt::Display for NftError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NftError::NotInstalled { command } => write!(
+                f,
+                "`{}` failed: nft binary not found, is nftables installed?",
+                command
+            ),
+            NftError::CommandFailed {
+                command,
+                code: Some(code),
+                stderr,
+            } => write!(f, "`{}` failed (exit code {}): {}", command, code, stderr),
+            NftError::CommandFailed {
+                command, stderr, ..
+            } => write!(f, "`{}` failed (killed by signal): {}", command, stderr),
+            NftError::IOError { command, error } => write!(f, "`{}` failed: {}", command, error),
+        }
+    }
+}
+
+impl From<CommandError> for NftError {
+    fn from(error: CommandError) -> Self {
+        match error {
+            CommandError::NotInstalled { command } => NftError::NotInstalled { command },
+            CommandError::Failed {
+                command,
+                code,
+                stderr,
+            } => NftError::CommandFailed {
+                command,
+                code,
+                stderr,
+            },
+            CommandError::IOError { command, error } => NftError::IOError { command, error },
+        }
+    }
+}
+
+/// Handle to the nft binary
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Nft {
+    binary_path: String,
+}
+
+impl Default for Nft {
+    fn default() -> Self {
+        Nft {
+            binary_path: "nft".into(),
+        }
+    }
+}
+
+impl Nft {
+    /// Loads a ruleset, which nft does atomically: either all of it applies or none of it does
+    pub fn apply(&self, ruleset: &str) -> Result<(), NftError> {
+        self.run(&["-f", "-"], Some(ruleset.as_bytes()))
+    }
+
+    /// Deletes a table, doing nothing if it doesn't exist
+    pub fn delete_table(&self, family: &str, name: &str) -> Result<(), NftError> {
+        // Declaring the table first makes deleting it succeed either way, in one transaction
+        let ruleset = format!("table {0} {1}\ndelete table {0} {1}\n", family, name);
+        self.apply(&ruleset)
+    }
+
+    fn run(&self, args: &[&str], stdin: Option<&[u8]>) -> Result<(), NftError> {
+        utils::run_command(&self.binary_path, args, stdin)?;
+        Ok(())
+    }
+}
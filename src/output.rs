@@ -0,0 +1,1713 @@
+/// Rendering of command results as tables, CSV or JSON.
+///
+/// The JSON documents produced here are part of the CLI's interface, see the README for their
+/// schema. Fields may be added, but existing fields must not be renamed or removed.
+use std::{collections::BTreeMap, net::Ipv4Addr, path::PathBuf, str::FromStr};
+
+use serde::Serialize;
+
+use crate::auth::{ApiToken, Role};
+use crate::client_config::ExportFormat;
+use crate::dns::{DnsFile, DnsFormat};
+use crate::doctor::Check;
+use crate::forwarding::NatMode;
+use crate::group::Group;
+use crate::invite::Invite;
+use crate::manager::{Change, Client, Manager};
+use crate::mesh::Mesh;
+use crate::policy::Rule;
+use crate::status::{PeerState, PeerStatus};
+use crate::tuning::Tuning;
+use crate::utils::{cli_table, format_bytes, format_duration, format_time};
+
+/// Format that command results (and errors) are printed in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Table,
+    Json,
+    Csv,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            other => Err(format!("unknown output format '{}'", other)),
+        }
+    }
+}
+
+impl OutputFormat {
+    /// The format asked for with `-o`/`--output` in raw arguments, for errors from arguments
+    /// that clap couldn't parse. The last one given wins, as it does with clap.
+    pub fn from_args(args: &[String]) -> Option<Self> {
+        args.iter().enumerate().rev().find_map(|(i, arg)| {
+            let value = match arg.as_str() {
+                "-o" | "--output" => args.get(i + 1)?.as_str(),
+                arg => arg
+                    .strip_prefix("--output=")
+                    .or_else(|| arg.strip_prefix("-o"))?,
+            };
+            value.parse().ok()
+        })
+    }
+}
+
+/// The result of a command, which can be printed in any `OutputFormat`
+pub trait Output: Serialize {
+    /// Rows for table and csv output, the first row being the header
+    fn rows(&self) -> Vec<Vec<String>>;
+
+    /// Human-readable output, defaults to an aligned table of `rows`
+    fn text(&self) -> String {
+        let rows = self.rows();
+        let table = rows
+            .iter()
+            .map(|row| row.iter().map(|cell| cell.as_str()).collect())
+            .collect();
+
+        cli_table(table).join("\n")
+    }
+}
+
+/// Prints `output` to stdout in the given format
+pub fn print(format: OutputFormat, output: &impl Output) {
+    match format {
+        OutputFormat::Table => println!("{}", output.text()),
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(output).expect("output is always serializable")
+        ),
+        OutputFormat::Csv => {
+            for row in output.rows() {
+                println!("{}", csv_row(&row));
+            }
+        }
+    }
+}
+
+/// Error document, printed to stderr in JSON mode
+#[derive(Serialize)]
+pub struct ErrorOutput<'a> {
+    pub error: ErrorBody<'a>,
+}
+
+#[derive(Serialize)]
+pub struct ErrorBody<'a> {
+    /// Process exit code
+    pub code: i32,
+    /// Stable identifier for the kind of error
+    pub kind: &'a str,
+    pub message: String,
+}
+
+/// Joins cells into a CSV line, quoting where RFC 4180 requires it
+fn csv_row(row: &[String]) -> String {
+    row.iter()
+        .map(|cell| {
+            if cell.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", cell.replace('"', "\"\""))
+            } else {
+                cell.clone()
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// Tuning as it is set, rather than inherited, with `null` or `[]` for what isn't
+#[derive(Serialize)]
+pub struct TuningView {
+    pub mtu: Option<u16>,
+    /// `0` meaning turned off
+    pub persistent_keepalive: Option<u16>,
+    /// `0` meaning turned off
+    pub fwmark: Option<u32>,
+    pub table: Option<String>,
+    pub post_up: Vec<String>,
+    pub post_down: Vec<String>,
+    /// As shown in tables
+    #[serde(skip)]
+    pub summary: String,
+}
+
+impl From<&Tuning> for TuningView {
+    fn from(tuning: &Tuning) -> Self {
+        TuningView {
+            mtu: tuning.mtu,
+            persistent_keepalive: tuning.persistent_keepalive,
+            fwmark: tuning.fwmark,
+            table: tuning.table.map(|table| table.to_string()),
+            post_up: tuning.post_up.clone(),
+            post_down: tuning.post_down.clone(),
+            summary: tuning.to_string(),
+        }
+    }
+}
+
+/// A client, as shown to the user
+#[derive(Serialize)]
+pub struct ClientView {
+    pub name: String,
+    pub public_key: String,
+    pub ip: Ipv4Addr,
+    pub groups: Vec<String>,
+    /// Networks behind the client, in CIDR notation
+    pub subnets: Vec<String>,
+    /// If any, the client's own ranges excluded from routing everything through the VPN
+    pub route_all_except: Vec<String>,
+    /// The client's own tuning, leaving aside what it inherits
+    #[serde(flatten)]
+    pub tuning: TuningView,
+    /// Whether the client has a preshared key (which is never shown here)
+    pub psk: bool,
+    pub enabled: bool,
+    /// Unix timestamp, only for disabled clients
+    pub disabled_at: Option<u64>,
+    pub disabled_reason: Option<String>,
+    /// Unix timestamp
+    pub expires_at: Option<u64>,
+    pub owner: Option<String>,
+    pub description: Option<String>,
+    pub tags: BTreeMap<String, String>,
+    /// Unix timestamps
+    pub created_at: Option<u64>,
+    pub updated_at: Option<u64>,
+    pub created_by: Option<String>,
+}
+
+impl From<&Client> for ClientView {
+    fn from(client: &Client) -> Self {
+        ClientView {
+            name: client.name().clone(),
+            public_key: client.public_key().clone(),
+            ip: client.ip(),
+            groups: client.groups().clone(),
+            subnets: client
+                .subnets()
+                .iter()
+                .map(|subnet| subnet.to_string())
+                .collect(),
+            route_all_except: client
+                .route_all_except()
+                .iter()
+                .map(|network| network.to_string())
+                .collect(),
+            tuning: TuningView::from(client.tuning()),
+            psk: client.preshared_key().is_some(),
+            enabled: client.is_enabled(),
+            disabled_at: client.disabled().map(|disabled| disabled.at()),
+            disabled_reason: client
+                .disabled()
+                .and_then(|disabled| disabled.reason().cloned()),
+            expires_at: client.expires_at(),
+            owner: client.owner().cloned(),
+            description: client.description().cloned(),
+            tags: client.tags().clone(),
+            created_at: client.created_at(),
+            updated_at: client.updated_at(),
+            created_by: client.created_by().cloned(),
+        }
+    }
+}
+
+impl ClientView {
+    fn header() -> Vec<String> {
+        vec![
+            "Name".into(),
+            "Pubkey".into(),
+            "IP".into(),
+            "Groups".into(),
+            "PSK".into(),
+            "State".into(),
+            "Expires".into(),
+            "Owner".into(),
+        ]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            self.public_key.clone(),
+            self.ip.to_string(),
+            self.groups.join(","),
+            if self.psk { "yes" } else { "no" }.into(),
+            self.state(),
+            self.expires_at
+                .map(format_time)
+                .unwrap_or_else(|| "never".into()),
+            self.owner.clone().unwrap_or_default(),
+        ]
+    }
+
+    /// Every field as a row of its own, for showing a single client in full
+    fn fields(&self) -> Vec<Vec<String>> {
+        let time = |ts: Option<u64>| ts.map(format_time).unwrap_or_default();
+        let tags: Vec<String> = self
+            .tags
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+
+        vec![
+            vec!["Name".into(), self.name.clone()],
+            vec!["Pubkey".into(), self.public_key.clone()],
+            vec!["IP".into(), self.ip.to_string()],
+            vec!["Groups".into(), self.groups.join(",")],
+            vec!["Subnets".into(), self.subnets.join(",")],
+            vec!["Route all except".into(), self.route_all_except.join(",")],
+            vec!["Tuning".into(), self.tuning.summary.clone()],
+            vec!["PSK".into(), if self.psk { "yes" } else { "no" }.into()],
+            vec!["State".into(), self.state()],
+            vec![
+                "Expires".into(),
+                self.expires_at
+                    .map(format_time)
+                    .unwrap_or_else(|| "never".into()),
+            ],
+            vec!["Owner".into(), self.owner.clone().unwrap_or_default()],
+            vec![
+                "Description".into(),
+                self.description.clone().unwrap_or_default(),
+            ],
+            vec!["Tags".into(), tags.join(",")],
+            vec!["Created".into(), time(self.created_at)],
+            vec![
+                "Created by".into(),
+                self.created_by.clone().unwrap_or_default(),
+            ],
+            vec!["Updated".into(), time(self.updated_at)],
+        ]
+    }
+
+    fn state(&self) -> String {
+        match (self.disabled_at, &self.disabled_reason) {
+            (None, _) => "enabled".into(),
+            (Some(at), None) => format!("disabled since {}", format_time(at)),
+            (Some(at), Some(reason)) => {
+                format!("disabled since {} ({})", format_time(at), reason)
+            }
+        }
+    }
+}
+
+/// Output of `server rotate-key`
+#[derive(Serialize)]
+pub struct ServerKeyOutput {
+    /// The server's new public key
+    pub public_key: String,
+    /// Whether the key is only staged, rather than in use
+    pub staged: bool,
+    /// Unix timestamp at which the daemon will switch to a staged key
+    pub switch_at: Option<u64>,
+    /// Re-issued configs for clients whose private key is stored, by client name
+    pub configs: BTreeMap<String, String>,
+    /// Clients whose private key isn't stored, who need to update the server's public key
+    /// in their config themselves
+    pub reimport: Vec<String>,
+}
+
+impl Output for ServerKeyOutput {
+    fn rows(&self) -> Vec<Vec<String>> {
+        let mut rows = vec![vec!["Client".into(), "Config".into()]];
+        rows.extend(
+            self.configs
+                .iter()
+                .map(|(name, config)| vec![name.clone(), config.clone()]),
+        );
+        rows.extend(
+            self.reimport
+                .iter()
+                .map(|name| vec![name.clone(), String::new()]),
+        );
+        rows
+    }
+
+    fn text(&self) -> String {
+        let mut text = match (self.staged, self.switch_at) {
+            (false, _) => format!("Server key rotated, new public key: {}", self.public_key),
+            (true, Some(switch_at)) => format!(
+                "Staged new server key {}, the daemon will switch to it at {}",
+                self.public_key,
+                format_time(switch_at)
+            ),
+            (true, None) => format!(
+                "Staged new server key {}, switch to it with `server rotate-key --switch`",
+                self.public_key
+            ),
+        };
+
+        if !self.configs.is_empty() {
+            text.push_str(&format!(
+                "\nRe-issued configs for: {}",
+                self.configs
+                    .keys()
+                    .cloned()
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ));
+        }
+        if !self.reimport.is_empty() {
+            text.push_str(&format!(
+                "\nThese clients must set `PublicKey = {}` under [Peer] themselves: {}",
+                self.public_key,
+                self.reimport.join(", ")
+            ));
+        }
+        text
+    }
+}
+
+/// Output of `server settings`
+#[derive(Serialize)]
+pub struct SettingsOutput {
+    pub default_psk: bool,
+    /// Seconds, `None` meaning expired clients are never deleted
+    pub expiry_grace_secs: Option<u64>,
+    pub egress_interface: Option<String>,
+    pub nat: NatMode,
+    #[serde(flatten)]
+    pub tuning: TuningView,
+}
+
+impl Output for SettingsOutput {
+    fn rows(&self) -> Vec<Vec<String>> {
+        vec![
+            vec!["Setting".into(), "Value".into()],
+            vec!["default-psk".into(), on_off(self.default_psk).into()],
+            vec![
+                "expiry-grace".into(),
+                self.expiry_grace_secs
+                    .map(format_duration)
+                    .unwrap_or_else(|| "never".into()),
+            ],
+            vec![
+                "egress-interface".into(),
+                self.egress_interface
+                    .clone()
+                    .unwrap_or_else(|| "none".into()),
+            ],
+            vec!["nat".into(), self.nat.to_string()],
+            vec!["mtu".into(), or_default(self.tuning.mtu)],
+            vec![
+                "keepalive".into(),
+                match self.tuning.persistent_keepalive {
+                    Some(0) => "off".into(),
+                    keepalive => or_default(keepalive),
+                },
+            ],
+            vec![
+                "fwmark".into(),
+                match self.tuning.fwmark {
+                    Some(0) => "off".into(),
+                    Some(fwmark) => format!("0x{:x}", fwmark),
+                    None => "default".into(),
+                },
+            ],
+            vec!["table".into(), or_default(self.tuning.table.clone())],
+            vec!["post-up".into(), self.tuning.post_up.join("; ")],
+            vec!["post-down".into(), self.tuning.post_down.join("; ")],
+        ]
+    }
+}
+
+/// Output of `server config`
+#[derive(Serialize)]
+pub struct ServerConfigOutput {
+    /// The server's config in wg-quick format
+    pub config: String,
+}
+
+impl Output for ServerConfigOutput {
+    fn rows(&self) -> Vec<Vec<String>> {
+        vec![vec!["Config".into()], vec![self.config.clone()]]
+    }
+
+    fn text(&self) -> String {
+        self.config.trim_end().to_owned()
+    }
+}
+
+/// Output of `interface down`
+#[derive(Serialize)]
+pub struct InterfaceDownOutput {
+    pub interface_name: String,
+    /// Descriptions of what was undone
+    pub changes: Vec<String>,
+}
+
+impl Output for InterfaceDownOutput {
+    fn rows(&self) -> Vec<Vec<String>> {
+        std::iter::once(vec!["Change".into()])
+            .chain(self.changes.iter().map(|change| vec![change.clone()]))
+            .collect()
+    }
+
+    fn text(&self) -> String {
+        self.changes
+            .iter()
+            .map(|change| format!("- {}", change))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+/// Output of `doctor`
+#[derive(Serialize)]
+pub struct DoctorOutput {
+    pub checks: Vec<Check>,
+}
+
+impl Output for DoctorOutput {
+    fn rows(&self) -> Vec<Vec<String>> {
+        std::iter::once(vec!["Check".into(), "Status".into(), "Message".into()])
+            .chain(self.checks.iter().map(|check| {
+                vec![
+                    check.name.into(),
+                    if check.ok { "ok" } else { "warn" }.into(),
+                    check.message.clone(),
+                ]
+            }))
+            .collect()
+    }
+}
+
+fn on_off(value: bool) -> &'static str {
+    if value {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+/// A setting that may be left to its default, as `--mtu default` and the like leave it
+fn or_default<T: ToString>(value: Option<T>) -> String {
+    value
+        .map(|value| value.to_string())
+        .unwrap_or_else(|| "default".into())
+}
+
+/// Output of `new`, and an interface in `interface list`
+#[derive(Serialize)]
+pub struct ServerOutput {
+    pub interface_name: String,
+    pub public_key: String,
+    pub endpoint: String,
+    pub ip_range: String,
+}
+
+impl ServerOutput {
+    pub fn new(manager: &Manager) -> Self {
+        ServerOutput {
+            interface_name: manager.interface_name().clone(),
+            public_key: manager.public_key().clone(),
+            endpoint: manager.endpoint().to_string(),
+            ip_range: manager.ip_range().to_string(),
+        }
+    }
+
+    fn header() -> Vec<String> {
+        vec![
+            "Interface".into(),
+            "Endpoint".into(),
+            "IP range".into(),
+            "Pubkey".into(),
+        ]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.interface_name.clone(),
+            self.endpoint.clone(),
+            self.ip_range.clone(),
+            self.public_key.clone(),
+        ]
+    }
+}
+
+impl Output for ServerOutput {
+    fn rows(&self) -> Vec<Vec<String>> {
+        vec![ServerOutput::header(), self.row()]
+    }
+}
+
+/// Output of `interface list`
+#[derive(Serialize)]
+pub struct InterfaceListOutput {
+    pub interfaces: Vec<ServerOutput>,
+}
+
+impl Output for InterfaceListOutput {
+    fn rows(&self) -> Vec<Vec<String>> {
+        std::iter::once(ServerOutput::header())
+            .chain(self.interfaces.iter().map(ServerOutput::row))
+            .collect()
+    }
+}
+
+/// Output of `client list`
+#[derive(Serialize)]
+pub struct ClientListOutput {
+    pub clients: Vec<ClientView>,
+}
+
+impl Output for ClientListOutput {
+    fn rows(&self) -> Vec<Vec<String>> {
+        std::iter::once(ClientView::header())
+            .chain(self.clients.iter().map(ClientView::row))
+            .collect()
+    }
+}
+
+/// Output of `client show`, and of the commands that change a single client
+#[derive(Serialize)]
+pub struct ClientOutput {
+    pub client: ClientView,
+}
+
+impl Output for ClientOutput {
+    fn rows(&self) -> Vec<Vec<String>> {
+        std::iter::once(vec!["Field".into(), "Value".into()])
+            .chain(self.client.fields())
+            .collect()
+    }
+}
+
+/// Output of `client new`, and of redeeming an invite
+#[derive(Serialize)]
+pub struct NewClientOutput {
+    pub client: ClientView,
+    /// Only if the keypair was generated, rather than the public key supplied
+    pub private_key: Option<String>,
+    pub config: String,
+}
+
+impl NewClientOutput {
+    fn header() -> Vec<String> {
+        let mut header = ClientView::header();
+        header.push("Private key".into());
+        header.push("Config".into());
+        header
+    }
+
+    fn row(&self) -> Vec<String> {
+        let mut row = self.client.row();
+        row.push(self.private_key.clone().unwrap_or_default());
+        row.push(self.config.clone());
+        row
+    }
+}
+
+impl Output for NewClientOutput {
+    fn rows(&self) -> Vec<Vec<String>> {
+        vec![Self::header(), self.row()]
+    }
+
+    fn text(&self) -> String {
+        match self.private_key {
+            Some(_) => format!("Here is auto-generated config:\n{}", self.config),
+            None => format!(
+                "Here is a config template, fill in your private key:\n{}",
+                self.config
+            ),
+        }
+    }
+}
+
+/// Output of `client rotate --all`
+#[derive(Serialize)]
+pub struct RotatedClientsOutput {
+    pub rotated: Vec<NewClientOutput>,
+}
+
+impl Output for RotatedClientsOutput {
+    fn rows(&self) -> Vec<Vec<String>> {
+        let mut rows = vec![NewClientOutput::header()];
+        rows.extend(self.rotated.iter().map(|rotated| rotated.row()));
+        rows
+    }
+
+    fn text(&self) -> String {
+        if self.rotated.is_empty() {
+            return "No keys needed rotating".into();
+        }
+
+        self.rotated
+            .iter()
+            .map(|rotated| format!("# {}\n{}", rotated.client.name, rotated.config))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+/// Output of `client psk`
+#[derive(Serialize)]
+pub struct PresharedKeyOutput {
+    pub client: ClientView,
+    pub preshared_key: String,
+}
+
+impl Output for PresharedKeyOutput {
+    fn rows(&self) -> Vec<Vec<String>> {
+        let mut header = ClientView::header();
+        header.push("Preshared key".into());
+
+        let mut row = self.client.row();
+        row.push(self.preshared_key.clone());
+
+        vec![header, row]
+    }
+
+    fn text(&self) -> String {
+        format!(
+            "New preshared key for '{}', replace `PresharedKey` in its config with:\n{}",
+            self.client.name, self.preshared_key
+        )
+    }
+}
+
+/// Output of `client export`
+#[derive(Serialize)]
+pub struct ExportOutput {
+    pub name: String,
+    pub format: ExportFormat,
+    /// Contents by file name
+    pub files: BTreeMap<String, String>,
+}
+
+impl Output for ExportOutput {
+    fn rows(&self) -> Vec<Vec<String>> {
+        let mut rows = vec![vec!["File".into(), "Contents".into()]];
+        rows.extend(
+            self.files
+                .iter()
+                .map(|(name, contents)| vec![name.clone(), contents.clone()]),
+        );
+        rows
+    }
+
+    fn text(&self) -> String {
+        // A lone file prints as is, so that it can be redirected into place
+        if self.files.len() == 1 {
+            return self
+                .files
+                .values()
+                .map(|contents| contents.trim_end())
+                .collect();
+        }
+
+        self.files
+            .iter()
+            .map(|(name, contents)| format!("# {}\n{}", name, contents.trim_end()))
+            .collect::<Vec<String>>()
+            .join("\n\n")
+    }
+}
+
+/// Output of `client delete`
+#[derive(Serialize)]
+pub struct DeletedClientOutput {
+    pub deleted: ClientView,
+}
+
+impl Output for DeletedClientOutput {
+    fn rows(&self) -> Vec<Vec<String>> {
+        vec![ClientView::header(), self.deleted.row()]
+    }
+
+    fn text(&self) -> String {
+        format!("Deleted client '{}'", self.deleted.name)
+    }
+}
+
+/// An invite, as shown to the user (never including the token or its hash)
+#[derive(Serialize)]
+pub struct InviteView {
+    pub name: String,
+    pub ip: Ipv4Addr,
+    pub groups: Vec<String>,
+    /// Unix timestamps
+    pub created_at: u64,
+    pub expires_at: u64,
+}
+
+impl From<&Invite> for InviteView {
+    fn from(invite: &Invite) -> Self {
+        InviteView {
+            name: invite.name().clone(),
+            ip: invite.ip(),
+            groups: invite.groups().clone(),
+            created_at: invite.created_at(),
+            expires_at: invite.expires_at(),
+        }
+    }
+}
+
+/// Output of `client invite`
+#[derive(Serialize)]
+pub struct NewInviteOutput {
+    pub invite: InviteView,
+    pub token: String,
+}
+
+impl Output for NewInviteOutput {
+    fn rows(&self) -> Vec<Vec<String>> {
+        vec![
+            vec![
+                "Name".into(),
+                "IP".into(),
+                "Groups".into(),
+                "Expires".into(),
+                "Token".into(),
+            ],
+            vec![
+                self.invite.name.clone(),
+                self.invite.ip.to_string(),
+                self.invite.groups.join(","),
+                self.invite.expires_at.to_string(),
+                self.token.clone(),
+            ],
+        ]
+    }
+
+    fn text(&self) -> String {
+        format!(
+            "Created invite for '{}', valid for {}. It will not be shown again:\n{}\n\n\
+             Redeem it with `POST /invites/redeem` on the REST API.",
+            self.invite.name,
+            format_duration(self.invite.expires_at - self.invite.created_at),
+            self.token
+        )
+    }
+}
+
+/// A client and/or live peer, as shown by `status`
+#[derive(Serialize)]
+pub struct PeerStatusView {
+    /// `None` for peers on the interface that aren't configured
+    pub name: Option<String>,
+    pub public_key: String,
+    /// `None` for peers on the interface that aren't configured
+    pub ip: Option<Ipv4Addr>,
+    pub state: PeerState,
+    pub endpoint: Option<String>,
+    /// Unix timestamp of the latest handshake
+    pub latest_handshake: Option<u64>,
+    pub handshake_age_secs: Option<u64>,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub persistent_keepalive: Option<u16>,
+}
+
+impl PeerStatusView {
+    pub fn new(status: &PeerStatus, now: u64, online_threshold: u64) -> Self {
+        let peer = status.peer.as_ref();
+
+        PeerStatusView {
+            name: status.client.map(|client| client.name().clone()),
+            public_key: status
+                .client
+                .map(|client| client.public_key().clone())
+                .or_else(|| peer.map(|peer| peer.public_key.clone()))
+                .unwrap_or_default(),
+            ip: status.client.map(|client| client.ip()),
+            state: status.state(now, online_threshold),
+            endpoint: peer
+                .and_then(|peer| peer.endpoint)
+                .map(|endpoint| endpoint.to_string()),
+            latest_handshake: peer.and_then(|peer| peer.latest_handshake),
+            handshake_age_secs: status.handshake_age(now),
+            rx_bytes: peer.map(|peer| peer.rx_bytes).unwrap_or(0),
+            tx_bytes: peer.map(|peer| peer.tx_bytes).unwrap_or(0),
+            persistent_keepalive: peer.and_then(|peer| peer.persistent_keepalive),
+        }
+    }
+}
+
+/// Output of `status` and `client list --live`
+#[derive(Serialize)]
+pub struct StatusOutput {
+    pub interface_name: String,
+    pub online_threshold_secs: u64,
+    pub peers: Vec<PeerStatusView>,
+}
+
+impl Output for StatusOutput {
+    fn rows(&self) -> Vec<Vec<String>> {
+        let header = vec![
+            "Name".into(),
+            "IP".into(),
+            "Pubkey".into(),
+            "State".into(),
+            "Endpoint".into(),
+            "Handshake".into(),
+            "RX".into(),
+            "TX".into(),
+            "Keepalive".into(),
+        ];
+        let or_dash = |value: Option<String>| value.unwrap_or_else(|| "-".into());
+
+        std::iter::once(header)
+            .chain(self.peers.iter().map(|peer| {
+                vec![
+                    or_dash(peer.name.clone()),
+                    or_dash(peer.ip.map(|ip| ip.to_string())),
+                    peer.public_key.clone(),
+                    peer.state.to_string(),
+                    or_dash(peer.endpoint.clone()),
+                    or_dash(
+                        peer.handshake_age_secs
+                            .map(|age| format!("{} ago", format_duration(age))),
+                    ),
+                    format_bytes(peer.rx_bytes),
+                    format_bytes(peer.tx_bytes),
+                    or_dash(peer.persistent_keepalive.map(|secs| format!("{}s", secs))),
+                ]
+            }))
+            .collect()
+    }
+}
+
+/// A change to the interface, as shown by `plan`
+#[derive(Serialize)]
+pub struct ChangeView {
+    /// One of `set_private_key`, `set_listen_port`, `set_fwmark`, `set_mtu`, `add_peer`,
+    /// `update_peer`, `replace_peer`, `set_preshared_key`, `remove_preshared_key`, `remove_peer`
+    pub action: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
+    /// Key that `replace_peer` swaps out
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_public_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_ips: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub listen_port: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fwmark: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mtu: Option<u16>,
+    pub description: String,
+}
+
+impl From<&Change> for ChangeView {
+    fn from(change: &Change) -> Self {
+        let mut view = ChangeView {
+            action: "",
+            name: None,
+            public_key: None,
+            old_public_key: None,
+            allowed_ips: None,
+            listen_port: None,
+            fwmark: None,
+            mtu: None,
+            description: change.to_string(),
+        };
+
+        match change {
+            Change::SetPrivateKey => view.action = "set_private_key",
+            Change::SetListenPort(port) => {
+                view.action = "set_listen_port";
+                view.listen_port = Some(*port);
+            }
+            Change::SetFwMark(fwmark) => {
+                view.action = "set_fwmark";
+                view.fwmark = Some(*fwmark);
+            }
+            Change::SetMtu(mtu) => {
+                view.action = "set_mtu";
+                view.mtu = Some(*mtu);
+            }
+            Change::AddPeer {
+                name,
+                public_key,
+                allowed_ips,
+            }
+            | Change::UpdatePeer {
+                name,
+                public_key,
+                allowed_ips,
+            } => {
+                view.action = match change {
+                    Change::AddPeer { .. } => "add_peer",
+                    _ => "update_peer",
+                };
+                view.name = Some(name.clone());
+                view.public_key = Some(public_key.clone());
+                view.allowed_ips = Some(allowed_ips.iter().map(|ip| ip.to_string()).collect());
+            }
+            Change::ReplacePeer {
+                name,
+                old_public_key,
+                public_key,
+                allowed_ips,
+                ..
+            } => {
+                view.action = "replace_peer";
+                view.name = Some(name.clone());
+                view.public_key = Some(public_key.clone());
+                view.old_public_key = Some(old_public_key.clone());
+                view.allowed_ips = Some(allowed_ips.iter().map(|ip| ip.to_string()).collect());
+            }
+            Change::SetPresharedKey {
+                name,
+                public_key,
+                preshared_key,
+            } => {
+                // The key itself is a secret, so it's never shown
+                view.action = match preshared_key {
+                    Some(_) => "set_preshared_key",
+                    None => "remove_preshared_key",
+                };
+                view.name = Some(name.clone());
+                view.public_key = Some(public_key.clone());
+            }
+            Change::RemoveDisabledPeer { name, public_key } => {
+                view.action = "remove_disabled_peer";
+                view.name = Some(name.clone());
+                view.public_key = Some(public_key.clone());
+            }
+            Change::RemovePeer { public_key } => {
+                view.action = "remove_peer";
+                view.public_key = Some(public_key.clone());
+            }
+        }
+
+        view
+    }
+}
+
+/// Output of `plan`
+#[derive(Serialize)]
+pub struct PlanOutput {
+    pub interface_name: String,
+    pub changes: Vec<ChangeView>,
+}
+
+impl Output for PlanOutput {
+    fn rows(&self) -> Vec<Vec<String>> {
+        std::iter::once(vec!["Action".into(), "Description".into()])
+            .chain(
+                self.changes
+                    .iter()
+                    .map(|change| vec![change.action.into(), change.description.clone()]),
+            )
+            .collect()
+    }
+
+    fn text(&self) -> String {
+        if self.changes.is_empty() {
+            format!("{} is up to date", self.interface_name)
+        } else {
+            self.changes
+                .iter()
+                .map(|change| format!("- {}", change.description))
+                .collect::<Vec<String>>()
+                .join("\n")
+        }
+    }
+}
+
+/// A group's settings, along with its members
+#[derive(Serialize)]
+pub struct GroupView {
+    pub name: String,
+    pub allowed_ips: Vec<String>,
+    pub dns: Vec<String>,
+    #[serde(flatten)]
+    pub tuning: TuningView,
+    pub route_all_except: Vec<String>,
+    pub members: Vec<String>,
+}
+
+impl GroupView {
+    pub fn new(name: &str, group: &Group, members: &[&Client]) -> Self {
+        GroupView {
+            name: name.to_owned(),
+            allowed_ips: group
+                .allowed_ips()
+                .iter()
+                .map(|ip| ip.to_string())
+                .collect(),
+            dns: group.dns().iter().map(|ip| ip.to_string()).collect(),
+            tuning: TuningView::from(group.tuning()),
+            route_all_except: group
+                .route_all_except()
+                .iter()
+                .map(|network| network.to_string())
+                .collect(),
+            members: members.iter().map(|client| client.name().clone()).collect(),
+        }
+    }
+
+    fn header() -> Vec<String> {
+        vec![
+            "Name".into(),
+            "Allowed IPs".into(),
+            "DNS".into(),
+            "Tuning".into(),
+            "Route all except".into(),
+            "Members".into(),
+        ]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            self.allowed_ips.join(","),
+            self.dns.join(","),
+            self.tuning.summary.clone(),
+            self.route_all_except.join(","),
+            self.members.join(","),
+        ]
+    }
+}
+
+/// Output of `group list`
+#[derive(Serialize)]
+pub struct GroupListOutput {
+    pub groups: Vec<GroupView>,
+}
+
+impl Output for GroupListOutput {
+    fn rows(&self) -> Vec<Vec<String>> {
+        std::iter::once(GroupView::header())
+            .chain(self.groups.iter().map(GroupView::row))
+            .collect()
+    }
+}
+
+/// Output of `group set` and `group render`, with the configs of every member re-rendered
+#[derive(Serialize)]
+pub struct GroupOutput {
+    pub group: GroupView,
+    /// Configs by client name, templates for clients whose private key isn't stored
+    pub configs: BTreeMap<String, String>,
+}
+
+impl Output for GroupOutput {
+    fn rows(&self) -> Vec<Vec<String>> {
+        let mut rows = vec![vec!["Client".into(), "Config".into()]];
+        rows.extend(
+            self.configs
+                .iter()
+                .map(|(name, config)| vec![name.clone(), config.clone()]),
+        );
+        rows
+    }
+
+    fn text(&self) -> String {
+        let mut text = cli_table(vec![
+            GroupView::header()
+                .iter()
+                .map(|cell| cell.as_str())
+                .collect(),
+            self.group.row().iter().map(|cell| cell.as_str()).collect(),
+        ])
+        .join("\n");
+
+        for (name, config) in &self.configs {
+            text.push_str(&format!("\n\n# {}\n{}", name, config.trim_end()));
+        }
+        text
+    }
+}
+
+/// Output of `group delete`
+#[derive(Serialize)]
+pub struct DeletedGroupOutput {
+    pub deleted: GroupView,
+}
+
+impl Output for DeletedGroupOutput {
+    fn rows(&self) -> Vec<Vec<String>> {
+        vec![GroupView::header(), self.deleted.row()]
+    }
+
+    fn text(&self) -> String {
+        format!("Deleted group '{}'", self.deleted.name)
+    }
+}
+
+/// A mesh node, as shown to the user (never including its private key)
+#[derive(Serialize)]
+pub struct NodeView {
+    pub name: String,
+    pub ip: Ipv4Addr,
+    pub endpoint: String,
+    pub public_key: String,
+    /// Whether the node's private key is stored, so that its config is complete
+    pub key_stored: bool,
+}
+
+/// A mesh, along with its nodes
+#[derive(Serialize)]
+pub struct MeshView {
+    pub name: String,
+    pub ip_range: String,
+    pub persistent_keepalive: Option<u16>,
+    pub nodes: Vec<NodeView>,
+}
+
+impl MeshView {
+    pub fn new(name: &str, mesh: &Mesh) -> Self {
+        MeshView {
+            name: name.to_owned(),
+            ip_range: mesh.ip_range().to_string(),
+            persistent_keepalive: mesh.persistent_keepalive(),
+            nodes: mesh
+                .nodes()
+                .iter()
+                .map(|(name, node)| NodeView {
+                    name: name.clone(),
+                    ip: node.ip(),
+                    endpoint: node.endpoint().clone(),
+                    public_key: node.public_key().clone(),
+                    key_stored: node.private_key().is_some(),
+                })
+                .collect(),
+        }
+    }
+
+    fn header() -> Vec<String> {
+        vec![
+            "Name".into(),
+            "IP range".into(),
+            "Keepalive".into(),
+            "Nodes".into(),
+        ]
+    }
+
+    fn row(&self) -> Vec<String> {
+        let nodes: Vec<&str> = self.nodes.iter().map(|node| node.name.as_str()).collect();
+        vec![
+            self.name.clone(),
+            self.ip_range.clone(),
+            self.persistent_keepalive
+                .map(|keepalive| keepalive.to_string())
+                .unwrap_or_else(|| "-".into()),
+            nodes.join(","),
+        ]
+    }
+}
+
+/// Output of `mesh list`
+#[derive(Serialize)]
+pub struct MeshListOutput {
+    pub meshes: Vec<MeshView>,
+}
+
+impl Output for MeshListOutput {
+    fn rows(&self) -> Vec<Vec<String>> {
+        std::iter::once(MeshView::header())
+            .chain(self.meshes.iter().map(MeshView::row))
+            .collect()
+    }
+}
+
+/// Output of `mesh new`, `mesh add`, `mesh remove` and `mesh render`, with every node's config
+#[derive(Serialize)]
+pub struct MeshOutput {
+    pub mesh: MeshView,
+    /// Configs by node name, templates for nodes whose private key isn't stored
+    pub configs: BTreeMap<String, String>,
+}
+
+impl Output for MeshOutput {
+    fn rows(&self) -> Vec<Vec<String>> {
+        let mut rows = vec![vec!["Node".into(), "Config".into()]];
+        rows.extend(
+            self.configs
+                .iter()
+                .map(|(name, config)| vec![name.clone(), config.clone()]),
+        );
+        rows
+    }
+
+    fn text(&self) -> String {
+        let mut rows = vec![vec![
+            "Node".to_owned(),
+            "IP".into(),
+            "Endpoint".into(),
+            "Pubkey".into(),
+        ]];
+        rows.extend(self.mesh.nodes.iter().map(|node| {
+            vec![
+                node.name.clone(),
+                node.ip.to_string(),
+                node.endpoint.clone(),
+                node.public_key.clone(),
+            ]
+        }));
+        let mut text = cli_table(
+            rows.iter()
+                .map(|row| row.iter().map(|cell| cell.as_str()).collect())
+                .collect(),
+        )
+        .join("\n");
+
+        for (name, config) in &self.configs {
+            text.push_str(&format!("\n\n# {}\n{}", name, config.trim_end()));
+        }
+        text
+    }
+}
+
+/// Output of `mesh delete`
+#[derive(Serialize)]
+pub struct DeletedMeshOutput {
+    pub deleted: MeshView,
+}
+
+impl Output for DeletedMeshOutput {
+    fn rows(&self) -> Vec<Vec<String>> {
+        vec![MeshView::header(), self.deleted.row()]
+    }
+
+    fn text(&self) -> String {
+        format!("Deleted mesh '{}'", self.deleted.name)
+    }
+}
+
+/// An access policy rule, as shown to the user
+#[derive(Serialize)]
+pub struct RuleView {
+    /// Position in the policy, counting from 1
+    pub number: usize,
+    pub from: String,
+    pub to: String,
+    /// Empty meaning any port
+    pub ports: Vec<String>,
+}
+
+impl RuleView {
+    pub fn new(number: usize, rule: &Rule) -> Self {
+        RuleView {
+            number,
+            from: rule.from().clone(),
+            to: rule.to().to_string(),
+            ports: rule.ports().iter().map(|ports| ports.to_string()).collect(),
+        }
+    }
+
+    fn header() -> Vec<String> {
+        vec!["#".into(), "From".into(), "To".into(), "Ports".into()]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.number.to_string(),
+            self.from.clone(),
+            self.to.clone(),
+            match self.ports.is_empty() {
+                true => "any".into(),
+                false => self.ports.join(", "),
+            },
+        ]
+    }
+
+    fn describe(&self) -> String {
+        match self.ports.is_empty() {
+            true => format!("{} -> {}", self.from, self.to),
+            false => format!("{} -> {} on {}", self.from, self.to, self.ports.join(", ")),
+        }
+    }
+}
+
+/// Output of `policy list`
+#[derive(Serialize)]
+pub struct PolicyListOutput {
+    pub rules: Vec<RuleView>,
+}
+
+impl Output for PolicyListOutput {
+    fn rows(&self) -> Vec<Vec<String>> {
+        std::iter::once(RuleView::header())
+            .chain(self.rules.iter().map(RuleView::row))
+            .collect()
+    }
+}
+
+/// Output of `policy allow`
+#[derive(Serialize)]
+pub struct RuleOutput {
+    pub rule: RuleView,
+}
+
+impl Output for RuleOutput {
+    fn rows(&self) -> Vec<Vec<String>> {
+        vec![RuleView::header(), self.rule.row()]
+    }
+
+    fn text(&self) -> String {
+        format!("Added rule {}: {}", self.rule.number, self.rule.describe())
+    }
+}
+
+/// Output of `policy remove`
+#[derive(Serialize)]
+pub struct DeletedRuleOutput {
+    pub deleted: RuleView,
+}
+
+impl Output for DeletedRuleOutput {
+    fn rows(&self) -> Vec<Vec<String>> {
+        vec![RuleView::header(), self.deleted.row()]
+    }
+
+    fn text(&self) -> String {
+        format!(
+            "Removed rule {}: {}",
+            self.deleted.number,
+            self.deleted.describe()
+        )
+    }
+}
+
+/// Output of `dns set` and `dns off`, every field but `skipped` being `None` when DNS is off
+#[derive(Serialize)]
+pub struct DnsOutput {
+    pub domain: Option<String>,
+    pub server_ip: Option<Ipv4Addr>,
+    pub format: Option<DnsFormat>,
+    pub file: Option<PathBuf>,
+    /// Clients left out of the records, since their names can't be used in DNS
+    pub skipped: Vec<String>,
+}
+
+impl DnsOutput {
+    pub fn new(manager: &Manager) -> Self {
+        let dns = manager.dns();
+        DnsOutput {
+            domain: dns.map(|dns| dns.domain().clone()),
+            server_ip: dns.map(|dns| dns.server()),
+            format: dns.map(|dns| dns.format()),
+            file: dns.map(|dns| dns.path().clone()),
+            skipped: manager.dns_skipped(),
+        }
+    }
+}
+
+impl Output for DnsOutput {
+    fn rows(&self) -> Vec<Vec<String>> {
+        let or_none = |value: Option<String>| value.unwrap_or_else(|| "none".into());
+        vec![
+            vec!["Setting".into(), "Value".into()],
+            vec!["domain".into(), or_none(self.domain.clone())],
+            vec![
+                "server-ip".into(),
+                or_none(self.server_ip.map(|ip| ip.to_string())),
+            ],
+            vec![
+                "format".into(),
+                or_none(self.format.map(|format| format.to_string())),
+            ],
+            vec![
+                "file".into(),
+                or_none(self.file.as_ref().map(|file| file.display().to_string())),
+            ],
+            vec!["skipped".into(), self.skipped.join(", ")],
+        ]
+    }
+}
+
+#[derive(Serialize)]
+pub struct DnsFileView {
+    pub path: PathBuf,
+    pub contents: String,
+}
+
+impl From<DnsFile> for DnsFileView {
+    fn from(file: DnsFile) -> Self {
+        DnsFileView {
+            path: file.path,
+            contents: file.contents,
+        }
+    }
+}
+
+/// Output of `dns render`
+#[derive(Serialize)]
+pub struct DnsRenderOutput {
+    /// Empty when DNS is off
+    pub files: Vec<DnsFileView>,
+    /// Clients left out of the records, since their names can't be used in DNS
+    pub skipped: Vec<String>,
+}
+
+impl Output for DnsRenderOutput {
+    fn rows(&self) -> Vec<Vec<String>> {
+        let mut rows = vec![vec!["File".into(), "Contents".into()]];
+        rows.extend(
+            self.files
+                .iter()
+                .map(|file| vec![file.path.display().to_string(), file.contents.clone()]),
+        );
+        rows
+    }
+
+    fn text(&self) -> String {
+        if self.files.is_empty() {
+            return "DNS is off, so there are no records to write".into();
+        }
+
+        let mut text: Vec<String> = self
+            .files
+            .iter()
+            .map(|file| format!("# {}\n{}", file.path.display(), file.contents.trim_end()))
+            .collect();
+        if !self.skipped.is_empty() {
+            text.push(format!(
+                "Left out, since their names can't be used in DNS: {}",
+                self.skipped.join(", ")
+            ));
+        }
+        text.join("\n\n")
+    }
+}
+
+/// Output of `policy render`
+#[derive(Serialize)]
+pub struct PolicyRenderOutput {
+    /// The nftables ruleset, `None` when there are no rules to enforce
+    pub ruleset: Option<String>,
+}
+
+impl Output for PolicyRenderOutput {
+    fn rows(&self) -> Vec<Vec<String>> {
+        vec![
+            vec!["Ruleset".into()],
+            vec![self.ruleset.clone().unwrap_or_default()],
+        ]
+    }
+
+    fn text(&self) -> String {
+        match &self.ruleset {
+            Some(ruleset) => ruleset.trim_end().to_owned(),
+            None => "No policy rules, so there is no ruleset to load".into(),
+        }
+    }
+}
+
+/// Output of `policy check`
+#[derive(Serialize)]
+pub struct PolicyCheckOutput {
+    pub source: Ipv4Addr,
+    pub destination: Ipv4Addr,
+    /// e.g. `tcp/22`, `None` meaning any traffic
+    pub port: Option<String>,
+    pub allowed: bool,
+    /// Whether the destination is covered by the policy at all
+    pub managed: bool,
+    /// The rule that allows the traffic
+    pub rule: Option<RuleView>,
+    /// Ports that rules allow, when the traffic is denied and no port was asked about
+    pub allowed_ports: Vec<String>,
+}
+
+impl Output for PolicyCheckOutput {
+    fn rows(&self) -> Vec<Vec<String>> {
+        vec![
+            vec![
+                "Source".into(),
+                "Destination".into(),
+                "Port".into(),
+                "Allowed".into(),
+                "Rule".into(),
+            ],
+            vec![
+                self.source.to_string(),
+                self.destination.to_string(),
+                self.port.clone().unwrap_or_else(|| "any".into()),
+                self.allowed.to_string(),
+                self.rule
+                    .as_ref()
+                    .map(|rule| rule.number.to_string())
+                    .unwrap_or_default(),
+            ],
+        ]
+    }
+
+    fn text(&self) -> String {
+        let flow = match &self.port {
+            Some(port) => format!("{} -> {} on {}", self.source, self.destination, port),
+            None => format!("{} -> {}", self.source, self.destination),
+        };
+
+        match (&self.rule, self.managed) {
+            (Some(rule), _) => format!(
+                "{}: allowed by rule {} ({})",
+                flow,
+                rule.number,
+                rule.describe()
+            ),
+            (None, false) => format!(
+                "{}: allowed, the destination isn't covered by the policy",
+                flow
+            ),
+            (None, true) if self.allowed_ports.is_empty() => {
+                format!("{}: denied, no rule allows it", flow)
+            }
+            (None, true) => format!(
+                "{}: denied, though rules allow {}",
+                flow,
+                self.allowed_ports.join(", ")
+            ),
+        }
+    }
+}
+
+/// An API token, as shown to the user (never including the secret or its hash)
+#[derive(Serialize)]
+pub struct TokenView {
+    pub name: String,
+    pub role: Role,
+    /// Group that an `issuer` may create clients in
+    pub group: Option<String>,
+    /// Unix timestamp
+    pub created_at: u64,
+}
+
+impl From<&ApiToken> for TokenView {
+    fn from(token: &ApiToken) -> Self {
+        TokenView {
+            name: token.name().clone(),
+            role: token.role(),
+            group: token.group().cloned(),
+            created_at: token.created_at(),
+        }
+    }
+}
+
+impl TokenView {
+    fn header() -> Vec<String> {
+        vec![
+            "Name".into(),
+            "Role".into(),
+            "Group".into(),
+            "Created".into(),
+        ]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            self.role.to_string(),
+            self.group.clone().unwrap_or_else(|| "-".into()),
+            self.created_at.to_string(),
+        ]
+    }
+}
+
+/// Output of `token list`
+#[derive(Serialize)]
+pub struct TokenListOutput {
+    pub tokens: Vec<TokenView>,
+}
+
+impl Output for TokenListOutput {
+    fn rows(&self) -> Vec<Vec<String>> {
+        std::iter::once(TokenView::header())
+            .chain(self.tokens.iter().map(TokenView::row))
+            .collect()
+    }
+}
+
+/// Output of `token new`
+#[derive(Serialize)]
+pub struct NewTokenOutput {
+    pub token: TokenView,
+    pub secret: String,
+}
+
+impl Output for NewTokenOutput {
+    fn rows(&self) -> Vec<Vec<String>> {
+        let mut header = TokenView::header();
+        header.push("Secret".into());
+
+        let mut row = self.token.row();
+        row.push(self.secret.clone());
+
+        vec![header, row]
+    }
+
+    fn text(&self) -> String {
+        format!(
+            "Created token '{}', it will not be shown again:\n{}",
+            self.token.name, self.secret
+        )
+    }
+}
+
+/// Output of `token delete`
+#[derive(Serialize)]
+pub struct DeletedTokenOutput {
+    pub deleted: TokenView,
+}
+
+impl Output for DeletedTokenOutput {
+    fn rows(&self) -> Vec<Vec<String>> {
+        vec![TokenView::header(), self.deleted.row()]
+    }
+
+    fn text(&self) -> String {
+        format!("Deleted token '{}'", self.deleted.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_row() {
+        let row = vec![
+            "plain".to_owned(),
+            "with,comma".to_owned(),
+            "with \"quotes\"".to_owned(),
+            "multi\nline".to_owned(),
+        ];
+
+        assert_eq!(
+            csv_row(&row),
+            "plain,\"with,comma\",\"with \"\"quotes\"\"\",\"multi\nline\""
+        );
+    }
+
+    #[test]
+    fn test_output_format_from_args() {
+        let args = |args: &[&str]| -> Vec<String> { args.iter().map(|a| a.to_string()).collect() };
+
+        assert_eq!(
+            OutputFormat::from_args(&args(&["wg-manager", "client", "new", "-o", "json"])),
+            Some(OutputFormat::Json)
+        );
+        assert_eq!(
+            OutputFormat::from_args(&args(&["wg-manager", "--output=csv", "--bogus"])),
+            Some(OutputFormat::Csv)
+        );
+        assert_eq!(
+            OutputFormat::from_args(&args(&["wg-manager", "-ojson", "--output", "table"])),
+            Some(OutputFormat::Table)
+        );
+        // `--owner` isn't `-o`, and an output flag without a value is no format
+        assert_eq!(
+            OutputFormat::from_args(&args(&["wg-manager", "--owner", "json", "-o"])),
+            None
+        );
+    }
+
+    #[test]
+    fn test_output_format_from_str() {
+        assert_eq!("json".parse(), Ok(OutputFormat::Json));
+        assert_eq!("csv".parse(), Ok(OutputFormat::Csv));
+        assert_eq!("table".parse(), Ok(OutputFormat::Table));
+        assert!("yaml".parse::<OutputFormat>().is_err());
+    }
+}
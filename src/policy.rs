@@ -0,0 +1,576 @@
+/// Access policy between groups of clients, enforced with nftables.
+///
+/// Without a policy, WireGuard lets any client reach any other through the server. Once there is
+/// at least one rule, traffic from the interface to the VPN's range, or to any network a rule
+/// names, is dropped unless a rule allows it. Anything else (such as the internet) is left alone.
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    fmt::{self, Write},
+    net::Ipv4Addr,
+    str::FromStr,
+};
+
+use ipnet::Ipv4Net;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Protocol::Tcp => write!(f, "tcp"),
+            Protocol::Udp => write!(f, "udp"),
+        }
+    }
+}
+
+impl FromStr for Protocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tcp" => Ok(Protocol::Tcp),
+            "udp" => Ok(Protocol::Udp),
+            other => Err(format!("unknown protocol '{}', expected tcp or udp", other)),
+        }
+    }
+}
+
+/// A port or range of ports, written as `tcp/22` or `udp/8000-8100`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Ports {
+    protocol: Protocol,
+    first: u16,
+    last: u16,
+}
+
+impl Ports {
+    /// The port, if this is a single port rather than a range
+    pub fn port(&self) -> Option<(Protocol, u16)> {
+        if self.first == self.last {
+            Some((self.protocol, self.first))
+        } else {
+            None
+        }
+    }
+
+    pub fn contains(&self, protocol: Protocol, port: u16) -> bool {
+        self.protocol == protocol && self.first <= port && port <= self.last
+    }
+}
+
+impl fmt::Display for Ports {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.first == self.last {
+            write!(f, "{}/{}", self.protocol, self.first)
+        } else {
+            write!(f, "{}/{}-{}", self.protocol, self.first, self.last)
+        }
+    }
+}
+
+impl FromStr for Ports {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid ports '{}', expected e.g. tcp/22 or udp/8000-8100",
+                s
+            )
+        };
+
+        let (protocol, ports) = s.split_once('/').ok_or_else(invalid)?;
+        let protocol = protocol.parse()?;
+        let (first, last) = ports.split_once('-').unwrap_or((ports, ports));
+        let first: u16 = first.parse().map_err(|_| invalid())?;
+        let last: u16 = last.parse().map_err(|_| invalid())?;
+
+        if first == 0 || first > last {
+            return Err(invalid());
+        }
+        Ok(Ports {
+            protocol,
+            first,
+            last,
+        })
+    }
+}
+
+impl TryFrom<String> for Ports {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Ports> for String {
+    fn from(ports: Ports) -> Self {
+        ports.to_string()
+    }
+}
+
+/// What a rule allows traffic to, written as a group name or a network
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Target {
+    Group(String),
+    Network(Ipv4Net),
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Target::Group(name) => write!(f, "{}", name),
+            Target::Network(network) => write!(f, "{}", network),
+        }
+    }
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(network) = s.parse::<Ipv4Net>() {
+            Ok(Target::Network(network.trunc()))
+        } else if let Ok(ip) = s.parse::<Ipv4Addr>() {
+            Ok(Target::Network(Ipv4Net::from(ip)))
+        } else if s.is_empty() {
+            Err("expected a group name or a network".into())
+        } else {
+            Ok(Target::Group(s.to_owned()))
+        }
+    }
+}
+
+impl TryFrom<String> for Target {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Target> for String {
+    fn from(target: Target) -> Self {
+        target.to_string()
+    }
+}
+
+/// Members of group `from` may reach `to`, on `ports` if there are any
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Rule {
+    from: String,
+    to: Target,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    ports: Vec<Ports>,
+}
+
+impl Rule {
+    pub fn new(from: String, to: Target, ports: Vec<Ports>) -> Self {
+        Rule { from, to, ports }
+    }
+
+    pub fn from(&self) -> &String {
+        &self.from
+    }
+
+    pub fn to(&self) -> &Target {
+        &self.to
+    }
+
+    pub fn ports(&self) -> &Vec<Ports> {
+        &self.ports
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} -> {}", self.from, self.to)?;
+        if !self.ports.is_empty() {
+            let ports: Vec<String> = self.ports.iter().map(Ports::to_string).collect();
+            write!(f, " on {}", ports.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// The outcome of a flow through the policy
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    /// The destination isn't covered by the policy, so nothing stops the traffic
+    Unmanaged,
+    /// Allowed by the rule at this index
+    Allowed(usize),
+    /// Denied, though rules allow these ports (when no port was asked about)
+    Denied(Vec<Ports>),
+}
+
+/// A policy, along with what it needs to know about the VPN to be compiled or evaluated
+pub struct Policy<'a> {
+    pub interface: &'a str,
+    pub ip_range: Ipv4Net,
+    /// Networks routed to clients, which the policy covers like the VPN's range
+    pub subnets: Vec<Ipv4Net>,
+    pub rules: &'a [Rule],
+    /// Addresses of each group's members
+    pub members: BTreeMap<String, Vec<Ipv4Net>>,
+}
+
+impl<'a> Policy<'a> {
+    /// Destinations that traffic from the interface is dropped to, unless a rule allows it
+    fn managed(&self) -> Vec<Ipv4Net> {
+        let mut managed = vec![self.ip_range];
+        let networks = self.rules.iter().filter_map(|rule| match rule.to {
+            Target::Network(network) => Some(network),
+            Target::Group(_) => None,
+        });
+        for network in self.subnets.iter().copied().chain(networks) {
+            if !managed.contains(&network) {
+                managed.push(network);
+            }
+        }
+        managed
+    }
+
+    /// Whether `network` lies within the addresses of a group's members
+    fn in_group(&self, group: &str, network: Ipv4Net) -> bool {
+        self.members
+            .get(group)
+            .is_some_and(|members| members.iter().any(|member| member.contains(&network)))
+    }
+
+    /// Whether traffic matching `rule` can come from `network`
+    fn is_from(&self, rule: &Rule, network: Ipv4Net) -> bool {
+        self.in_group(&rule.from, network)
+    }
+
+    /// Whether traffic matching `rule` can go to (some of) `network`
+    fn reaches(&self, rule: &Rule, network: Ipv4Net) -> bool {
+        match &rule.to {
+            Target::Group(group) => self.in_group(group, network),
+            Target::Network(to) => to.contains(&network) || network.contains(to),
+        }
+    }
+
+    /// Whether any rule lets traffic flow between `a` and `b`, in either direction
+    pub fn connects(&self, a: Ipv4Net, b: Ipv4Net) -> bool {
+        self.rules.iter().any(|rule| {
+            (self.is_from(rule, a) && self.reaches(rule, b))
+                || (self.is_from(rule, b) && self.reaches(rule, a))
+        })
+    }
+
+    /// Renders the policy as the sets and chain of an nftables table, see `nft::replace_table`
+    pub fn render(&self) -> String {
+        let mut groups: Vec<&String> = Vec::new();
+        for rule in self.rules {
+            for group in std::iter::once(&rule.from).chain(match &rule.to {
+                Target::Group(group) => Some(group),
+                Target::Network(_) => None,
+            }) {
+                if !groups.contains(&group) {
+                    groups.push(group);
+                }
+            }
+        }
+        groups.sort();
+        let set_names = set_names(&groups);
+
+        let mut out = String::new();
+        for group in &groups {
+            let _ = writeln!(out, "\t# group {}", group);
+            let _ = writeln!(out, "\tset {} {{", set_names[*group]);
+            let _ = writeln!(out, "\t\ttype ipv4_addr");
+            let _ = writeln!(out, "\t\tflags interval");
+            let _ = writeln!(out, "\t\tauto-merge");
+            let members = self.members.get(*group).cloned().unwrap_or_default();
+            if !members.is_empty() {
+                let _ = writeln!(out, "\t\telements = {{ {} }}", join(&members));
+            }
+            let _ = writeln!(out, "\t}}");
+        }
+
+        let _ = writeln!(out, "\tchain forward {{");
+        let _ = writeln!(
+            out,
+            "\t\ttype filter hook forward priority filter; policy accept;"
+        );
+        let _ = writeln!(out, "\t\tiifname != \"{}\" accept", self.interface);
+        let _ = writeln!(out, "\t\tct state established,related accept");
+
+        for rule in self.rules {
+            let daddr = match &rule.to {
+                Target::Group(group) => format!("@{}", set_names[group]),
+                Target::Network(network) => network.to_string(),
+            };
+            let matches = format!("ip saddr @{} ip daddr {}", set_names[&rule.from], daddr);
+
+            if rule.ports.is_empty() {
+                let _ = writeln!(out, "\t\t{} accept # {}", matches, rule);
+            }
+            for protocol in [Protocol::Tcp, Protocol::Udp] {
+                let ports: Vec<String> = rule
+                    .ports
+                    .iter()
+                    .filter(|ports| ports.protocol == protocol)
+                    .map(|ports| match ports.first == ports.last {
+                        true => ports.first.to_string(),
+                        false => format!("{}-{}", ports.first, ports.last),
+                    })
+                    .collect();
+                if !ports.is_empty() {
+                    let _ = writeln!(
+                        out,
+                        "\t\t{} {} dport {{ {} }} accept # {}",
+                        matches,
+                        protocol,
+                        ports.join(", "),
+                        rule
+                    );
+                }
+            }
+        }
+
+        for network in self.managed() {
+            let _ = writeln!(out, "\t\tip daddr {} drop", network);
+        }
+        let _ = writeln!(out, "\t}}");
+
+        out
+    }
+
+    /// Whether traffic from `source` to `destination` (on `port`, if given) is allowed
+    pub fn check(
+        &self,
+        source: Ipv4Addr,
+        destination: Ipv4Addr,
+        port: Option<(Protocol, u16)>,
+    ) -> Verdict {
+        if !self
+            .managed()
+            .iter()
+            .any(|network| network.contains(&destination))
+        {
+            return Verdict::Unmanaged;
+        }
+
+        let (source, destination) = (Ipv4Net::from(source), Ipv4Net::from(destination));
+        let mut allowed_ports = Vec::new();
+        for (index, rule) in self.rules.iter().enumerate() {
+            if !self.is_from(rule, source) || !self.reaches(rule, destination) {
+                continue;
+            }
+
+            match port {
+                _ if rule.ports.is_empty() => return Verdict::Allowed(index),
+                Some((protocol, port)) => {
+                    if rule
+                        .ports
+                        .iter()
+                        .any(|ports| ports.contains(protocol, port))
+                    {
+                        return Verdict::Allowed(index);
+                    }
+                }
+                None => allowed_ports.extend(rule.ports.iter().copied()),
+            }
+        }
+
+        Verdict::Denied(allowed_ports)
+    }
+}
+
+/// Names of the nftables sets for each group, which can only contain some characters
+fn set_names<'a>(groups: &[&'a String]) -> BTreeMap<&'a String, String> {
+    let mut names: BTreeMap<&String, String> = BTreeMap::new();
+
+    for (index, group) in groups.iter().enumerate() {
+        let base: String = group
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        let base = format!("group_{}", base);
+        // Groups whose names only differ in other characters get told apart by position, and
+        // that can clash with a group named like `ops_team_1` in turn
+        let mut name = base.clone();
+        let mut suffix = index;
+        while names.values().any(|other| *other == name) {
+            name = format!("{}_{}", base, suffix);
+            suffix += 1;
+        }
+        names.insert(group, name);
+    }
+
+    names
+}
+
+fn join(networks: &[Ipv4Net]) -> String {
+    networks
+        .iter()
+        .map(|network| network.to_string())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules() -> Vec<Rule> {
+        vec![
+            Rule::new(
+                "engineering".into(),
+                "servers".parse().unwrap(),
+                vec!["tcp/22".parse().unwrap(), "tcp/8000-8100".parse().unwrap()],
+            ),
+            Rule::new("engineering".into(), "10.1.0.0/16".parse().unwrap(), vec![]),
+        ]
+    }
+
+    fn policy(rules: &[Rule]) -> Policy<'_> {
+        Policy {
+            interface: "wg0",
+            ip_range: "10.0.0.0/24".parse().unwrap(),
+            subnets: vec!["192.168.50.0/24".parse().unwrap()],
+            rules,
+            members: BTreeMap::from([
+                (
+                    "engineering".to_owned(),
+                    vec!["10.0.0.2/32".parse().unwrap()],
+                ),
+                ("servers".to_owned(), vec!["10.0.0.3/32".parse().unwrap()]),
+                (
+                    "branch".to_owned(),
+                    vec![
+                        "10.0.0.4/32".parse().unwrap(),
+                        "192.168.50.0/24".parse().unwrap(),
+                    ],
+                ),
+            ]),
+        }
+    }
+
+    #[test]
+    fn test_parse_ports() {
+        assert_eq!("tcp/22".parse::<Ports>().unwrap().to_string(), "tcp/22");
+        assert_eq!(
+            "udp/8000-8100".parse::<Ports>().unwrap().to_string(),
+            "udp/8000-8100"
+        );
+        assert!("tcp/0".parse::<Ports>().is_err());
+        assert!("tcp/90-80".parse::<Ports>().is_err());
+        assert!("sctp/22".parse::<Ports>().is_err());
+        assert!("22".parse::<Ports>().is_err());
+    }
+
+    #[test]
+    fn test_render() {
+        let rules = rules();
+
+        assert_eq!(
+            policy(&rules).render(),
+            "\t# group engineering\n\
+             \tset group_engineering {\n\
+             \t\ttype ipv4_addr\n\
+             \t\tflags interval\n\
+             \t\tauto-merge\n\
+             \t\telements = { 10.0.0.2/32 }\n\
+             \t}\n\
+             \t# group servers\n\
+             \tset group_servers {\n\
+             \t\ttype ipv4_addr\n\
+             \t\tflags interval\n\
+             \t\tauto-merge\n\
+             \t\telements = { 10.0.0.3/32 }\n\
+             \t}\n\
+             \tchain forward {\n\
+             \t\ttype filter hook forward priority filter; policy accept;\n\
+             \t\tiifname != \"wg0\" accept\n\
+             \t\tct state established,related accept\n\
+             \t\tip saddr @group_engineering ip daddr @group_servers tcp dport { 22, 8000-8100 } accept # engineering -> servers on tcp/22, tcp/8000-8100\n\
+             \t\tip saddr @group_engineering ip daddr 10.1.0.0/16 accept # engineering -> 10.1.0.0/16\n\
+             \t\tip daddr 10.0.0.0/24 drop\n\
+             \t\tip daddr 192.168.50.0/24 drop\n\
+             \t\tip daddr 10.1.0.0/16 drop\n\
+             \t}\n"
+        );
+    }
+
+    #[test]
+    fn test_check() {
+        let rules = rules();
+        let policy = policy(&rules);
+        let ip = |ip: &str| ip.parse::<Ipv4Addr>().unwrap();
+
+        assert_eq!(
+            policy.check(ip("10.0.0.2"), ip("10.0.0.3"), Some((Protocol::Tcp, 8080))),
+            Verdict::Allowed(0)
+        );
+        assert_eq!(
+            policy.check(ip("10.0.0.2"), ip("10.0.0.3"), Some((Protocol::Udp, 22))),
+            Verdict::Denied(vec![])
+        );
+        assert_eq!(
+            policy.check(ip("10.0.0.2"), ip("10.0.0.3"), None),
+            Verdict::Denied(rules[0].ports.clone())
+        );
+        assert_eq!(
+            policy.check(ip("10.0.0.2"), ip("10.1.2.3"), None),
+            Verdict::Allowed(1)
+        );
+        // Nothing allows servers to reach engineering, but the internet is none of the policy's
+        // business
+        assert_eq!(
+            policy.check(ip("10.0.0.3"), ip("10.0.0.2"), None),
+            Verdict::Denied(vec![])
+        );
+        assert_eq!(
+            policy.check(ip("10.0.0.3"), ip("1.1.1.1"), None),
+            Verdict::Unmanaged
+        );
+    }
+
+    #[test]
+    fn test_connects() {
+        let rules = vec![Rule::new(
+            "engineering".into(),
+            "branch".parse().unwrap(),
+            vec!["tcp/22".parse().unwrap()],
+        )];
+        let policy = policy(&rules);
+        let net = |net: &str| net.parse::<Ipv4Net>().unwrap();
+
+        assert!(policy.connects(net("10.0.0.2/32"), net("192.168.50.0/24")));
+        // Replies from the branch's LAN need routing back too
+        assert!(policy.connects(net("192.168.50.0/24"), net("10.0.0.2/32")));
+        assert!(!policy.connects(net("10.0.0.3/32"), net("192.168.50.0/24")));
+        assert_eq!(
+            policy.check(
+                "10.0.0.2".parse().unwrap(),
+                "192.168.50.7".parse().unwrap(),
+                Some((Protocol::Tcp, 22))
+            ),
+            Verdict::Allowed(0)
+        );
+    }
+
+    #[test]
+    fn test_set_names() {
+        let a = "ops-team".to_owned();
+        let b = "ops.team".to_owned();
+        let c = "ops_team_1".to_owned();
+        let names = set_names(&[&a, &b, &c]);
+
+        assert_eq!(names[&a], "group_ops_team");
+        assert_eq!(names[&b], "group_ops_team_1");
+        assert_eq!(names[&c], "group_ops_team_1_2");
+    }
+}
@@ -0,0 +1,117 @@
+/// The server's own config, for bringing the interface up with `wg-quick` rather than by hand.
+///
+/// Addresses are left out, since `wg-manager` doesn't know which address in the VPN's range the
+/// server has.
+use std::fmt::Write;
+
+use ipnet::IpNet;
+
+use crate::tuning::Table;
+
+/// Everything needed to render the server's config
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerConfig {
+    pub private_key: String,
+    pub listen_port: u16,
+    pub mtu: Option<u16>,
+    pub fwmark: Option<u32>,
+    pub table: Option<Table>,
+    pub post_up: Vec<String>,
+    pub post_down: Vec<String>,
+    /// Enabled clients, by name
+    pub peers: Vec<ServerPeer>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerPeer {
+    pub name: String,
+    pub public_key: String,
+    pub preshared_key: Option<String>,
+    pub allowed_ips: Vec<IpNet>,
+}
+
+impl ServerConfig {
+    /// Renders the config in the format read by `wg-quick`
+    pub fn to_wg_quick(&self) -> String {
+        let mut out = String::new();
+
+        // Writing to a `String` cannot fail
+        let _ = writeln!(out, "[Interface]");
+        let _ = writeln!(out, "PrivateKey = {}", self.private_key);
+        let _ = writeln!(out, "ListenPort = {}", self.listen_port);
+        if let Some(mtu) = self.mtu {
+            let _ = writeln!(out, "MTU = {}", mtu);
+        }
+        if let Some(fwmark) = self.fwmark {
+            let _ = writeln!(out, "FwMark = 0x{:x}", fwmark);
+        }
+        if let Some(table) = self.table {
+            let _ = writeln!(out, "Table = {}", table);
+        }
+        for hook in &self.post_up {
+            let _ = writeln!(out, "PostUp = {}", hook);
+        }
+        for hook in &self.post_down {
+            let _ = writeln!(out, "PostDown = {}", hook);
+        }
+
+        for peer in &self.peers {
+            let allowed_ips: Vec<String> = peer.allowed_ips.iter().map(IpNet::to_string).collect();
+            let _ = writeln!(out);
+            let _ = writeln!(out, "# {}", peer.name);
+            let _ = writeln!(out, "[Peer]");
+            let _ = writeln!(out, "PublicKey = {}", peer.public_key);
+            if let Some(preshared_key) = &peer.preshared_key {
+                let _ = writeln!(out, "PresharedKey = {}", preshared_key);
+            }
+            let _ = writeln!(out, "AllowedIPs = {}", allowed_ips.join(", "));
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_wg_quick() {
+        let config = ServerConfig {
+            private_key: "server-private".into(),
+            listen_port: 51820,
+            mtu: Some(1420),
+            fwmark: Some(0x51820),
+            table: Some(Table::Id(1234)),
+            post_up: vec!["iptables -A FORWARD -i %i -j ACCEPT".into()],
+            post_down: vec!["iptables -D FORWARD -i %i -j ACCEPT".into()],
+            peers: vec![ServerPeer {
+                name: "branch".into(),
+                public_key: "branch-public".into(),
+                preshared_key: Some("preshared".into()),
+                allowed_ips: vec![
+                    "10.0.0.2/32".parse().unwrap(),
+                    "192.168.50.0/24".parse().unwrap(),
+                ],
+            }],
+        };
+
+        assert_eq!(
+            config.to_wg_quick(),
+            "[Interface]\n\
+             PrivateKey = server-private\n\
+             ListenPort = 51820\n\
+             MTU = 1420\n\
+             FwMark = 0x51820\n\
+             Table = 1234\n\
+             PostUp = iptables -A FORWARD -i %i -j ACCEPT\n\
+             PostDown = iptables -D FORWARD -i %i -j ACCEPT\n\
+             \n\
+             # branch\n\
+             [Peer]\n\
+             PublicKey = branch-public\n\
+             PresharedKey = preshared\n\
+             AllowedIPs = 10.0.0.2/32, 192.168.50.0/24\n"
+        );
+    }
+}
@@ -0,0 +1,186 @@
+/// Split tunnels that route everything through the VPN except some ranges, such as a home LAN.
+///
+/// WireGuard has no way to exclude a range from `AllowedIPs`, so "everything except" has to be
+/// spelled out as the CIDRs that make up the complement of the excluded ranges, for IPv4 and IPv6.
+use ipnet::IpNet;
+
+/// The smallest set of CIDRs covering all of IPv4 and IPv6 except `excluded`, while still
+/// covering `routed` (which wins where the two overlap). Sorted, IPv4 first.
+pub fn route_all_except(excluded: &[IpNet], routed: &[IpNet]) -> Vec<IpNet> {
+    let mut remaining: Vec<IpNet> = vec![
+        "0.0.0.0/0".parse().expect("valid network"),
+        "::/0".parse().expect("valid network"),
+    ];
+    for excluded in excluded {
+        let excluded = excluded.trunc();
+        remaining = remaining
+            .into_iter()
+            .flat_map(|network| exclude(network, excluded))
+            .collect();
+    }
+
+    remaining.extend(routed.iter().map(IpNet::trunc));
+    IpNet::aggregate(&remaining)
+}
+
+/// What is left of `network` without `excluded`
+fn exclude(network: IpNet, excluded: IpNet) -> Vec<IpNet> {
+    if excluded.contains(&network) {
+        return Vec::new();
+    }
+    // Two CIDRs either nest or don't overlap at all
+    if !network.contains(&excluded) {
+        return vec![network];
+    }
+
+    // Halve the network until reaching the excluded range, keeping the halves without it
+    let mut left = Vec::new();
+    let mut current = network;
+    while current.prefix_len() < excluded.prefix_len() {
+        let halves = current
+            .subnets(current.prefix_len() + 1)
+            .expect("the prefix is shorter than the excluded one");
+        for half in halves {
+            if half.contains(&excluded) {
+                current = half;
+            } else {
+                left.push(half);
+            }
+        }
+    }
+
+    left
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nets(nets: &[&str]) -> Vec<IpNet> {
+        nets.iter().map(|net| net.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_nothing_excluded() {
+        assert_eq!(route_all_except(&[], &[]), nets(&["0.0.0.0/0", "::/0"]));
+    }
+
+    #[test]
+    fn test_private_ranges() {
+        // As given by the usual AllowedIPs calculators for excluding RFC 1918 ranges
+        assert_eq!(
+            route_all_except(
+                &nets(&["10.0.0.0/8", "172.16.0.0/12", "192.168.0.0/16"]),
+                &[]
+            ),
+            nets(&[
+                "0.0.0.0/5",
+                "8.0.0.0/7",
+                "11.0.0.0/8",
+                "12.0.0.0/6",
+                "16.0.0.0/4",
+                "32.0.0.0/3",
+                "64.0.0.0/2",
+                "128.0.0.0/3",
+                "160.0.0.0/5",
+                "168.0.0.0/6",
+                "172.0.0.0/12",
+                "172.32.0.0/11",
+                "172.64.0.0/10",
+                "172.128.0.0/9",
+                "173.0.0.0/8",
+                "174.0.0.0/7",
+                "176.0.0.0/4",
+                "192.0.0.0/9",
+                "192.128.0.0/11",
+                "192.160.0.0/13",
+                "192.169.0.0/16",
+                "192.170.0.0/15",
+                "192.172.0.0/14",
+                "192.176.0.0/12",
+                "192.192.0.0/10",
+                "193.0.0.0/8",
+                "194.0.0.0/7",
+                "196.0.0.0/6",
+                "200.0.0.0/5",
+                "208.0.0.0/4",
+                "224.0.0.0/3",
+                "::/0",
+            ])
+        );
+    }
+
+    #[test]
+    fn test_ipv6() {
+        assert_eq!(
+            route_all_except(&nets(&["fd00::/8", "8000::/1"]), &[]),
+            nets(&["0.0.0.0/0", "::/1"])
+        );
+        assert_eq!(
+            route_all_except(&nets(&["fc00::/7"]), &[]),
+            nets(&[
+                "0.0.0.0/0",
+                "::/1",
+                "8000::/2",
+                "c000::/3",
+                "e000::/4",
+                "f000::/5",
+                "f800::/6",
+                "fe00::/7",
+            ])
+        );
+    }
+
+    #[test]
+    fn test_routed_wins() {
+        // The VPN's own range stays routed inside an excluded range, and a single excluded
+        // address is carved out of what's left
+        assert_eq!(
+            route_all_except(
+                &nets(&["10.0.0.0/8", "192.0.2.1/32", "::/0"]),
+                &nets(&["10.0.0.0/24", "10.0.1.0/24"])
+            ),
+            nets(&[
+                "0.0.0.0/5",
+                "8.0.0.0/7",
+                "10.0.0.0/23",
+                "11.0.0.0/8",
+                "12.0.0.0/6",
+                "16.0.0.0/4",
+                "32.0.0.0/3",
+                "64.0.0.0/2",
+                "128.0.0.0/2",
+                "192.0.0.0/23",
+                "192.0.2.0/32",
+                "192.0.2.2/31",
+                "192.0.2.4/30",
+                "192.0.2.8/29",
+                "192.0.2.16/28",
+                "192.0.2.32/27",
+                "192.0.2.64/26",
+                "192.0.2.128/25",
+                "192.0.3.0/24",
+                "192.0.4.0/22",
+                "192.0.8.0/21",
+                "192.0.16.0/20",
+                "192.0.32.0/19",
+                "192.0.64.0/18",
+                "192.0.128.0/17",
+                "192.1.0.0/16",
+                "192.2.0.0/15",
+                "192.4.0.0/14",
+                "192.8.0.0/13",
+                "192.16.0.0/12",
+                "192.32.0.0/11",
+                "192.64.0.0/10",
+                "192.128.0.0/9",
+                "193.0.0.0/8",
+                "194.0.0.0/7",
+                "196.0.0.0/6",
+                "200.0.0.0/5",
+                "208.0.0.0/4",
+                "224.0.0.0/3",
+            ])
+        );
+    }
+}
@@ -0,0 +1,148 @@
+/// Joins configured clients with the live state of the interface.
+use std::{collections::HashMap, fmt};
+
+use serde::Serialize;
+
+use crate::manager::Client;
+use crate::wg::PeerInfo;
+
+/// How long after the latest handshake a peer is still considered online, by default.
+///
+/// WireGuard re-handshakes every two minutes while traffic is flowing, so this leaves some slack.
+pub const DEFAULT_ONLINE_THRESHOLD: u64 = 180;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PeerState {
+    /// Configured, and handshaked recently
+    Online,
+    /// Configured and on the interface, but no recent handshake
+    Offline,
+    /// Configured, but not on the interface
+    Missing,
+    /// On the interface, but not configured
+    Unknown,
+}
+
+impl fmt::Display for PeerState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = match self {
+            PeerState::Online => "online",
+            PeerState::Offline => "offline",
+            PeerState::Missing => "missing",
+            PeerState::Unknown => "unknown",
+        };
+        write!(f, "{}", state)
+    }
+}
+
+/// A configured client, a live peer, or both
+#[derive(Debug)]
+pub struct PeerStatus<'a> {
+    pub client: Option<&'a Client>,
+    pub peer: Option<PeerInfo>,
+}
+
+impl<'a> PeerStatus<'a> {
+    /// Seconds since the latest handshake, `None` if there has never been one
+    pub fn handshake_age(&self, now: u64) -> Option<u64> {
+        self.peer
+            .as_ref()
+            .and_then(|peer| peer.latest_handshake)
+            .map(|handshake| now.saturating_sub(handshake))
+    }
+
+    pub fn state(&self, now: u64, online_threshold: u64) -> PeerState {
+        match (self.client, &self.peer) {
+            (Some(_), Some(_)) => match self.handshake_age(now) {
+                Some(age) if age <= online_threshold => PeerState::Online,
+                _ => PeerState::Offline,
+            },
+            (Some(_), None) => PeerState::Missing,
+            (None, _) => PeerState::Unknown,
+        }
+    }
+}
+
+/// Matches clients to peers by public key.
+///
+/// Clients come first, sorted by name, followed by unknown peers sorted by public key.
+pub fn join<'a>(clients: Vec<&'a Client>, peers: Vec<PeerInfo>) -> Vec<PeerStatus<'a>> {
+    let mut peers: HashMap<String, PeerInfo> = peers
+        .into_iter()
+        .map(|peer| (peer.public_key.clone(), peer))
+        .collect();
+
+    let mut clients = clients;
+    clients.sort_by_key(|client| client.name());
+
+    let mut statuses: Vec<PeerStatus> = clients
+        .into_iter()
+        .map(|client| PeerStatus {
+            client: Some(client),
+            peer: peers.remove(client.public_key()),
+        })
+        .collect();
+
+    let mut unknown: Vec<PeerInfo> = peers.into_values().collect();
+    unknown.sort_by(|a, b| a.public_key.cmp(&b.public_key));
+    statuses.extend(unknown.into_iter().map(|peer| PeerStatus {
+        client: None,
+        peer: Some(peer),
+    }));
+
+    statuses
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(public_key: &str, latest_handshake: Option<u64>) -> PeerInfo {
+        PeerInfo {
+            public_key: public_key.into(),
+            endpoint: None,
+            allowed_ips: Vec::new(),
+            latest_handshake,
+            rx_bytes: 0,
+            tx_bytes: 0,
+            persistent_keepalive: None,
+        }
+    }
+
+    #[test]
+    fn test_join() {
+        let alice = Client::new("alice".into(), "A=".into(), "10.0.0.2".parse().unwrap());
+        let bob = Client::new("bob".into(), "B=".into(), "10.0.0.3".parse().unwrap());
+        let carol = Client::new("carol".into(), "C=".into(), "10.0.0.4".parse().unwrap());
+
+        let statuses = join(
+            vec![&carol, &bob, &alice],
+            vec![
+                peer("X=", None),
+                peer("A=", Some(900)),
+                peer("B=", Some(100)),
+            ],
+        );
+        let states: Vec<(Option<&str>, PeerState)> = statuses
+            .iter()
+            .map(|status| {
+                (
+                    status.client.map(|client| client.name().as_str()),
+                    status.state(1000, DEFAULT_ONLINE_THRESHOLD),
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            states,
+            vec![
+                (Some("alice"), PeerState::Online),
+                (Some("bob"), PeerState::Offline),
+                (Some("carol"), PeerState::Missing),
+                (None, PeerState::Unknown),
+            ]
+        );
+        assert_eq!(statuses[0].handshake_age(1000), Some(100));
+    }
+}
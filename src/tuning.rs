@@ -0,0 +1,436 @@
+/// Interface tuning: MTU, keepalive, firewall mark, routing table and wg-quick hooks.
+///
+/// Every setting is optional, and unset ones are inherited: a client's own settings win over its
+/// groups', which win over the server's. Only the MTU and keepalive are passed down from the
+/// server though, since its firewall mark, table and hooks are about its own interface. On the
+/// server, the MTU and firewall mark are applied to the interface by commits, and everything goes
+/// into the config that `server config` renders.
+use std::{convert::TryFrom, fmt, path::PathBuf, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::utils::{self, CommandError};
+
+/// IPv6 needs links of at least 1280 bytes, and jumbo frames top out around 9000
+pub const MTU_RANGE: std::ops::RangeInclusive<u16> = 1280..=9000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TuningError {
+    MtuOutOfRange(u16),
+    /// A hook that is empty or spans several lines, which wg-quick configs can't hold
+    InvalidHook(String),
+}
+
+impl fmt::Display for TuningError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TuningError::MtuOutOfRange(mtu) => write!(
+                f,
+                "MTU {} is out of range, expected {} to {}",
+                mtu,
+                MTU_RANGE.start(),
+                MTU_RANGE.end()
+            ),
+            TuningError::InvalidHook(hook) => {
+                write!(f, "invalid hook '{}', expected a single line", hook)
+            }
+        }
+    }
+}
+
+/// The routing table wg-quick adds routes for `AllowedIPs` to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Table {
+    /// Adds no routes
+    Off,
+    /// The main table, or a new one with policy routing when routing a /0
+    Auto,
+    Id(u32),
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Table::Off => write!(f, "off"),
+            Table::Auto => write!(f, "auto"),
+            Table::Id(id) => write!(f, "{}", id),
+        }
+    }
+}
+
+impl FromStr for Table {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Table::Off),
+            "auto" => Ok(Table::Auto),
+            "main" => Ok(Table::Id(254)),
+            _ => match s.parse::<u32>() {
+                // 0 is unspecified, and 253 and 255 are the kernel's default and local tables
+                Ok(0) | Ok(253) | Ok(255) => Err(format!("table {} is reserved", s)),
+                Ok(id) => Ok(Table::Id(id)),
+                Err(_) => Err(format!(
+                    "invalid table '{}', expected off, auto, main or a table number",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl TryFrom<String> for Table {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Table> for String {
+    fn from(table: Table) -> Self {
+        table.to_string()
+    }
+}
+
+/// Parses a firewall mark as `wg` does: decimal, hex with `0x`, or `off` for 0
+pub fn parse_fwmark(s: &str) -> Result<u32, String> {
+    let fwmark = match s.strip_prefix("0x") {
+        _ if s == "off" => Ok(0),
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    fwmark.map_err(|_| format!("invalid fwmark '{}', expected a number or off", s))
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Tuning {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtu: Option<u16>,
+    /// Seconds between keepalive packets, `Some(0)` turning an inherited keepalive off
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub persistent_keepalive: Option<u16>,
+    /// `Some(0)` turning an inherited mark off
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fwmark: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub table: Option<Table>,
+    /// Shell commands wg-quick runs after bringing the interface up, in order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_up: Vec<String>,
+    /// Shell commands wg-quick runs after taking the interface down, in order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_down: Vec<String>,
+}
+
+impl Tuning {
+    pub fn validate(&self) -> Result<(), TuningError> {
+        if let Some(mtu) = self.mtu {
+            if !MTU_RANGE.contains(&mtu) {
+                return Err(TuningError::MtuOutOfRange(mtu));
+            }
+        }
+        for hook in self.post_up.iter().chain(&self.post_down) {
+            if hook.trim().is_empty() || hook.contains('\n') || hook.contains('\r') {
+                return Err(TuningError::InvalidHook(hook.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Combines the settings of the groups a client is in: the shortest keepalive (leaving aside
+    /// those turned off) and the smallest MTU, the first firewall mark and table that are set,
+    /// and every hook in order, without duplicates
+    pub fn merge<'a>(tunings: impl IntoIterator<Item = &'a Tuning>) -> Tuning {
+        let mut merged = Tuning::default();
+
+        for tuning in tunings {
+            merged.mtu = match (merged.mtu, tuning.mtu) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            };
+            merged.persistent_keepalive =
+                match (merged.persistent_keepalive, tuning.persistent_keepalive) {
+                    (Some(0), Some(b)) | (Some(b), Some(0)) => Some(b),
+                    (Some(a), Some(b)) => Some(a.min(b)),
+                    (a, b) => a.or(b),
+                };
+            merged.fwmark = merged.fwmark.or(tuning.fwmark);
+            merged.table = merged.table.or(tuning.table);
+            for hook in &tuning.post_up {
+                if !merged.post_up.contains(hook) {
+                    merged.post_up.push(hook.clone());
+                }
+            }
+            for hook in &tuning.post_down {
+                if !merged.post_down.contains(hook) {
+                    merged.post_down.push(hook.clone());
+                }
+            }
+        }
+
+        merged
+    }
+
+    /// These settings, falling back on `fallback` for those that aren't set
+    pub fn or(&self, fallback: &Tuning) -> Tuning {
+        let or_hooks = |hooks: &Vec<String>, fallback: &Vec<String>| match hooks.is_empty() {
+            true => fallback.clone(),
+            false => hooks.clone(),
+        };
+
+        Tuning {
+            mtu: self.mtu.or(fallback.mtu),
+            persistent_keepalive: self.persistent_keepalive.or(fallback.persistent_keepalive),
+            fwmark: self.fwmark.or(fallback.fwmark),
+            table: self.table.or(fallback.table),
+            post_up: or_hooks(&self.post_up, &fallback.post_up),
+            post_down: or_hooks(&self.post_down, &fallback.post_down),
+        }
+    }
+
+    /// The server's settings that clients inherit
+    pub fn inherited(&self) -> Tuning {
+        Tuning {
+            mtu: self.mtu,
+            persistent_keepalive: self.persistent_keepalive,
+            ..Tuning::default()
+        }
+    }
+
+    /// The keepalive to configure, if it isn't off
+    pub fn keepalive(&self) -> Option<u16> {
+        self.persistent_keepalive.filter(|secs| *secs != 0)
+    }
+
+    /// The firewall mark to configure, if it isn't off
+    pub fn mark(&self) -> Option<u32> {
+        self.fwmark.filter(|mark| *mark != 0)
+    }
+}
+
+/// Summarises the settings that are set, e.g. `mtu 1380, keepalive 25s, table off`
+impl fmt::Display for Tuning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(mtu) = self.mtu {
+            parts.push(format!("mtu {}", mtu));
+        }
+        match self.persistent_keepalive {
+            Some(0) => parts.push("keepalive off".into()),
+            Some(secs) => parts.push(format!("keepalive {}s", secs)),
+            None => {}
+        }
+        match self.fwmark {
+            Some(0) => parts.push("fwmark off".into()),
+            Some(mark) => parts.push(format!("fwmark 0x{:x}", mark)),
+            None => {}
+        }
+        if let Some(table) = self.table {
+            parts.push(format!("table {}", table));
+        }
+        if !self.post_up.is_empty() {
+            parts.push(format!("{} post-up", self.post_up.len()));
+        }
+        if !self.post_down.is_empty() {
+            parts.push(format!("{} post-down", self.post_down.len()));
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+#[derive(Debug)]
+pub enum LinkError {
+    /// Reading a link's attribute under `/sys/class/net` failed
+    Sysfs {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    /// The ip binary could not be found
+    NotInstalled { command: String },
+    /// ip exited with a non-zero status
+    CommandFailed {
+        command: String,
+        code: Option<i32>,
+        stderr: String,
+    },
+    /// Spawning ip failed
+    IOError {
+        command: String,
+        error: std::io::Error,
+    },
+}
+
+impl From<CommandError> for LinkError {
+    fn from(error: CommandError) -> Self {
+        match error {
+            CommandError::NotInstalled { command } => LinkError::NotInstalled { command },
+            CommandError::Failed {
+                command,
+                code,
+                stderr,
+            } => LinkError::CommandFailed {
+                command,
+                code,
+                stderr,
+            },
+            CommandError::IOError { command, error } => LinkError::IOError { command, error },
+        }
+    }
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LinkError::Sysfs { path, error } => write!(f, "{}: {}", path.display(), error),
+            LinkError::NotInstalled { command } => write!(
+                f,
+                "`{}` failed: ip binary not found, is iproute2 installed?",
+                command
+            ),
+            LinkError::CommandFailed {
+                command,
+                code: Some(code),
+                stderr,
+            } => write!(f, "`{}` failed (exit code {}): {}", command, code, stderr),
+            LinkError::CommandFailed {
+                command, stderr, ..
+            } => write!(f, "`{}` failed (killed by signal): {}", command, stderr),
+            LinkError::IOError { command, error } => {
+                write!(f, "`{}` failed: {}", command, error)
+            }
+        }
+    }
+}
+
+/// Handle to network links, read through `/sys/class/net` and changed with the ip binary
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Link {
+    binary_path: String,
+    sys_path: PathBuf,
+}
+
+impl Default for Link {
+    fn default() -> Self {
+        Link {
+            binary_path: "ip".into(),
+            sys_path: PathBuf::from("/sys/class/net"),
+        }
+    }
+}
+
+impl Link {
+    pub fn mtu(&self, interface: &str) -> Result<u16, LinkError> {
+        let path = self.sys_path.join(interface).join("mtu");
+        let mtu = std::fs::read_to_string(&path).map_err(|error| LinkError::Sysfs {
+            path: path.clone(),
+            error,
+        })?;
+
+        mtu.trim().parse().map_err(|_| LinkError::Sysfs {
+            error: std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("'{}' is not an MTU", mtu.trim()),
+            ),
+            path,
+        })
+    }
+
+    pub fn set_mtu(&self, interface: &str, mtu: u16) -> Result<(), LinkError> {
+        let mtu = mtu.to_string();
+        self.run(&["link", "set", "dev", interface, "mtu", &mtu])
+    }
+
+    fn run(&self, args: &[&str]) -> Result<(), LinkError> {
+        utils::run_command(&self.binary_path, args, None)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inheritance() {
+        let server = Tuning {
+            mtu: Some(1420),
+            persistent_keepalive: Some(25),
+            fwmark: Some(0x51820),
+            post_up: vec!["iptables -A FORWARD -i wg0 -j ACCEPT".into()],
+            ..Tuning::default()
+        };
+        let laptops = Tuning {
+            mtu: Some(1380),
+            table: Some(Table::Off),
+            post_up: vec!["resolvectl domain wg0 ~vpn".into()],
+            ..Tuning::default()
+        };
+        let roaming = Tuning {
+            mtu: Some(1280),
+            persistent_keepalive: Some(0),
+            post_up: vec!["resolvectl domain wg0 ~vpn".into(), "logger up".into()],
+            ..Tuning::default()
+        };
+        let client = Tuning {
+            persistent_keepalive: Some(15),
+            ..Tuning::default()
+        };
+
+        let groups = Tuning::merge([&laptops, &roaming]);
+        assert_eq!(
+            client.or(&groups).or(&server.inherited()),
+            Tuning {
+                mtu: Some(1280),
+                persistent_keepalive: Some(15),
+                fwmark: None,
+                table: Some(Table::Off),
+                post_up: vec!["resolvectl domain wg0 ~vpn".into(), "logger up".into()],
+                post_down: vec![],
+            }
+        );
+
+        // A keepalive turned off gives way to one that is set, but still overrides the server's
+        let tuning = Tuning::merge([&roaming]).or(&server.inherited());
+        assert_eq!(tuning.persistent_keepalive, Some(0));
+        assert_eq!(tuning.keepalive(), None);
+        let tuning = Tuning::merge([&roaming, &server]);
+        assert_eq!(tuning.keepalive(), Some(25));
+    }
+
+    #[test]
+    fn test_validate() {
+        for mtu in [1279, 9001] {
+            let tuning = Tuning {
+                mtu: Some(mtu),
+                ..Tuning::default()
+            };
+            assert_eq!(tuning.validate(), Err(TuningError::MtuOutOfRange(mtu)));
+        }
+        for hook in ["", " ", "up\ndown"] {
+            let tuning = Tuning {
+                post_down: vec![hook.into()],
+                ..Tuning::default()
+            };
+            assert!(tuning.validate().is_err(), "{:?}", hook);
+        }
+        assert!(Tuning {
+            mtu: Some(1420),
+            post_up: vec!["true".into()],
+            ..Tuning::default()
+        }
+        .validate()
+        .is_ok());
+
+        assert_eq!("main".parse(), Ok(Table::Id(254)));
+        assert_eq!("1234".parse(), Ok(Table::Id(1234)));
+        for table in ["0", "253", "255", "-1", "on"] {
+            assert!(table.parse::<Table>().is_err(), "{}", table);
+        }
+        assert_eq!(parse_fwmark("0x51820"), Ok(0x51820));
+        assert_eq!(parse_fwmark("1234"), Ok(1234));
+        assert_eq!(parse_fwmark("off"), Ok(0));
+        assert!(parse_fwmark("0xg").is_err());
+    }
+}
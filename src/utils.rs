@@ -2,7 +2,12 @@ use ipnet::Ipv4Net;
 use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
 use std::{
     ffi::OsString,
+    fmt,
+    fs::OpenOptions,
+    io::Write,
     path::{Path, PathBuf},
+    process::{Command, Stdio},
+    str::FromStr,
 };
 
 pub fn serialize_ipv4net<S>(ipv4net: &Ipv4Net, serializer: S) -> Result<S::Ok, S::Error>
@@ -16,8 +21,30 @@ pub fn deserialize_ipv4net<'de, D>(deserializer: D) -> Result<Ipv4Net, D::Error>
 where
     D: Deserializer<'de>,
 {
-    String::deserialize(deserializer)
-        .and_then(|x| x.parse::<Ipv4Net>().map_err(|e| D::Error::custom(e)))
+    String::deserialize(deserializer).and_then(|x| x.parse::<Ipv4Net>().map_err(D::Error::custom))
+}
+
+/// Serializes networks (`IpNet` or `Ipv4Net`) as a list of strings in CIDR notation
+pub fn serialize_ipnets<S, N>(ipnets: &[N], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    N: fmt::Display,
+{
+    serializer.collect_seq(ipnets.iter().map(|ipnet| ipnet.to_string()))
+}
+
+pub fn deserialize_ipnets<'de, D, N>(deserializer: D) -> Result<Vec<N>, D::Error>
+where
+    D: Deserializer<'de>,
+    N: FromStr,
+    N::Err: fmt::Display,
+{
+    Vec::<String>::deserialize(deserializer).and_then(|ipnets| {
+        ipnets
+            .iter()
+            .map(|ipnet| ipnet.parse::<N>().map_err(D::Error::custom))
+            .collect()
+    })
 }
 
 // Takes in a table of strings (vec of rows, each row is a vec of strings)
@@ -56,6 +83,282 @@ pub fn cli_table(table: Vec<Vec<&str>>) -> Vec<String> {
     lines
 }
 
+/// Current time as seconds since the unix epoch
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Formats a number of seconds compactly, e.g. `1h 2m 3s`
+pub fn format_duration(secs: u64) -> String {
+    let units = [("d", 86400), ("h", 3600), ("m", 60), ("s", 1)];
+
+    let mut remaining = secs;
+    let mut parts: Vec<String> = Vec::new();
+    for (suffix, unit_secs) in units.iter() {
+        if remaining >= *unit_secs {
+            parts.push(format!("{}{}", remaining / unit_secs, suffix));
+            remaining %= unit_secs;
+        }
+    }
+
+    if parts.is_empty() {
+        "0s".into()
+    } else {
+        parts.join(" ")
+    }
+}
+
+/// Name of the user running the command, looking through `sudo`
+pub fn invoking_user() -> Option<String> {
+    ["SUDO_USER", "USER"]
+        .iter()
+        .find_map(|var| std::env::var(var).ok().filter(|user| !user.is_empty()))
+}
+
+/// Parses a duration such as `30d`, `12h`, `90m` or `45s` into seconds.
+///
+/// A bare number is taken as seconds.
+pub fn parse_duration(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (number, unit_secs) = match s.char_indices().last() {
+        Some((i, 'd')) => (&s[..i], 86400),
+        Some((i, 'h')) => (&s[..i], 3600),
+        Some((i, 'm')) => (&s[..i], 60),
+        Some((i, 's')) => (&s[..i], 1),
+        _ => (s, 1),
+    };
+
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(unit_secs))
+        .ok_or_else(|| {
+            format!(
+                "invalid duration '{}', expected e.g. 30d, 12h, 90m or 45s",
+                s
+            )
+        })
+}
+
+/// Parses a point in time into a unix timestamp.
+///
+/// Accepts a unix timestamp, a date (`2026-12-31`, meaning the start of that day) or a date and
+/// time (`2026-12-31T18:00:00Z` or `2026-12-31T18:00Z`), all in UTC.
+pub fn parse_time(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let invalid = || {
+        format!(
+            "invalid time '{}', expected e.g. 2026-12-31, 2026-12-31T18:00Z or a unix timestamp",
+            s
+        )
+    };
+
+    if let Ok(timestamp) = s.parse::<u64>() {
+        return Ok(timestamp);
+    }
+
+    let (date, time) = match s.split_once('T') {
+        Some((date, time)) => (date, Some(time.strip_suffix('Z').ok_or_else(invalid)?)),
+        None => (s, None),
+    };
+
+    let date: Vec<u64> = date
+        .split('-')
+        .map(|part| part.parse::<u64>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| invalid())?;
+    let time: Vec<u64> = match time {
+        Some(time) => time
+            .split(':')
+            .map(|part| part.parse::<u64>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| invalid())?,
+        None => vec![0, 0],
+    };
+
+    match (date.as_slice(), time.as_slice()) {
+        ([year, month, day], [hour, minute]) | ([year, month, day], [hour, minute, _]) => {
+            let second = time.get(2).copied().unwrap_or(0);
+            if *year < 1970
+                || !(1..=12).contains(month)
+                || !(1..=days_in_month(*year, *month)).contains(day)
+                || *hour > 23
+                || *minute > 59
+                || second > 59
+            {
+                return Err(invalid());
+            }
+
+            let days = days_since_epoch(*year, *month, *day);
+            Ok(days * 86400 + hour * 3600 + minute * 60 + second)
+        }
+        _ => Err(invalid()),
+    }
+}
+
+/// Formats a unix timestamp as an ISO 8601 date and time in UTC, e.g. `2026-12-31T18:00:00Z`
+pub fn format_time(timestamp: u64) -> String {
+    let (days, secs) = (timestamp / 86400, timestamp % 86400);
+
+    // Inverse of `days_since_epoch`, from Howard Hinnant's `civil_from_days`
+    let z = days + 719468;
+    let era = z / 146097;
+    let day_of_era = z % 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as u64;
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+fn days_in_month(year: u64, month: u64) -> u64 {
+    let leap = (year.is_multiple_of(4) && !year.is_multiple_of(100)) || year.is_multiple_of(400);
+    match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days from 1970-01-01 to a date, from Howard Hinnant's `days_from_civil`
+fn days_since_epoch(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let month_index = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146097 + day_of_era - 719468
+}
+
+/// Formats a byte count with binary units, e.g. `1.50 KiB`
+pub fn format_bytes(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.2} {}", value, units[unit])
+    }
+}
+
+/// Writes `data` to `path` by writing a temporary file alongside it and renaming it over `path`,
+/// so that readers never see a partially written file.
+pub fn write_atomic(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let mut temp_name = OsString::from(".");
+    temp_name.push(path.file_name().unwrap_or_default());
+    temp_name.push(format!(".{}.tmp", std::process::id()));
+    let temp_path = path.with_file_name(temp_name);
+
+    std::fs::write(&temp_path, data)?;
+    std::fs::rename(&temp_path, path)
+}
+
+/// Whether `name` is safe to name a client, invite, group, mesh or node by. Names end up in file
+/// names (`<name>.conf` in bundles) and nftables rulesets, so only letters, digits, `.`, `_` and
+/// `-` are allowed, and a leading dot (which would make `..` or hidden files) isn't.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+/// Why running an external binary failed, which each binding turns into an error of its own
+#[derive(Debug)]
+pub enum CommandError {
+    /// The binary could not be found
+    NotInstalled { command: String },
+    /// The command exited with a non-zero status
+    Failed {
+        command: String,
+        code: Option<i32>,
+        stderr: String,
+    },
+    /// Spawning, or communicating with, the process failed
+    IOError {
+        command: String,
+        error: std::io::Error,
+    },
+}
+
+/// Runs `binary_path` with `args`, writing `stdin` to it if given, and returns the command line
+/// that was run along with what it wrote to stdout
+pub fn run_command(
+    binary_path: &str,
+    args: &[&str],
+    stdin: Option<&[u8]>,
+) -> Result<(String, Vec<u8>), CommandError> {
+    let command = std::iter::once(binary_path)
+        .chain(args.iter().copied())
+        .collect::<Vec<&str>>()
+        .join(" ");
+    let io_error = |error| CommandError::IOError {
+        command: command.clone(),
+        error,
+    };
+
+    let mut child = Command::new(binary_path)
+        .args(args)
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|error| match error.kind() {
+            std::io::ErrorKind::NotFound => CommandError::NotInstalled {
+                command: command.clone(),
+            },
+            _ => io_error(error),
+        })?;
+
+    if let Some(input) = stdin {
+        // Taking stdin means it is closed when dropped, so the child sees EOF
+        let mut child_stdin = child.stdin.take().expect("stdin is piped");
+        child_stdin.write_all(input).map_err(io_error)?;
+    }
+
+    let output = child.wait_with_output().map_err(io_error)?;
+
+    if output.status.success() {
+        Ok((command, output.stdout))
+    } else {
+        Err(CommandError::Failed {
+            code: output.status.code(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+            command,
+        })
+    }
+}
+
 /// Generates a lock path to specific file path.
 ///
 /// Same file path will always produce same lock path.
@@ -78,12 +381,12 @@ pub enum LockError {
     IOError(std::io::Error),
 }
 
-impl ToString for LockError {
-    fn to_string(&self) -> String {
-        match &self {
-            LockError::MalformedLockExists => "lock exists, but it is malformed".into(),
-            LockError::LockExists(e) => format!("lock exists with process id: {}", e),
-            LockError::IOError(e) => e.to_string(),
+impl fmt::Display for LockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LockError::MalformedLockExists => write!(f, "lock exists, but it is malformed"),
+            LockError::LockExists(e) => write!(f, "lock exists with process id: {}", e),
+            LockError::IOError(e) => write!(f, "{}", e),
         }
     }
 }
@@ -100,8 +403,8 @@ impl From<std::io::Error> for LockError {
 /// 1. if path is not a file, fail
 /// 2. if path has incorrect permissions, fail
 /// 3. if path is a file and exists:
-///   a. and contains a valid number (process id), fail with that id
-///   b. and no valid number, fail
+///    a. and contains a valid number (process id), fail with that id
+///    b. and no valid number, fail
 ///
 /// Lock dropping rules
 /// 1. Releasing a lock cannot fail in the sense that an Err is returned, so will require
@@ -116,17 +419,26 @@ impl Lock {
     pub fn acquire(lock_path: impl Into<PathBuf>) -> Result<Self, LockError> {
         let lock_path = lock_path.into();
 
-        if lock_path.exists() {
-            let locking_process_id: u32 = std::fs::read_to_string(lock_path)?
-                .parse()
-                .or(Err(LockError::MalformedLockExists))?;
-
-            Err(LockError::LockExists(locking_process_id))
-        } else {
-            let process_id = std::process::id();
+        // Creating the file fails if it exists, so of several processes only one gets the lock
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+        {
+            Ok(mut file) => {
+                // Held before writing, so that the file is removed if writing fails
+                let lock = Self(lock_path);
+                file.write_all(std::process::id().to_string().as_bytes())?;
+                Ok(lock)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                let locking_process_id: u32 = std::fs::read_to_string(lock_path)?
+                    .parse()
+                    .or(Err(LockError::MalformedLockExists))?;
 
-            std::fs::write(&lock_path, process_id.to_string().as_bytes())?;
-            Ok(Self(lock_path))
+                Err(LockError::LockExists(locking_process_id))
+            }
+            Err(e) => Err(e.into()),
         }
     }
 }
@@ -137,3 +449,102 @@ impl Drop for Lock {
         let _ = std::fs::remove_file(&self.0);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_command() {
+        let (command, stdout) = run_command("cat", &["-"], Some(b"hello")).unwrap();
+        assert_eq!(command, "cat -");
+        assert_eq!(stdout, b"hello");
+
+        assert!(matches!(
+            run_command("sh", &["-c", "echo oops >&2; exit 3"], None),
+            Err(CommandError::Failed { code: Some(3), stderr, .. }) if stderr == "oops"
+        ));
+        assert!(matches!(
+            run_command("/nonexistent/wg", &["show"], None),
+            Err(CommandError::NotInstalled { command }) if command == "/nonexistent/wg show"
+        ));
+    }
+
+    #[test]
+    fn test_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock_path = dir.path().join(".wgman.conf.lock");
+
+        let lock = Lock::acquire(&lock_path).unwrap();
+        assert!(matches!(
+            Lock::acquire(&lock_path),
+            Err(LockError::LockExists(id)) if id == std::process::id()
+        ));
+        drop(lock);
+        assert!(!lock_path.exists());
+
+        std::fs::write(&lock_path, "garbage").unwrap();
+        assert!(matches!(
+            Lock::acquire(&lock_path),
+            Err(LockError::MalformedLockExists)
+        ));
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(0), "0s");
+        assert_eq!(format_duration(59), "59s");
+        assert_eq!(format_duration(3723), "1h 2m 3s");
+        assert_eq!(format_duration(90000), "1d 1h");
+    }
+
+    #[test]
+    fn test_is_valid_name() {
+        assert!(is_valid_name("alice"));
+        assert!(is_valid_name("alice-laptop_2.home"));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name(".hidden"));
+        assert!(!is_valid_name(".."));
+        assert!(!is_valid_name("../../etc/cron.d/x"));
+        assert!(!is_valid_name("a/b"));
+        assert!(!is_valid_name("with space"));
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("30d"), Ok(30 * 86400));
+        assert_eq!(parse_duration("12h"), Ok(12 * 3600));
+        assert_eq!(parse_duration("90m"), Ok(5400));
+        assert_eq!(parse_duration("45s"), Ok(45));
+        assert_eq!(parse_duration("3600"), Ok(3600));
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("1w").is_err());
+        assert!(parse_duration("-1d").is_err());
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(parse_time("1700000000"), Ok(1700000000));
+        assert_eq!(parse_time("1970-01-01"), Ok(0));
+        assert_eq!(parse_time("2026-12-31"), Ok(1798675200));
+        assert_eq!(parse_time("2024-02-29T12:30Z"), Ok(1709209800));
+        assert_eq!(parse_time("2024-02-29T12:30:15Z"), Ok(1709209815));
+        assert!(parse_time("2023-02-29").is_err());
+        assert!(parse_time("2026-12-31T18:00").is_err());
+        assert!(parse_time("31/12/2026").is_err());
+    }
+
+    #[test]
+    fn test_format_time() {
+        assert_eq!(format_time(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_time(1709209815), "2024-02-29T12:30:15Z");
+        assert_eq!(format_time(1798675200), "2026-12-31T00:00:00Z");
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.50 KiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024 * 1024), "3.00 GiB");
+    }
+}
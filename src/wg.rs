@@ -1,12 +1,160 @@
 /// Minimal bindings to the `wg` binary.
-use std::{
-    io::{BufRead, Write},
-    path::Path,
-    process::{Command, Stdio},
-};
+use std::{fmt, net::SocketAddr, path::Path};
 
+use ipnet::IpNet;
 use serde::{Deserialize, Serialize};
 
+use crate::tuning;
+use crate::utils::{self, CommandError};
+
+/// An error from invoking the `wg` binary.
+///
+/// Every variant records the command line that was run, so that the error can be reported
+/// without any further context.
+#[derive(Debug)]
+pub enum WgError {
+    /// The binary could not be found
+    NotInstalled { command: String },
+    /// The interface that the command operated on does not exist
+    InterfaceNotFound {
+        command: String,
+        code: Option<i32>,
+        stderr: String,
+    },
+    /// The command was not allowed to run, or to access the interface
+    PermissionDenied {
+        command: String,
+        code: Option<i32>,
+        stderr: String,
+    },
+    /// The command exited with a non-zero status for any other reason
+    CommandFailed {
+        command: String,
+        code: Option<i32>,
+        stderr: String,
+    },
+    /// Spawning, or communicating with, the process failed
+    IOError {
+        command: String,
+        error: std::io::Error,
+    },
+    /// The command succeeded, but its output could not be understood
+    InvalidOutput { command: String, reason: String },
+}
+
+impl WgError {
+    /// Classifies a non-zero exit from `wg` by what it wrote to stderr.
+    fn from_failure(command: String, code: Option<i32>, stderr: String) -> Self {
+        if stderr.contains("No such device") {
+            WgError::InterfaceNotFound {
+                command,
+                code,
+                stderr,
+            }
+        } else if stderr.contains("Operation not permitted") || stderr.contains("Permission denied")
+        {
+            WgError::PermissionDenied {
+                command,
+                code,
+                stderr,
+            }
+        } else {
+            WgError::CommandFailed {
+                command,
+                code,
+                stderr,
+            }
+        }
+    }
+}
+
+impl From<CommandError> for WgError {
+    fn from(error: CommandError) -> Self {
+        match error {
+            CommandError::NotInstalled { command } => WgError::NotInstalled { command },
+            CommandError::Failed {
+                command,
+                code,
+                stderr,
+            } => WgError::from_failure(command, code, stderr),
+            CommandError::IOError { command, error }
+                if error.kind() == std::io::ErrorKind::PermissionDenied =>
+            {
+                WgError::PermissionDenied {
+                    command,
+                    code: None,
+                    stderr: error.to_string(),
+                }
+            }
+            CommandError::IOError { command, error } => WgError::IOError { command, error },
+        }
+    }
+}
+
+impl fmt::Display for WgError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = |code: &Option<i32>| match code {
+            Some(code) => format!("exit code {}", code),
+            None => "killed by signal".to_owned(),
+        };
+
+        match self {
+            WgError::NotInstalled { command } => {
+                write!(
+                    f,
+                    "`{}` failed: wg binary not found, is wireguard-tools installed?",
+                    command
+                )
+            }
+            WgError::InterfaceNotFound {
+                command,
+                code,
+                stderr,
+            } => write!(
+                f,
+                "`{}` failed ({}): interface does not exist: {}",
+                command,
+                status(code),
+                stderr
+            ),
+            WgError::PermissionDenied {
+                command,
+                code,
+                stderr,
+            } => write!(
+                f,
+                "`{}` failed ({}): permission denied: {}",
+                command,
+                status(code),
+                stderr
+            ),
+            WgError::CommandFailed {
+                command,
+                code,
+                stderr,
+            } => write!(f, "`{}` failed ({}): {}", command, status(code), stderr),
+            WgError::IOError { command, error } => write!(f, "`{}` failed: {}", command, error),
+            WgError::InvalidOutput { command, reason } => {
+                write!(f, "`{}` produced unexpected output: {}", command, reason)
+            }
+        }
+    }
+}
+
+/// Live state of a peer on an interface, as reported by `wg show <interface> dump`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerInfo {
+    pub public_key: String,
+    pub endpoint: Option<SocketAddr>,
+    pub allowed_ips: Vec<IpNet>,
+    /// Unix timestamp of the most recent handshake, `None` if there has never been one
+    pub latest_handshake: Option<u64>,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    /// Persistent keepalive interval in seconds, `None` if off
+    pub persistent_keepalive: Option<u16>,
+}
+
 /// Struct that represents a handle to the wg binary.
 #[derive(Deserialize, Serialize)]
 pub struct Wg {
@@ -18,89 +166,316 @@ impl Wg {
         Wg { binary_path }
     }
 
-    pub fn genkey(&self) -> String {
-        let output_bytes = Command::new(&self.binary_path)
-            .arg("genkey")
-            .output()
-            .expect("`wg genkey` failed")
-            .stdout;
+    pub fn genkey(&self) -> Result<String, WgError> {
+        let (command, output_bytes) = self.run(&["genkey"], None)?;
+        strip_and_convert(&command, &output_bytes)
+    }
+
+    pub fn pubkey(&self, privkey: &str) -> Result<String, WgError> {
+        let input = format!("{}\n", privkey);
+        let (command, output_bytes) = self.run(&["pubkey"], Some(input.as_bytes()))?;
+        strip_and_convert(&command, &output_bytes)
+    }
+
+    pub fn genpsk(&self) -> Result<String, WgError> {
+        let (command, output_bytes) = self.run(&["genpsk"], None)?;
+        strip_and_convert(&command, &output_bytes)
+    }
+
+    /// Returns the private key of the interface, or `None` if it has not been set
+    pub fn show_private_key(&self, interface: &str) -> Result<Option<String>, WgError> {
+        let (command, output_bytes) = self.run(&["show", interface, "private-key"], None)?;
+        let private_key = strip_and_convert(&command, &output_bytes)?;
+
+        if private_key == "(none)" {
+            Ok(None)
+        } else {
+            Ok(Some(private_key))
+        }
+    }
+
+    pub fn set_private_key(&self, interface: &str, path: &Path) -> Result<(), WgError> {
+        let path = path.to_string_lossy();
+        self.run(&["set", interface, "private-key", &path], None)?;
+        Ok(())
+    }
+
+    /// Returns the listening port of the interface, `0` meaning that none is set
+    pub fn show_listen_port(&self, interface: &str) -> Result<u16, WgError> {
+        let (command, output_bytes) = self.run(&["show", interface, "listen-port"], None)?;
+        let listen_port = strip_and_convert(&command, &output_bytes)?;
+
+        listen_port.parse().map_err(|_| WgError::InvalidOutput {
+            command,
+            reason: format!("'{}' is not a port", listen_port),
+        })
+    }
 
-        let privkey = strip_and_convert(&output_bytes);
-        privkey
+    pub fn set_listen_port(&self, interface: &str, port: u16) -> Result<(), WgError> {
+        self.run(&["set", interface, "listen-port", &port.to_string()], None)?;
+        Ok(())
     }
 
-    pub fn pubkey(&self, privkey: &String) -> String {
-        let mut child = Command::new(&self.binary_path)
-            .arg("pubkey")
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .spawn()
-            .expect("`wg pubkey` failed");
+    /// Returns the firewall mark of the interface, `0` meaning that none is set
+    pub fn show_fwmark(&self, interface: &str) -> Result<u32, WgError> {
+        let (command, output_bytes) = self.run(&["show", interface, "fwmark"], None)?;
+        let fwmark = strip_and_convert(&command, &output_bytes)?;
 
-        let child_stdin = child.stdin.as_mut().unwrap();
-        child_stdin.write_all(privkey.as_bytes()).unwrap();
-        child_stdin.write_all(b"\n").unwrap();
-        drop(child_stdin);
+        tuning::parse_fwmark(&fwmark).map_err(|reason| WgError::InvalidOutput { command, reason })
+    }
 
-        let output_bytes = child.wait_with_output().unwrap().stdout;
-        let pubkey = strip_and_convert(&output_bytes);
-        pubkey
+    /// Sets the firewall mark of the interface, `0` turning it off
+    pub fn set_fwmark(&self, interface: &str, fwmark: u32) -> Result<(), WgError> {
+        self.run(&["set", interface, "fwmark", &fwmark.to_string()], None)?;
+        Ok(())
     }
 
-    pub fn show_private_key(&self, interface: &str) -> String {
-        todo!();
+    /// Adds a peer to the interface, or replaces the allowed ips of an existing one
+    pub fn set_peer_allowed_ips(
+        &self,
+        interface: &str,
+        public_key: &str,
+        allowed_ips: &[IpNet],
+    ) -> Result<(), WgError> {
+        let allowed_ips = join_allowed_ips(allowed_ips);
+        self.run(
+            &[
+                "set",
+                interface,
+                "peer",
+                public_key,
+                "allowed-ips",
+                &allowed_ips,
+            ],
+            None,
+        )?;
+        Ok(())
     }
 
-    pub fn set_private_key(&self, interface: &str, path: &Path) {
-        todo!();
+    /// Sets the preshared key of a peer from the file at `path`, `/dev/null` removing it
+    pub fn set_peer_preshared_key(
+        &self,
+        interface: &str,
+        public_key: &str,
+        path: &Path,
+    ) -> Result<(), WgError> {
+        let path = path.to_string_lossy();
+        self.run(
+            &["set", interface, "peer", public_key, "preshared-key", &path],
+            None,
+        )?;
+        Ok(())
     }
 
-    pub fn show_listen_port(&self, interface: &str) -> u16 {
-        todo!();
+    /// Replaces the peer with `old_public_key` by one with `public_key`, in a single `wg set` so
+    /// that the interface never has both or neither
+    pub fn replace_peer(
+        &self,
+        interface: &str,
+        old_public_key: &str,
+        public_key: &str,
+        allowed_ips: &[IpNet],
+        preshared_key: Option<&Path>,
+    ) -> Result<(), WgError> {
+        let allowed_ips = join_allowed_ips(allowed_ips);
+        let mut args = vec![
+            "set",
+            interface,
+            "peer",
+            old_public_key,
+            "remove",
+            "peer",
+            public_key,
+            "allowed-ips",
+            &allowed_ips,
+        ];
+
+        let preshared_key = preshared_key.map(|path| path.to_string_lossy());
+        if let Some(path) = &preshared_key {
+            args.push("preshared-key");
+            args.push(path);
+        }
+
+        self.run(&args, None)?;
+        Ok(())
     }
 
-    pub fn set_listen_port(&self, interface: &str, port: u16) {
-        todo!();
+    pub fn remove_peer(&self, interface: &str, public_key: &str) -> Result<(), WgError> {
+        self.run(&["set", interface, "peer", public_key, "remove"], None)?;
+        Ok(())
     }
 
-    pub fn show_peers(&self, interface: &str) -> Vec<String> {
-        let output_bytes = Command::new(&self.binary_path)
-            .arg("show")
-            .arg(interface)
-            .arg("peers")
-            .output()
-            .expect(&format!("`wg show {} peers` failed", interface))
-            .stdout;
+    /// Returns the allowed ips for each peer on the interface, keyed by public key
+    pub fn show_allowed_ips(&self, interface: &str) -> Result<Vec<(String, Vec<IpNet>)>, WgError> {
+        let (command, output_bytes) = self.run(&["show", interface, "allowed-ips"], None)?;
 
-        let peers: Vec<String> = output_bytes
-            .split(|x| *x == b'\n')
-            .map(|b| std::str::from_utf8(b).unwrap().to_owned())
-            .filter(|s| s.len() != 0)
-            .collect();
+        parse_table_strings(&command, &output_bytes)?
+            .into_iter()
+            .map(|mut row| {
+                let public_key = row.remove(0);
+                let allowed_ips = row
+                    .iter()
+                    .filter(|cell| cell.as_str() != "(none)")
+                    .map(|cell| {
+                        cell.parse().map_err(|_| WgError::InvalidOutput {
+                            command: command.clone(),
+                            reason: format!("'{}' is not an ip range", cell),
+                        })
+                    })
+                    .collect::<Result<Vec<IpNet>, WgError>>()?;
+                Ok((public_key, allowed_ips))
+            })
+            .collect()
+    }
 
-        peers
+    /// Returns the preshared key of each peer on the interface, keyed by public key
+    pub fn show_preshared_keys(
+        &self,
+        interface: &str,
+    ) -> Result<Vec<(String, Option<String>)>, WgError> {
+        let (command, output_bytes) = self.run(&["show", interface, "preshared-keys"], None)?;
+        parse_preshared_keys(&command, &output_bytes)
     }
 
-    pub fn show_allowed_ips(&self, interface: &str) -> Vec<Vec<String>> {
-        let output_bytes = Command::new(&self.binary_path)
-            .arg("show")
-            .arg(interface)
-            .arg("allowed-ips")
-            .output()
-            .expect(&format!("`wg show {} allowed-ips` failed", interface))
-            .stdout;
+    /// Returns the live state of every peer on the interface
+    pub fn show_dump(&self, interface: &str) -> Result<Vec<PeerInfo>, WgError> {
+        let (command, output_bytes) = self.run(&["show", interface, "dump"], None)?;
+        parse_dump(&command, &output_bytes)
+    }
 
-        parse_table_strings(&output_bytes)
+    /// Runs the binary with `args`, optionally feeding `stdin` to it.
+    ///
+    /// Returns the command line that was run (for error reporting) along with stdout.
+    fn run(&self, args: &[&str], stdin: Option<&[u8]>) -> Result<(String, Vec<u8>), WgError> {
+        Ok(utils::run_command(&self.binary_path, args, stdin)?)
     }
 }
 
-fn strip_and_convert(bytes: &[u8]) -> String {
-    let string_str = std::str::from_utf8(bytes).unwrap();
+/// Whether `key` is a key in the form `wg` reads and writes, i.e. 32 bytes of base64.
+///
+/// Any 32 bytes are a valid Curve25519 public key, so the encoding is all there is to check.
+pub fn is_valid_key(key: &str) -> bool {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let bytes = key.as_bytes();
+    if bytes.len() != 44 || bytes[43] != b'=' {
+        return false;
+    }
+
+    let digits: Option<Vec<usize>> = bytes[..43]
+        .iter()
+        .map(|byte| ALPHABET.iter().position(|c| c == byte))
+        .collect();
+    // 43 digits carry 258 bits, the last two of which must be zero for exactly 32 bytes
+    matches!(digits, Some(digits) if digits[42] & 0b11 == 0)
+}
+
+/// Formats allowed ips in the comma-separated form that `wg set` expects
+fn join_allowed_ips(allowed_ips: &[IpNet]) -> String {
+    allowed_ips
+        .iter()
+        .map(|ip| ip.to_string())
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+fn strip_and_convert(command: &str, bytes: &[u8]) -> Result<String, WgError> {
+    let string_str = std::str::from_utf8(bytes).map_err(|_| WgError::InvalidOutput {
+        command: command.to_owned(),
+        reason: "output is not valid UTF-8".into(),
+    })?;
     let mut string = string_str.to_owned();
-    if string.ends_with("\n") {
+    if string.ends_with('\n') {
         string.pop();
     }
-    string
+    Ok(string)
+}
+
+/// Parses the output of `wg show <interface> preshared-keys`, `(none)` meaning no key
+fn parse_preshared_keys(
+    command: &str,
+    bytes: &[u8],
+) -> Result<Vec<(String, Option<String>)>, WgError> {
+    parse_table_strings(command, bytes)?
+        .into_iter()
+        .map(|row| match row.as_slice() {
+            [public_key, preshared_key] => Ok((
+                public_key.clone(),
+                Some(preshared_key.clone()).filter(|key| key != "(none)"),
+            )),
+            _ => Err(WgError::InvalidOutput {
+                command: command.to_owned(),
+                reason: format!("expected 2 columns, got {}", row.len()),
+            }),
+        })
+        .collect()
+}
+
+/// Parses the output of `wg show <interface> dump`.
+///
+/// The first row describes the interface itself and is skipped, each following row is a peer:
+/// public key, preshared key, endpoint, allowed ips, latest handshake, rx, tx, keepalive.
+fn parse_dump(command: &str, bytes: &[u8]) -> Result<Vec<PeerInfo>, WgError> {
+    let invalid = |reason: String| WgError::InvalidOutput {
+        command: command.to_owned(),
+        reason,
+    };
+    let parse_number = |cell: &str| {
+        cell.parse::<u64>()
+            .map_err(|_| invalid(format!("'{}' is not a number", cell)))
+    };
+
+    parse_table_strings(command, bytes)?
+        .into_iter()
+        .skip(1)
+        .map(|row| {
+            if row.len() != 8 {
+                return Err(invalid(format!(
+                    "expected 8 columns for a peer, found {}",
+                    row.len()
+                )));
+            }
+
+            let endpoint = match row[2].as_str() {
+                "(none)" => None,
+                endpoint => Some(
+                    endpoint
+                        .parse()
+                        .map_err(|_| invalid(format!("'{}' is not an endpoint", endpoint)))?,
+                ),
+            };
+            let allowed_ips = row[3]
+                .split(',')
+                .filter(|ip| *ip != "(none)")
+                .map(|ip| {
+                    ip.parse()
+                        .map_err(|_| invalid(format!("'{}' is not an ip range", ip)))
+                })
+                .collect::<Result<Vec<IpNet>, WgError>>()?;
+            let latest_handshake = match parse_number(&row[4])? {
+                0 => None,
+                timestamp => Some(timestamp),
+            };
+            let persistent_keepalive = match row[7].as_str() {
+                "off" => None,
+                keepalive => Some(
+                    keepalive
+                        .parse()
+                        .map_err(|_| invalid(format!("'{}' is not a keepalive", keepalive)))?,
+                ),
+            };
+
+            Ok(PeerInfo {
+                public_key: row[0].clone(),
+                endpoint,
+                allowed_ips,
+                latest_handshake,
+                rx_bytes: parse_number(&row[5])?,
+                tx_bytes: parse_number(&row[6])?,
+                persistent_keepalive,
+            })
+        })
+        .collect()
 }
 
 /// Parses text table as a list of rows, which is a list of cells, where each cell is a byte string
@@ -116,12 +491,19 @@ fn parse_table(bytes: &[u8]) -> Vec<Vec<&[u8]>> {
         .collect()
 }
 
-fn parse_table_strings(bytes: &[u8]) -> Vec<Vec<String>> {
+fn parse_table_strings(command: &str, bytes: &[u8]) -> Result<Vec<Vec<String>>, WgError> {
     parse_table(bytes)
         .iter()
         .map(|row| {
             row.iter()
-                .map(|cell| std::str::from_utf8(cell).unwrap().to_owned())
+                .map(|cell| {
+                    std::str::from_utf8(cell)
+                        .map(|cell| cell.to_owned())
+                        .map_err(|_| WgError::InvalidOutput {
+                            command: command.to_owned(),
+                            reason: "output is not valid UTF-8".into(),
+                        })
+                })
                 .collect()
         })
         .collect()
@@ -131,6 +513,21 @@ fn parse_table_strings(bytes: &[u8]) -> Vec<Vec<String>> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_is_valid_key() {
+        assert!(is_valid_key("YNqHbfBQKaGvzefSSTlTb4rFNH5DF9UQ0UV0YYuG1XU="));
+        assert!(!is_valid_key(
+            "YNqHbfBQKaGvzefSSTlTb4rFNH5DF9UQ0UV0YYuG1XV="
+        ));
+        assert!(!is_valid_key(
+            "YNqHbfBQKaGvzefSSTlTb4rFNH5DF9UQ0UV0YYuG1X=="
+        ));
+        assert!(!is_valid_key("YNqHbfBQKaGvzefSSTlTb4rFNH5DF9UQ0UV0YYuG1XU"));
+        assert!(!is_valid_key(
+            "YNqHbfBQKaGvzefSSTlTb4rFNH5DF9UQ0UV0YYu-1XU="
+        ));
+    }
+
     #[test]
     fn test_parse_table() {
         let bytes = b"\ncell11    cell12 cell13    \n   \n   cell21   cell22   cell23  \n  cell31 cell32 cell33\n\n";
@@ -151,7 +548,7 @@ mod tests {
     fn test_parse_table_strings() {
         let bytes = b"\ncell11    cell12 cell13    \n   \n   cell21   cell22   cell23  \n  cell31 cell32 cell33\n\n";
 
-        let parsed = parse_table_strings(bytes);
+        let parsed = parse_table_strings("wg", bytes).unwrap();
 
         assert_eq!(
             parsed,
@@ -162,4 +559,97 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn test_parse_table_strings_invalid_utf8() {
+        let bytes = b"cell11 \xff\n";
+
+        let parsed = parse_table_strings("wg", bytes);
+
+        assert!(matches!(parsed, Err(WgError::InvalidOutput { .. })));
+    }
+
+    #[test]
+    fn test_parse_dump() {
+        let bytes = b"privkey\tpubkey\t51900\toff\n\
+            peerA=\t(none)\t1.2.3.4:5678\t10.0.0.2/32,192.168.1.0/24\t1600000000\t100\t200\t25\n\
+            peerB=\t(none)\t(none)\t10.0.0.3/32\t0\t0\t0\toff\n";
+
+        let peers = parse_dump("wg show wg0 dump", bytes).unwrap();
+
+        assert_eq!(
+            peers,
+            vec![
+                PeerInfo {
+                    public_key: "peerA=".into(),
+                    endpoint: Some("1.2.3.4:5678".parse().unwrap()),
+                    allowed_ips: vec![
+                        "10.0.0.2/32".parse().unwrap(),
+                        "192.168.1.0/24".parse().unwrap()
+                    ],
+                    latest_handshake: Some(1600000000),
+                    rx_bytes: 100,
+                    tx_bytes: 200,
+                    persistent_keepalive: Some(25),
+                },
+                PeerInfo {
+                    public_key: "peerB=".into(),
+                    endpoint: None,
+                    allowed_ips: vec!["10.0.0.3/32".parse().unwrap()],
+                    latest_handshake: None,
+                    rx_bytes: 0,
+                    tx_bytes: 0,
+                    persistent_keepalive: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_preshared_keys() {
+        let bytes = b"peerA=\tPSK=\npeerB=\t(none)\n";
+
+        let keys = parse_preshared_keys("wg show wg0 preshared-keys", bytes).unwrap();
+
+        assert_eq!(
+            keys,
+            vec![
+                ("peerA=".into(), Some("PSK=".into())),
+                ("peerB=".into(), None)
+            ]
+        );
+        assert!(matches!(
+            parse_preshared_keys("wg show wg0 preshared-keys", b"peerA=\n"),
+            Err(WgError::InvalidOutput { .. })
+        ));
+    }
+
+    #[test]
+    fn test_failure_classification() {
+        let missing = WgError::from_failure(
+            "wg show wg9 peers".into(),
+            Some(1),
+            "Unable to access interface: No such device".into(),
+        );
+        let denied = WgError::from_failure(
+            "wg show wg0 peers".into(),
+            Some(1),
+            "Unable to access interface: Operation not permitted".into(),
+        );
+        let other = WgError::from_failure("wg set wg0".into(), Some(1), "Invalid argument".into());
+
+        assert!(matches!(missing, WgError::InterfaceNotFound { .. }));
+        assert!(matches!(denied, WgError::PermissionDenied { .. }));
+        assert!(matches!(
+            other,
+            WgError::CommandFailed { code: Some(1), .. }
+        ));
+    }
+
+    #[test]
+    fn test_missing_binary() {
+        let wg = Wg::new("/nonexistent/wg".into());
+
+        assert!(matches!(wg.genkey(), Err(WgError::NotInstalled { .. })));
+    }
 }